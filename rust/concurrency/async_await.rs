@@ -1,6 +1,15 @@
 // 异步编程 async/await
 
-use tokio::time::{sleep, Duration};
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration, Instant};
 use tokio::task;
 
 /// # async/await 基础
@@ -156,6 +165,15 @@ pub async fn timeout_demo() {
         Ok(value) => println!("任务完成: {}", value),
         Err(_) => println!("任务超时！"),
     }
+    
+    // 截止时间沿令牌向下传递：子步骤要 2 秒，但整个请求只剩 500 毫秒
+    let request = CancellationToken::with_timeout(Duration::from_millis(500));
+    let step = request.child_with_timeout(Duration::from_secs(2));
+    
+    match step.run_until_cancelled(long_task()).await {
+        Ok(value) => println!("子步骤完成: {}", value),
+        Err(reason) => println!("子步骤被取消: {:?}", reason),
+    }
 }
 
 /// # 异步互斥锁
@@ -234,19 +252,39 @@ pub async fn http_requests_demo() {
 pub async fn task_queue_demo() {
     println!("\n=== 异步任务队列 ===");
     
+    run_task_queue(5, |progress| {
+        println!("  处理: {} ({}/{})", progress.name, progress.completed, progress.total);
+    })
+    .await;
+}
+
+#[derive(Debug)]
+struct Task {
+    id: i32,
+    name: String,
+}
+
+/// 任务队列的进度，每处理完一个任务产生一次
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskProgress {
+    pub id: i32,
+    pub name: String,
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// 生产者投递 total 个任务，消费者逐个处理并回调 on_progress
+pub async fn run_task_queue<F>(total: usize, mut on_progress: F)
+where
+    F: FnMut(TaskProgress) + Send + 'static,
+{
     use tokio::sync::mpsc;
     
-    #[derive(Debug)]
-    struct Task {
-        id: i32,
-        name: String,
-    }
-    
     let (tx, mut rx) = mpsc::channel::<Task>(10);
     
     // 生产者
     let producer = task::spawn(async move {
-        for i in 0..5 {
+        for i in 0..total as i32 {
             let task = Task {
                 id: i,
                 name: format!("Task {}", i),
@@ -258,9 +296,16 @@ pub async fn task_queue_demo() {
     
     // 消费者
     let consumer = task::spawn(async move {
+        let mut completed = 0;
         while let Some(task) = rx.recv().await {
-            println!("  处理: {:?}", task);
             sleep(Duration::from_millis(100)).await;
+            completed += 1;
+            on_progress(TaskProgress {
+                id: task.id,
+                name: task.name,
+                completed,
+                total,
+            });
         }
     });
     
@@ -268,6 +313,1156 @@ pub async fn task_queue_demo() {
     consumer.await.unwrap();
 }
 
+/// # 取消与结构化并发
+///
+/// 对应 go/08_context_cancel.go：CancellationToken 相当于 context.Context，
+/// TaskScope 保证一组子任务不会比作用域活得更久。
+pub async fn cancellation_demo() {
+    println!("\n=== 取消与结构化并发 ===");
+    
+    // 父令牌取消时，所有子令牌一起取消
+    let parent = CancellationToken::new();
+    let child1 = parent.child();
+    let child2 = parent.child();
+    
+    let worker = |id: i32, token: CancellationToken| async move {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    println!("  Worker {}: 收到取消信号 ({:?})", id, token.reason().unwrap());
+                    return;
+                }
+                _ = sleep(Duration::from_millis(50)) => println!("  Worker {}: 工作中...", id),
+            }
+        }
+    };
+    let w1 = task::spawn(worker(1, child1));
+    let w2 = task::spawn(worker(2, child2));
+    
+    sleep(Duration::from_millis(120)).await;
+    parent.cancel();
+    let _ = tokio::join!(w1, w2);
+    
+    // 一个子任务失败，作用域取消其余任务并返回这个错误
+    let mut scope = TaskScope::new(&CancellationToken::new());
+    for id in 0..3u64 {
+        scope.spawn(move |token| async move {
+            if id == 1 {
+                sleep(Duration::from_millis(50)).await;
+                return Err(format!("任务 {} 失败", id));
+            }
+            token
+                .run_until_cancelled(sleep(Duration::from_secs(10)))
+                .await
+                .map_err(|reason| format!("任务 {} 被取消: {:?}", id, reason))?;
+            Ok(id)
+        });
+    }
+    
+    match scope.join().await {
+        Ok(results) => println!("  全部完成: {:?}", results),
+        Err(e) => println!("  作用域失败: {}", e),
+    }
+}
+
+/// 令牌被取消的原因，对应 Go 的 context.Canceled / context.DeadlineExceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    Cancelled,
+    DeadlineExceeded,
+}
+
+struct TokenState {
+    /// 显式调用 cancel（自己或祖先）的原因；截止时间到了不写这里
+    reason: Option<CancelReason>,
+    children: Vec<Weak<TokenInner>>,
+}
+
+struct TokenInner {
+    state: Mutex<TokenState>,
+    notify: Notify,
+    /// 自己和所有祖先中最早的截止时间
+    deadline: Option<Instant>,
+}
+
+impl TokenInner {
+    fn cancel(&self, reason: CancelReason) {
+        let children = {
+            let mut state = self.state.lock().unwrap();
+            if state.reason.is_some() {
+                return;
+            }
+            state.reason = Some(reason);
+            std::mem::take(&mut state.children)
+        };
+        
+        self.notify.notify_waiters();
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel(reason);
+        }
+    }
+}
+
+/// 可以分层的取消令牌
+///
+/// 克隆得到的是同一个令牌；`child` 创建子令牌，父令牌取消时子令牌一起取消，
+/// 子令牌取消不影响父令牌。截止时间向下传递：子令牌的截止时间不会晚于父令牌。
+///
+/// ```ignore
+/// let token = CancellationToken::with_timeout(Duration::from_secs(1));
+/// match token.run_until_cancelled(fetch()).await {
+///     Ok(data) => ...,
+///     Err(CancelReason::DeadlineExceeded) => ...,
+///     Err(CancelReason::Cancelled) => ...,
+/// }
+/// ```
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        CancellationToken::new()
+    }
+}
+
+impl CancellationToken {
+    /// 没有截止时间的根令牌
+    pub fn new() -> Self {
+        CancellationToken::root(None)
+    }
+    
+    /// 在 timeout 之后自动取消的根令牌
+    pub fn with_timeout(timeout: Duration) -> Self {
+        CancellationToken::root(Some(Instant::now() + timeout))
+    }
+    
+    fn root(deadline: Option<Instant>) -> Self {
+        CancellationToken {
+            inner: Arc::new(TokenInner {
+                state: Mutex::new(TokenState {
+                    reason: None,
+                    children: Vec::new(),
+                }),
+                notify: Notify::new(),
+                deadline,
+            }),
+        }
+    }
+    
+    pub fn child(&self) -> Self {
+        self.child_with_deadline(None)
+    }
+    
+    /// 子令牌的截止时间取 deadline 和父令牌截止时间中较早的一个
+    pub fn child_with_deadline(&self, deadline: impl Into<Option<Instant>>) -> Self {
+        let deadline = match (self.inner.deadline, deadline.into()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let child = CancellationToken::root(deadline);
+        
+        let mut state = self.inner.state.lock().unwrap();
+        match state.reason {
+            // 父令牌已经取消，子令牌生来就是取消的
+            Some(reason) => child.inner.cancel(reason),
+            None => {
+                state.children.retain(|c| c.strong_count() > 0);
+                state.children.push(Arc::downgrade(&child.inner));
+            }
+        }
+        child
+    }
+    
+    pub fn child_with_timeout(&self, timeout: Duration) -> Self {
+        self.child_with_deadline(Instant::now() + timeout)
+    }
+    
+    /// 取消这个令牌和它的所有子令牌；重复调用没有效果
+    pub fn cancel(&self) {
+        self.inner.cancel(CancelReason::Cancelled);
+    }
+    
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+    
+    /// 没有取消时返回 None
+    pub fn reason(&self) -> Option<CancelReason> {
+        let reason = self.inner.state.lock().unwrap().reason;
+        reason.or_else(|| {
+            self.inner
+                .deadline
+                .filter(|deadline| Instant::now() >= *deadline)
+                .map(|_| CancelReason::DeadlineExceeded)
+        })
+    }
+    
+    pub fn is_cancelled(&self) -> bool {
+        self.reason().is_some()
+    }
+    
+    /// 等到令牌被取消或截止时间到达
+    pub async fn cancelled(&self) -> CancelReason {
+        loop {
+            // 先登记再检查，检查之后的 cancel 一定能唤醒这里
+            let notified = self.inner.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            
+            if let Some(reason) = self.reason() {
+                return reason;
+            }
+            
+            match self.inner.deadline {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = &mut notified => {}
+                        _ = tokio::time::sleep_until(deadline) => {}
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+    
+    /// 运行 future，令牌先取消时丢弃它并返回取消原因
+    pub async fn run_until_cancelled<F: Future>(
+        &self,
+        future: F,
+    ) -> Result<F::Output, CancelReason> {
+        tokio::select! {
+            biased;
+            reason = self.cancelled() => Err(reason),
+            output = future => Ok(output),
+        }
+    }
+}
+
+/// TaskScope::join 的错误
+#[derive(Debug, PartialEq)]
+pub enum ScopeError<E> {
+    /// 第一个失败的子任务返回的错误
+    Failed(E),
+    /// 第一个 panic 的子任务的 panic 信息
+    Panicked(String),
+    /// 子任务没有运行完就被中止（运行时正在关闭，或任务被 abort），没有结果
+    Aborted,
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for ScopeError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScopeError::Failed(e) => write!(f, "子任务失败: {}", e),
+            ScopeError::Panicked(message) => write!(f, "子任务 panic: {}", message),
+            ScopeError::Aborted => write!(f, "子任务被中止"),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for ScopeError<E> {}
+
+/// 结构化并发：一组共享同一个取消令牌的子任务
+///
+/// 任意子任务返回 Err 或 panic 时立即取消令牌，其余子任务应当在
+/// `token.cancelled()` 处尽快退出；`join` 等所有子任务结束后返回
+/// 全部结果或第一个错误。
+///
+/// # 优先用 `TaskScope::run`
+///
+/// ```ignore
+/// let (summary, results) = TaskScope::run(&parent, |scope| {
+///     Box::pin(async move {
+///         scope.spawn(|token| async move { fetch(token).await });
+///         check_config()?; // 提前返回也会先取消并等待已启动的子任务
+///         Ok("done")
+///     })
+/// })
+/// .await?;
+/// ```
+///
+/// `run` 在主体返回 Err 或 panic 时都会取消令牌并等所有子任务结束，之后才返回
+/// 错误或继续传播 panic，作用域结束时子任务一定已经结束。
+///
+/// # Drop 不会等待子任务
+///
+/// 手动 `new` 出来的作用域在 `join` 之前被丢弃时只取消令牌并 abort 所有子任务，
+/// 然后立即返回：Drop 不能 `.await`，在异步上下文里阻塞等待又会占住运行时线程，
+/// 所以这里**不 join**。子任务在下一个 `.await` 处停止，在那之前正在执行的同步
+/// 代码会跑完，子任务持有的资源也是在 Drop 返回之后才释放。`run` 返回的 future
+/// 在完成前被丢弃时也是这样。
+pub struct TaskScope<T, E> {
+    token: CancellationToken,
+    tasks: task::JoinSet<(usize, Result<T, ScopeError<E>>)>,
+    spawned: usize,
+}
+
+impl<T, E> TaskScope<T, E>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    /// 在新作用域里执行 body，返回前一定等所有子任务结束
+    ///
+    /// body 返回 Err 或 panic 时先取消令牌，等子任务结束后再返回
+    /// `ScopeError::Failed` 或继续 panic；body 成功时返回它的结果和子任务的结果
+    /// （按 spawn 的顺序），有子任务失败时返回第一个错误。
+    pub async fn run<R, F>(
+        parent: &CancellationToken,
+        body: F,
+    ) -> Result<(R, Vec<T>), ScopeError<E>>
+    where
+        F: for<'a> FnOnce(&'a mut TaskScope<T, E>) -> BoxFuture<'a, Result<R, E>>,
+    {
+        use futures_util::FutureExt;
+        use std::panic::AssertUnwindSafe;
+        
+        let mut scope = TaskScope::new(parent);
+        let outcome = AssertUnwindSafe(body(&mut scope)).catch_unwind().await;
+        if !matches!(outcome, Ok(Ok(_))) {
+            scope.token.cancel();
+        }
+        let joined = scope.join().await;
+        
+        match outcome {
+            Ok(Ok(value)) => joined.map(|results| (value, results)),
+            Ok(Err(e)) => Err(ScopeError::Failed(e)),
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+    
+    /// 作用域的令牌是 parent 的子令牌，继承 parent 的取消和截止时间
+    pub fn new(parent: &CancellationToken) -> Self {
+        TaskScope {
+            token: parent.child(),
+            tasks: task::JoinSet::new(),
+            spawned: 0,
+        }
+    }
+    
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+    
+    /// 启动一个子任务，f 收到作用域的令牌
+    pub fn spawn<F, Fut>(&mut self, f: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        use futures_util::FutureExt;
+        use std::panic::AssertUnwindSafe;
+        
+        let index = self.spawned;
+        self.spawned += 1;
+        
+        let token = self.token.clone();
+        let future = f(token.clone());
+        self.tasks.spawn(async move {
+            let result = match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(Ok(value)) => Ok(value),
+                Ok(Err(e)) => Err(ScopeError::Failed(e)),
+                Err(payload) => Err(ScopeError::Panicked(panic_message(payload.as_ref()))),
+            };
+            if result.is_err() {
+                token.cancel();
+            }
+            (index, result)
+        });
+    }
+    
+    /// 等所有子任务结束；全部成功时按 spawn 的顺序返回结果
+    pub async fn join(mut self) -> Result<Vec<T>, ScopeError<E>> {
+        let mut results = Vec::with_capacity(self.spawned);
+        let mut first_error = None;
+        
+        while let Some(joined) = self.tasks.join_next().await {
+            // 子任务里的 panic 一般已经被捕获；JoinError 说明任务没有结果，
+            // 不能当作成功跳过，否则返回的结果会比 spawn 的任务少
+            let (index, result) = match joined {
+                Ok(joined) => joined,
+                Err(e) => {
+                    let error = match e.try_into_panic() {
+                        Ok(payload) => ScopeError::Panicked(panic_message(payload.as_ref())),
+                        Err(_) => ScopeError::Aborted,
+                    };
+                    first_error.get_or_insert(error);
+                    continue;
+                }
+            };
+            match result {
+                Ok(value) => results.push((index, value)),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+        results.sort_by_key(|(index, _)| *index);
+        Ok(results.into_iter().map(|(_, value)| value).collect())
+    }
+}
+
+impl<T, E> Drop for TaskScope<T, E> {
+    fn drop(&mut self) {
+        self.token.cancel();
+        // JoinSet 的 Drop 会 abort 剩下的任务
+    }
+}
+
+/// # 实战示例：可靠的任务队列
+///
+/// task_queue_demo 的队列只在内存里，任务失败就丢了。JobQueue 在它的基础上
+/// 加了多个 Worker、失败重试、可见性超时、死信列表和文件持久化。
+pub async fn job_queue_demo() {
+    println!("\n=== 可靠的任务队列 ===");
+    
+    #[derive(Serialize, Deserialize)]
+    struct Email {
+        to: String,
+    }
+    
+    /// 每个地址第一次发送都失败，无效地址永远失败
+    #[derive(Default)]
+    struct SendEmail {
+        tried: Mutex<HashMap<String, u32>>,
+    }
+    
+    #[async_trait]
+    impl JobHandler for SendEmail {
+        const KIND: &'static str = "send_email";
+        type Payload = Email;
+        
+        async fn handle(&self, email: Email) -> Result<(), String> {
+            let attempt = {
+                let mut tried = self.tried.lock().unwrap();
+                let attempt = tried.entry(email.to.clone()).or_insert(0);
+                *attempt += 1;
+                *attempt
+            };
+            sleep(Duration::from_millis(20)).await;
+            
+            if email.to.contains("invalid") {
+                return Err(format!("地址无效: {}", email.to));
+            }
+            if attempt == 1 {
+                return Err(format!("连接超时: {}", email.to));
+            }
+            println!("  第 {} 次发送给 {} 成功", attempt, email.to);
+            Ok(())
+        }
+    }
+    
+    let path = std::env::temp_dir().join("job_queue_demo.jsonl");
+    let _ = std::fs::remove_file(&path);
+    let config = JobQueueConfig {
+        workers: 2,
+        max_attempts: 3,
+        backoff_base: Duration::from_millis(50),
+        ..JobQueueConfig::default()
+    };
+    
+    // 先入队再关闭：任务保存在日志里
+    {
+        let queue = JobQueue::open(&path, config.clone()).expect("打开队列失败");
+        for to in ["a@example.com", "b@example.com", "invalid"] {
+            let id = queue
+                .enqueue::<SendEmail>(&Email { to: to.to_string() })
+                .await
+                .unwrap();
+            println!("  入队 #{}: {}", id, to);
+        }
+    }
+    
+    // "重启"之后继续处理
+    let queue = JobQueue::open(&path, config).expect("打开队列失败");
+    queue.register(SendEmail::default());
+    println!("  重启后待处理: {}", queue.pending());
+    
+    let shutdown = CancellationToken::new();
+    let workers = {
+        let queue = queue.clone();
+        let shutdown = shutdown.clone();
+        task::spawn(async move { queue.run(&shutdown).await })
+    };
+    
+    queue.wait_idle().await;
+    shutdown.cancel();
+    if let Err(e) = workers.await.unwrap() {
+        println!("  Worker 停止: {}", e);
+    }
+    
+    for job in queue.dead_letters() {
+        println!("  死信 #{} ({} 次): {}", job.id, job.attempts, job.error);
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+/// 一种任务的处理器，Payload 序列化成 JSON 存进队列
+///
+/// 返回 Err 表示这次执行失败，队列按退避时间重试；重试次数用完进入死信列表。
+/// 同一个任务可能被执行不止一次（超过可见性超时、进程在完成前退出），
+/// 处理器应当是幂等的。
+#[async_trait]
+pub trait JobHandler: Send + Sync + 'static {
+    /// 任务类型名，持久化后用来找到处理器，不要随意修改
+    const KIND: &'static str;
+    type Payload: Serialize + DeserializeOwned + Send + 'static;
+    
+    async fn handle(&self, payload: Self::Payload) -> Result<(), String>;
+}
+
+/// 一次执行失败的结果
+enum JobFailure {
+    /// 可以重试
+    Retry(String),
+    /// 重试也不会成功（没有处理器、payload 解析失败），直接进入死信列表
+    Permanent(String),
+}
+
+/// 擦除 Payload 类型，让不同的处理器放进同一个表
+#[async_trait]
+trait ErasedHandler: Send + Sync {
+    async fn handle(&self, payload: serde_json::Value) -> Result<(), JobFailure>;
+}
+
+struct TypedHandler<H>(H);
+
+#[async_trait]
+impl<H: JobHandler> ErasedHandler for TypedHandler<H> {
+    async fn handle(&self, payload: serde_json::Value) -> Result<(), JobFailure> {
+        let payload = serde_json::from_value(payload)
+            .map_err(|e| JobFailure::Permanent(format!("payload 解析失败: {}", e)))?;
+        self.0.handle(payload).await.map_err(JobFailure::Retry)
+    }
+}
+
+/// JobQueue 的配置
+#[derive(Debug, Clone)]
+pub struct JobQueueConfig {
+    pub workers: usize,
+    /// 包括第一次在内最多执行几次
+    pub max_attempts: u32,
+    /// 任务被取走后对其他 Worker 不可见的时间，也是单次执行的超时时间
+    pub visibility_timeout: Duration,
+    /// 第 n 次失败后等待 backoff_base * 2^(n-1)，最多 backoff_max
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+}
+
+impl Default for JobQueueConfig {
+    fn default() -> Self {
+        JobQueueConfig {
+            workers: 4,
+            max_attempts: 5,
+            visibility_timeout: Duration::from_secs(30),
+            backoff_base: Duration::from_secs(1),
+            backoff_max: Duration::from_secs(60),
+        }
+    }
+}
+
+impl JobQueueConfig {
+    fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(16);
+        self.backoff_base
+            .saturating_mul(1 << exponent)
+            .min(self.backoff_max)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JobRecord {
+    id: u64,
+    kind: String,
+    payload: serde_json::Value,
+    /// 被取走的次数；取走时就计数，进程在执行中退出也算一次
+    attempts: u32,
+    /// Unix 毫秒，在此之前不会被取走：重试的退避，或者执行中任务的租约
+    visible_at: u64,
+    /// 每次取走加一，用来识别租约过期后迟到的结果
+    lease: u64,
+    last_error: Option<String>,
+}
+
+/// 重试次数用完或无法处理的任务
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadJob {
+    pub id: u64,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub attempts: u32,
+    pub error: String,
+}
+
+/// 队列的当前状态，由日志重放得到
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    jobs: Vec<JobRecord>,
+    dead: Vec<DeadJob>,
+}
+
+impl QueueState {
+    fn apply(&mut self, entry: JobLogEntry) {
+        match entry {
+            JobLogEntry::NextId { next_id } => self.next_id = self.next_id.max(next_id),
+            JobLogEntry::Put { job } => {
+                self.next_id = self.next_id.max(job.id + 1);
+                match self.jobs.iter_mut().find(|j| j.id == job.id) {
+                    Some(existing) => *existing = job,
+                    None => self.jobs.push(job),
+                }
+            }
+            JobLogEntry::Done { id } => self.jobs.retain(|j| j.id != id),
+            JobLogEntry::Dead { job } => {
+                self.jobs.retain(|j| j.id != job.id);
+                self.dead.push(job);
+            }
+        }
+    }
+    
+    /// 只包含当前状态的日志内容，压缩时用来替换旧日志
+    fn snapshot(&self) -> std::io::Result<Vec<u8>> {
+        let mut bytes = encode_job_entry(&JobLogEntry::NextId {
+            next_id: self.next_id,
+        })?;
+        for job in &self.jobs {
+            bytes.extend(encode_job_entry(&JobLogEntry::Put { job: job.clone() })?);
+        }
+        for job in &self.dead {
+            bytes.extend(encode_job_entry(&JobLogEntry::Dead { job: job.clone() })?);
+        }
+        Ok(bytes)
+    }
+}
+
+/// 日志中的一条记录（JSON Lines，每行一条）
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JobLogEntry {
+    /// 压缩后的第一行，保证 id 不会被复用
+    NextId { next_id: u64 },
+    /// 入队，或者任务的租约、重试时间有变化；按 id 整条替换
+    Put { job: JobRecord },
+    /// 执行成功，从队列删除
+    Done { id: u64 },
+    /// 进入死信列表
+    Dead { job: DeadJob },
+}
+
+/// 一条日志编码成完整的一行
+fn encode_job_entry(entry: &JobLogEntry) -> std::io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// 上次压缩后至少追加这么多行，并且超过当前状态行数的两倍，才压缩
+const COMPACT_MIN_ENTRIES: usize = 256;
+
+/// 追加写的持久化日志
+///
+/// 和 http_server 的 FileUserRepository 一样：每次修改整行写入并 sync 之后
+/// 才改内存；只有最后一行允许不完整（崩溃时写了一半）。日志比当前状态大很多
+/// 时写一份只有当前状态的新文件，sync 后 rename 替换。
+struct QueueLog {
+    path: PathBuf,
+    file: std::fs::File,
+    /// 文件中完整记录的长度，写失败时截断到这里，不在日志中间留下半行
+    len: u64,
+    /// 上次压缩后追加的行数
+    appended: usize,
+}
+
+impl QueueLog {
+    /// 重放日志得到当前状态，并压缩成只包含当前状态的新日志
+    fn open(path: PathBuf) -> std::io::Result<(QueueLog, QueueState)> {
+        let mut state = QueueState::default();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let lines: Vec<(usize, &str)> = content
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !line.trim().is_empty())
+                    .collect();
+                let last = lines.len().saturating_sub(1);
+                
+                for (i, &(n, line)) in lines.iter().enumerate() {
+                    match serde_json::from_str(line) {
+                        Ok(entry) => state.apply(entry),
+                        // 崩溃时可能留下写了一半的最后一行，这次修改没有生效
+                        Err(_) if i == last => {}
+                        Err(e) => {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                format!("{} 第 {} 行无法解析: {}", path.display(), n + 1, e),
+                            ))
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        
+        let snapshot = state.snapshot()?;
+        let (file, len) = QueueLog::write_snapshot(&path, &snapshot)?;
+        let log = QueueLog {
+            path,
+            file,
+            len,
+            appended: 0,
+        };
+        Ok((log, state))
+    }
+    
+    /// 写临时文件并 sync，再 rename 替换；返回的句柄指向新文件，可以继续追加
+    fn write_snapshot(
+        path: &std::path::Path,
+        snapshot: &[u8],
+    ) -> std::io::Result<(std::fs::File, u64)> {
+        use std::io::Write;
+        
+        let tmp = path.with_extension("compact.tmp");
+        let _ = std::fs::remove_file(&tmp);
+        let mut file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&tmp)?;
+        file.write_all(snapshot)?;
+        file.sync_all()?;
+        // 先拿到句柄再 rename：rename 失败时旧日志和旧句柄都不受影响
+        std::fs::rename(&tmp, path)?;
+        Ok((file, snapshot.len() as u64))
+    }
+    
+    fn needs_compaction(&self, live: usize) -> bool {
+        self.appended >= COMPACT_MIN_ENTRIES && self.appended > 2 * live
+    }
+    
+    fn compact(&mut self, snapshot: &[u8]) -> std::io::Result<()> {
+        let (file, len) = QueueLog::write_snapshot(&self.path, snapshot)?;
+        self.file = file;
+        self.len = len;
+        self.appended = 0;
+        Ok(())
+    }
+    
+    /// 整行一次 write_all 再 sync_data，返回成功时这次修改已经落盘
+    fn append(&mut self, line: &[u8]) -> std::io::Result<()> {
+        use std::io::Write;
+        
+        let written = self
+            .file
+            .write_all(line)
+            .and_then(|()| self.file.sync_data());
+        if let Err(e) = written {
+            // 去掉可能写了一半的行，后面的记录才不会接在坏行后面
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        self.len += line.len() as u64;
+        self.appended += 1;
+        Ok(())
+    }
+}
+
+/// JobQueue 的错误
+#[derive(Debug)]
+pub enum JobQueueError {
+    Serialize(serde_json::Error),
+    /// 写持久化日志失败，这次修改没有生效（内存和文件都保持原样）
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for JobQueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobQueueError::Serialize(e) => write!(f, "payload 序列化失败: {}", e),
+            JobQueueError::Io(e) => write!(f, "保存队列失败: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JobQueueError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JobQueueError::Serialize(e) => Some(e),
+            JobQueueError::Io(e) => Some(e),
+        }
+    }
+}
+
+/// lease 没有取到任务
+#[derive(Debug)]
+enum LeaseError {
+    /// 没有可见的任务；Some 是离最早的任务可见还有多久
+    NotReady(Option<Duration>),
+    /// 保存租约失败，任务没有被取走
+    Io(std::io::Error),
+}
+
+struct QueueInner {
+    config: JobQueueConfig,
+    handlers: Mutex<HashMap<&'static str, Arc<dyn ErasedHandler>>>,
+    /// 只在读写内存时短暂持有，不跨 .await，也不在持有时做 IO
+    state: Mutex<QueueState>,
+    /// 所有修改都先拿这把锁，日志的顺序和内存中修改的顺序一致
+    ///
+    /// 写日志时把 QueueLog 移进 spawn_blocking，写完放回；内存队列始终为 None
+    log: tokio::sync::Mutex<Option<QueueLog>>,
+    persistent: bool,
+    /// 有任务入队、结束或改变可见时间时通知等待的 Worker 和 wait_idle
+    changed: Notify,
+}
+
+/// 带重试、可见性超时和死信列表的任务队列
+///
+/// Worker 取走任务时给它一个租约（可见性超时），期间其他 Worker 看不到它；
+/// 处理器在租约内没有完成时这次执行算失败，任务按退避时间重新可见。
+/// 持久化模式下每次状态变化追加一行日志并 sync（在 spawn_blocking 中进行，
+/// 不阻塞运行时线程），执行中的任务连同租约一起保存，进程重启后租约到期的
+/// 任务会被重新执行。写日志失败时这次修改不生效，错误返回给调用方。
+///
+/// ```ignore
+/// let queue = JobQueue::open("jobs.jsonl", JobQueueConfig::default())?;
+/// queue.register(SendEmail::default());
+/// queue.enqueue::<SendEmail>(&Email { to: "a@example.com".into() }).await?;
+/// queue.run(&shutdown).await?;
+/// ```
+#[derive(Clone)]
+pub struct JobQueue {
+    inner: Arc<QueueInner>,
+}
+
+impl JobQueue {
+    /// 只在内存中的队列
+    pub fn new(config: JobQueueConfig) -> Self {
+        JobQueue::with_state(config, QueueState::default(), None)
+    }
+    
+    /// 用 JSON Lines 日志持久化的队列；文件不存在时从空队列开始
+    ///
+    /// 打开时重放并压缩日志，是阻塞的文件操作，在启动阶段调用。
+    pub fn open(path: impl Into<PathBuf>, config: JobQueueConfig) -> std::io::Result<Self> {
+        let (log, state) = QueueLog::open(path.into())?;
+        Ok(JobQueue::with_state(config, state, Some(log)))
+    }
+    
+    fn with_state(config: JobQueueConfig, state: QueueState, log: Option<QueueLog>) -> Self {
+        JobQueue {
+            inner: Arc::new(QueueInner {
+                config,
+                handlers: Mutex::new(HashMap::new()),
+                state: Mutex::new(state),
+                persistent: log.is_some(),
+                log: tokio::sync::Mutex::new(log),
+                changed: Notify::new(),
+            }),
+        }
+    }
+    
+    /// 注册处理器；同一 KIND 重复注册时后注册的生效
+    pub fn register<H: JobHandler>(&self, handler: H) {
+        self.inner
+            .handlers
+            .lock()
+            .unwrap()
+            .insert(H::KIND, Arc::new(TypedHandler(handler)));
+    }
+    
+    /// 入队一个任务，返回任务 id；持久化模式下写入日志后才返回
+    pub async fn enqueue<H: JobHandler>(&self, payload: &H::Payload) -> Result<u64, JobQueueError> {
+        let payload = serde_json::to_value(payload).map_err(JobQueueError::Serialize)?;
+        
+        let mut log = self.inner.log.lock().await;
+        let id = self.inner.state.lock().unwrap().next_id;
+        let job = JobRecord {
+            id,
+            kind: H::KIND.to_string(),
+            payload,
+            attempts: 0,
+            visible_at: unix_millis(),
+            lease: 0,
+            last_error: None,
+        };
+        self.commit(&mut log, JobLogEntry::Put { job })
+            .await
+            .map_err(JobQueueError::Io)?;
+        drop(log);
+        
+        self.inner.changed.notify_waiters();
+        Ok(id)
+    }
+    
+    /// 还没有完成的任务数（包括执行中和等待重试的）
+    pub fn pending(&self) -> usize {
+        self.inner.state.lock().unwrap().jobs.len()
+    }
+    
+    pub fn dead_letters(&self) -> Vec<DeadJob> {
+        self.inner.state.lock().unwrap().dead.clone()
+    }
+    
+    /// 等到没有未完成的任务
+    pub async fn wait_idle(&self) {
+        loop {
+            let changed = self.inner.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            
+            if self.pending() == 0 {
+                return;
+            }
+            changed.await;
+        }
+    }
+    
+    /// 启动 config.workers 个 Worker，直到 shutdown 被取消
+    ///
+    /// 取消后 Worker 不再取新任务，执行中的任务会执行完（最多到可见性超时），
+    /// 所有 Worker 退出后返回。写日志失败时所有 Worker 停止并返回这个错误，
+    /// 文件里仍然是最后一次成功写入的状态。
+    pub async fn run(&self, shutdown: &CancellationToken) -> Result<(), JobQueueError> {
+        let mut scope = TaskScope::<(), JobQueueError>::new(shutdown);
+        for _ in 0..self.inner.config.workers.max(1) {
+            let queue = self.clone();
+            scope.spawn(move |token| async move { queue.work(token).await });
+        }
+        match scope.join().await {
+            Ok(_) => Ok(()),
+            Err(ScopeError::Failed(e)) => Err(e),
+            Err(e) => panic!("Worker 异常退出: {}", e),
+        }
+    }
+    
+    async fn work(&self, shutdown: CancellationToken) -> Result<(), JobQueueError> {
+        loop {
+            // 先登记再查看队列，查看之后的入队一定能唤醒这里
+            let changed = self.inner.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            
+            if shutdown.is_cancelled() {
+                return Ok(());
+            }
+            
+            match self.lease().await {
+                Ok(job) => self.execute(job).await.map_err(JobQueueError::Io)?,
+                Err(LeaseError::NotReady(Some(delay))) => {
+                    tokio::select! {
+                        _ = &mut changed => {}
+                        _ = sleep(delay) => {}
+                        _ = shutdown.cancelled() => {}
+                    }
+                }
+                Err(LeaseError::NotReady(None)) => {
+                    tokio::select! {
+                        _ = &mut changed => {}
+                        _ = shutdown.cancelled() => {}
+                    }
+                }
+                Err(LeaseError::Io(e)) => return Err(JobQueueError::Io(e)),
+            }
+        }
+    }
+    
+    /// 取走最早可见的任务，租约写入日志后才返回
+    ///
+    /// 次数已经用完却又可见的任务只能是最后一次租约到期了（Worker 卡住或崩溃），
+    /// 直接进入死信列表，不会再多执行一次。
+    async fn lease(&self) -> Result<JobRecord, LeaseError> {
+        let mut log = self.inner.log.lock().await;
+        loop {
+            let entry = {
+                let state = self.inner.state.lock().unwrap();
+                let now = unix_millis();
+                
+                let Some(job) = state.jobs.iter().min_by_key(|job| (job.visible_at, job.id)) else {
+                    return Err(LeaseError::NotReady(None));
+                };
+                if job.visible_at > now {
+                    let delay = Duration::from_millis(job.visible_at - now);
+                    return Err(LeaseError::NotReady(Some(delay)));
+                }
+                
+                // 最后一次租约到期：有之前的失败原因就记它，比超时本身更有用
+                if job.attempts >= self.inner.config.max_attempts {
+                    JobLogEntry::Dead {
+                        job: DeadJob {
+                            id: job.id,
+                            kind: job.kind.clone(),
+                            payload: job.payload.clone(),
+                            attempts: job.attempts,
+                            error: job
+                                .last_error
+                                .clone()
+                                .unwrap_or_else(|| "超过可见性超时".to_string()),
+                        },
+                    }
+                } else {
+                    let mut job = job.clone();
+                    job.attempts += 1;
+                    job.lease += 1;
+                    job.visible_at = now + self.inner.config.visibility_timeout.as_millis() as u64;
+                    JobLogEntry::Put { job }
+                }
+            };
+            
+            let leased = match &entry {
+                JobLogEntry::Put { job } => Some(job.clone()),
+                _ => None,
+            };
+            self.commit(&mut log, entry).await.map_err(LeaseError::Io)?;
+            match leased {
+                Some(job) => return Ok(job),
+                None => self.inner.changed.notify_waiters(),
+            }
+        }
+    }
+    
+    async fn execute(&self, job: JobRecord) -> std::io::Result<()> {
+        use futures_util::FutureExt;
+        use std::panic::AssertUnwindSafe;
+        
+        let handler = self
+            .inner
+            .handlers
+            .lock()
+            .unwrap()
+            .get(job.kind.as_str())
+            .cloned();
+        
+        let outcome = match handler {
+            None => Err(JobFailure::Permanent(format!(
+                "没有注册 {} 的处理器",
+                job.kind
+            ))),
+            Some(handler) => {
+                let run = AssertUnwindSafe(handler.handle(job.payload.clone())).catch_unwind();
+                match tokio::time::timeout(self.inner.config.visibility_timeout, run).await {
+                    Ok(Ok(result)) => result,
+                    Ok(Err(payload)) => Err(JobFailure::Retry(format!(
+                        "处理器 panic: {}",
+                        panic_message(payload.as_ref())
+                    ))),
+                    Err(_) => Err(JobFailure::Retry("超过可见性超时".to_string())),
+                }
+            }
+        };
+        
+        self.finish(&job, outcome).await
+    }
+    
+    /// 记录一次执行的结果：完成的删除，失败的安排重试或进入死信列表
+    async fn finish(
+        &self,
+        leased: &JobRecord,
+        outcome: Result<(), JobFailure>,
+    ) -> std::io::Result<()> {
+        let mut log = self.inner.log.lock().await;
+        let entry = {
+            let state = self.inner.state.lock().unwrap();
+            // 租约已经被别的 Worker 接手，这个结果作废
+            let Some(job) = state
+                .jobs
+                .iter()
+                .find(|job| job.id == leased.id && job.lease == leased.lease)
+            else {
+                return Ok(());
+            };
+            
+            let config = &self.inner.config;
+            match outcome {
+                Ok(()) => JobLogEntry::Done { id: job.id },
+                Err(JobFailure::Retry(error)) if job.attempts < config.max_attempts => {
+                    let mut job = job.clone();
+                    job.visible_at =
+                        unix_millis() + config.backoff(job.attempts).as_millis() as u64;
+                    job.last_error = Some(error);
+                    JobLogEntry::Put { job }
+                }
+                Err(JobFailure::Retry(error)) | Err(JobFailure::Permanent(error)) => {
+                    JobLogEntry::Dead {
+                        job: DeadJob {
+                            id: job.id,
+                            kind: job.kind.clone(),
+                            payload: job.payload.clone(),
+                            attempts: job.attempts,
+                            error,
+                        },
+                    }
+                }
+            }
+        };
+        
+        self.commit(&mut log, entry).await?;
+        drop(log);
+        self.inner.changed.notify_waiters();
+        Ok(())
+    }
+    
+    /// 先写日志再改内存，写失败时内存保持原样，和文件一致
+    ///
+    /// 调用方持有 log 锁，从读取状态到这里之间没有别的修改插进来。
+    async fn commit(&self, log: &mut Option<QueueLog>, entry: JobLogEntry) -> std::io::Result<()> {
+        if self.inner.persistent {
+            let writer = log
+                .take()
+                .ok_or_else(|| std::io::Error::other("之前写日志的任务异常退出，日志不可用"))?;
+            let line = encode_job_entry(&entry)?;
+            let snapshot = {
+                let state = self.inner.state.lock().unwrap();
+                let live = state.jobs.len() + state.dead.len();
+                if writer.needs_compaction(live) {
+                    Some(state.snapshot()?)
+                } else {
+                    None
+                }
+            };
+            
+            // 压缩在追加之前：压缩失败时这次修改也没有写入，直接返回错误
+            let (writer, written) = task::spawn_blocking(move || {
+                let mut writer = writer;
+                let written = match snapshot {
+                    Some(snapshot) => writer.compact(&snapshot),
+                    None => Ok(()),
+                };
+                let written = written.and_then(|()| writer.append(&line));
+                (writer, written)
+            })
+            .await
+            .map_err(std::io::Error::other)?;
+            *log = Some(writer);
+            written?;
+        }
+        
+        self.inner.state.lock().unwrap().apply(entry);
+        Ok(())
+    }
+}
+
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// catch_unwind 得到的 panic 信息
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "未知 panic".to_string())
+}
+
 /// # 错误处理
 pub async fn error_handling_demo() {
     println!("\n=== 异步错误处理 ===");
@@ -340,6 +1535,7 @@ pub async fn async_best_practices_demo() {
     println!("   - 使用 join! 而不是串行 await");
     println!("   - 合理使用缓冲通道");
     println!("   - 注意任务的生命周期");
+    println!("   - 用 CancellationToken / TaskScope 取消和回收子任务");
     
     println!("\n5. 测试:");
     println!("   - 使用 #[tokio::test]");
@@ -364,6 +1560,8 @@ pub async fn run_all() {
     async_channel_demo().await;
     http_requests_demo().await;
     task_queue_demo().await;
+    cancellation_demo().await;
+    job_queue_demo().await;
     error_handling_demo().await;
     async_trait_demo().await;
     async_best_practices_demo().await;
@@ -383,6 +1581,20 @@ mod tests {
         assert_eq!(result, 5);
     }
     
+    #[tokio::test]
+    async fn test_task_queue_reports_progress() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        run_task_queue(3, move |progress| tx.send(progress).unwrap()).await;
+        
+        let progress: Vec<TaskProgress> = rx.iter().collect();
+        assert_eq!(progress.len(), 3);
+        for (i, p) in progress.iter().enumerate() {
+            assert_eq!(p.id, i as i32);
+            assert_eq!(p.completed, i + 1);
+            assert_eq!(p.total, 3);
+        }
+    }
+    
     #[tokio::test]
     async fn test_concurrent_execution() {
         async fn task() -> i32 {
@@ -394,4 +1606,535 @@ mod tests {
         assert_eq!(r1, 42);
         assert_eq!(r2, 42);
     }
+    
+    #[tokio::test]
+    async fn test_cancel_propagates_to_children() {
+        let parent = CancellationToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        let sibling = parent.child();
+        
+        // 子令牌取消不影响父令牌和兄弟
+        sibling.cancel();
+        assert_eq!(sibling.reason(), Some(CancelReason::Cancelled));
+        assert!(!parent.is_cancelled());
+        assert!(!child.is_cancelled());
+        
+        let waiter = {
+            let grandchild = grandchild.clone();
+            task::spawn(async move { grandchild.cancelled().await })
+        };
+        sleep(Duration::from_millis(10)).await;
+        parent.cancel();
+        
+        let reason = tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("取消应当唤醒等待者")
+            .unwrap();
+        assert_eq!(reason, CancelReason::Cancelled);
+        assert!(child.is_cancelled());
+        
+        // 从已经取消的令牌派生的子令牌生来就是取消的
+        assert!(parent.child().is_cancelled());
+    }
+    
+    #[tokio::test]
+    async fn test_deadline_propagates_to_children() {
+        let parent = CancellationToken::with_timeout(Duration::from_millis(50));
+        let longer = parent.child_with_timeout(Duration::from_secs(10));
+        let shorter = parent.child_with_timeout(Duration::from_millis(10));
+        assert_eq!(longer.deadline(), parent.deadline());
+        assert!(shorter.deadline() < parent.deadline());
+        
+        let start = Instant::now();
+        let result = shorter
+            .run_until_cancelled(sleep(Duration::from_secs(5)))
+            .await;
+        assert_eq!(result, Err(CancelReason::DeadlineExceeded));
+        assert!(!parent.is_cancelled());
+        
+        let result = longer
+            .run_until_cancelled(sleep(Duration::from_secs(5)))
+            .await;
+        assert_eq!(result, Err(CancelReason::DeadlineExceeded));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(parent.reason(), Some(CancelReason::DeadlineExceeded));
+        
+        // 没有到截止时间就完成的 future 正常返回
+        let token = CancellationToken::with_timeout(Duration::from_secs(5));
+        assert_eq!(token.run_until_cancelled(async { 7 }).await, Ok(7));
+    }
+    
+    #[tokio::test]
+    async fn test_scope_cancels_siblings_on_failure() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        
+        let observed = Arc::new(AtomicUsize::new(0));
+        let mut scope = TaskScope::new(&CancellationToken::new());
+        for id in 0..3 {
+            let observed = Arc::clone(&observed);
+            scope.spawn(move |token| async move {
+                if id == 1 {
+                    sleep(Duration::from_millis(20)).await;
+                    return Err("boom");
+                }
+                token.cancelled().await;
+                observed.fetch_add(1, Ordering::SeqCst);
+                Ok(id)
+            });
+        }
+        
+        let start = Instant::now();
+        assert_eq!(scope.join().await, Err(ScopeError::Failed("boom")));
+        assert_eq!(observed.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+    
+    #[tokio::test]
+    async fn test_scope_results_and_panics() {
+        let mut scope = TaskScope::<u64, String>::new(&CancellationToken::new());
+        for id in 0..4u64 {
+            scope.spawn(move |_| async move {
+                // 完成顺序和 spawn 顺序相反
+                sleep(Duration::from_millis(40 - id * 10)).await;
+                Ok(id)
+            });
+        }
+        assert_eq!(scope.join().await, Ok(vec![0, 1, 2, 3]));
+        
+        let mut scope = TaskScope::<(), String>::new(&CancellationToken::new());
+        scope.spawn(|_| async { panic!("出错了") });
+        scope.spawn(|token| async move {
+            token.cancelled().await;
+            Ok(())
+        });
+        assert_eq!(
+            scope.join().await,
+            Err(ScopeError::Panicked("出错了".to_string()))
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_scope_run_waits_for_children_on_error_and_panic() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        
+        // 子任务收到取消后还要花点时间收尾，run 返回时收尾已经完成
+        fn spawn_slow_cleanup(scope: &mut TaskScope<(), &'static str>, finished: &Arc<AtomicBool>) {
+            let finished = Arc::clone(finished);
+            scope.spawn(move |token| async move {
+                token.cancelled().await;
+                sleep(Duration::from_millis(50)).await;
+                finished.store(true, Ordering::SeqCst);
+                Ok(())
+            });
+        }
+        
+        let finished = Arc::new(AtomicBool::new(false));
+        let result = TaskScope::run(&CancellationToken::new(), |scope| {
+            spawn_slow_cleanup(scope, &finished);
+            Box::pin(async { Err::<(), _>("主体失败") })
+        })
+        .await;
+        assert_eq!(result, Err(ScopeError::Failed("主体失败")));
+        assert!(finished.load(Ordering::SeqCst));
+        
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&finished);
+        let panicked = task::spawn(async move {
+            TaskScope::run::<(), _>(&CancellationToken::new(), |scope| {
+                spawn_slow_cleanup(scope, &flag);
+                Box::pin(async { panic!("主体 panic") })
+            })
+            .await
+        })
+        .await
+        .unwrap_err();
+        assert!(panicked.is_panic());
+        assert!(finished.load(Ordering::SeqCst));
+        
+        // 主体成功时返回主体和子任务的结果
+        let result = TaskScope::<u32, ()>::run(&CancellationToken::new(), |scope| {
+            Box::pin(async move {
+                scope.spawn(|_| async { Ok(1) });
+                scope.spawn(|_| async { Ok(2) });
+                Ok("ok")
+            })
+        })
+        .await;
+        assert_eq!(result, Ok(("ok", vec![1, 2])));
+    }
+    
+    #[tokio::test]
+    async fn test_scope_join_reports_aborted_tasks() {
+        let mut scope = TaskScope::<u32, ()>::new(&CancellationToken::new());
+        scope.spawn(|_| async { Ok(1) });
+        scope.spawn(|_| async {
+            sleep(Duration::from_secs(10)).await;
+            Ok(2)
+        });
+        sleep(Duration::from_millis(20)).await;
+        // 模拟运行时关闭：任务被中止，没有结果
+        scope.tasks.abort_all();
+        
+        assert_eq!(scope.join().await, Err(ScopeError::Aborted));
+    }
+    
+    /// 每次执行的 (Work.n, 开始时间)
+    type CallLog = Arc<Mutex<Vec<(u32, Instant)>>>;
+    
+    /// 测试用的处理器：前 fail_first 次失败，每次执行先睡 delay
+    struct Flaky {
+        fail_first: u32,
+        delay: Duration,
+        calls: CallLog,
+    }
+    
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Work {
+        n: u32,
+    }
+    
+    #[async_trait]
+    impl JobHandler for Flaky {
+        const KIND: &'static str = "flaky";
+        type Payload = Work;
+        
+        async fn handle(&self, work: Work) -> Result<(), String> {
+            let call = {
+                let mut calls = self.calls.lock().unwrap();
+                calls.push((work.n, Instant::now()));
+                calls.iter().filter(|(n, _)| *n == work.n).count() as u32
+            };
+            sleep(self.delay).await;
+            if call <= self.fail_first {
+                Err(format!("第 {} 次失败", call))
+            } else {
+                Ok(())
+            }
+        }
+    }
+    
+    fn flaky(fail_first: u32, delay: Duration) -> (Flaky, CallLog) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let handler = Flaky {
+            fail_first,
+            delay,
+            calls: Arc::clone(&calls),
+        };
+        (handler, calls)
+    }
+    
+    fn test_config() -> JobQueueConfig {
+        JobQueueConfig {
+            workers: 2,
+            max_attempts: 3,
+            visibility_timeout: Duration::from_secs(5),
+            backoff_base: Duration::from_millis(40),
+            backoff_max: Duration::from_secs(1),
+        }
+    }
+    
+    /// 启动 Worker，等队列空了再关闭
+    async fn drain(queue: &JobQueue) {
+        let shutdown = CancellationToken::new();
+        let workers = {
+            let queue = queue.clone();
+            let shutdown = shutdown.clone();
+            task::spawn(async move { queue.run(&shutdown).await })
+        };
+        tokio::time::timeout(Duration::from_secs(5), queue.wait_idle())
+            .await
+            .expect("队列应当在 5 秒内处理完");
+        shutdown.cancel();
+        workers.await.unwrap().unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_retries_with_backoff() {
+        let queue = JobQueue::new(test_config());
+        let (handler, calls) = flaky(2, Duration::ZERO);
+        queue.register(handler);
+        queue.enqueue::<Flaky>(&Work { n: 1 }).await.unwrap();
+        
+        drain(&queue).await;
+        
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        // 退避 40ms、80ms
+        assert!(calls[1].1 - calls[0].1 >= Duration::from_millis(40));
+        assert!(calls[2].1 - calls[1].1 >= Duration::from_millis(80));
+        assert!(queue.dead_letters().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_dead_letters() {
+        let queue = JobQueue::new(test_config());
+        let (handler, calls) = flaky(u32::MAX, Duration::ZERO);
+        queue.register(handler);
+        let id = queue.enqueue::<Flaky>(&Work { n: 1 }).await.unwrap();
+        
+        // 没有注册处理器、payload 不合法的任务不重试
+        struct Unregistered;
+        #[async_trait]
+        impl JobHandler for Unregistered {
+            const KIND: &'static str = "unregistered";
+            type Payload = ();
+            async fn handle(&self, _: ()) -> Result<(), String> {
+                Ok(())
+            }
+        }
+        let unregistered = queue.enqueue::<Unregistered>(&()).await.unwrap();
+        
+        struct WrongPayload;
+        #[async_trait]
+        impl JobHandler for WrongPayload {
+            const KIND: &'static str = "flaky";
+            type Payload = String;
+            async fn handle(&self, _: String) -> Result<(), String> {
+                Ok(())
+            }
+        }
+        let malformed = queue
+            .enqueue::<WrongPayload>(&"不是 Work".to_string())
+            .await
+            .unwrap();
+        
+        drain(&queue).await;
+        
+        assert_eq!(calls.lock().unwrap().len(), 3);
+        let mut dead = queue.dead_letters();
+        dead.sort_by_key(|job| job.id);
+        assert_eq!(dead.len(), 3);
+        assert_eq!((dead[0].id, dead[0].attempts), (id, 3));
+        assert_eq!(dead[0].error, "第 3 次失败");
+        assert_eq!((dead[1].id, dead[1].attempts), (unregistered, 1));
+        assert!(dead[1].error.contains("没有注册"));
+        assert_eq!((dead[2].id, dead[2].attempts), (malformed, 1));
+        assert!(dead[2].error.contains("解析失败"));
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_visibility_timeout() {
+        let config = JobQueueConfig {
+            visibility_timeout: Duration::from_millis(100),
+            backoff_base: Duration::from_millis(10),
+            ..test_config()
+        };
+        let queue = JobQueue::new(config);
+        
+        // 第一次执行超过可见性超时，被放弃后重试成功
+        struct SlowOnce {
+            slow: std::sync::atomic::AtomicBool,
+        }
+        #[async_trait]
+        impl JobHandler for SlowOnce {
+            const KIND: &'static str = "slow_once";
+            type Payload = ();
+            async fn handle(&self, _: ()) -> Result<(), String> {
+                if self.slow.swap(false, std::sync::atomic::Ordering::SeqCst) {
+                    sleep(Duration::from_secs(60)).await;
+                }
+                Ok(())
+            }
+        }
+        queue.register(SlowOnce {
+            slow: std::sync::atomic::AtomicBool::new(true),
+        });
+        queue.enqueue::<SlowOnce>(&()).await.unwrap();
+        
+        let start = Instant::now();
+        drain(&queue).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(queue.dead_letters().is_empty());
+        
+        // 每次都超时的任务用完重试次数后进入死信列表
+        struct Hang;
+        #[async_trait]
+        impl JobHandler for Hang {
+            const KIND: &'static str = "hang";
+            type Payload = ();
+            async fn handle(&self, _: ()) -> Result<(), String> {
+                sleep(Duration::from_secs(60)).await;
+                Ok(())
+            }
+        }
+        queue.register(Hang);
+        queue.enqueue::<Hang>(&()).await.unwrap();
+        
+        let start = Instant::now();
+        drain(&queue).await;
+        let dead = queue.dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 3);
+        assert_eq!(dead[0].error, "超过可见性超时");
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_workers_run_concurrently() {
+        let config = JobQueueConfig {
+            workers: 4,
+            ..test_config()
+        };
+        let queue = JobQueue::new(config);
+        let (handler, calls) = flaky(0, Duration::from_millis(100));
+        queue.register(handler);
+        for n in 0..8 {
+            queue.enqueue::<Flaky>(&Work { n }).await.unwrap();
+        }
+        
+        let start = Instant::now();
+        drain(&queue).await;
+        // 串行需要 800ms，4 个 Worker 大约 200ms
+        assert!(start.elapsed() < Duration::from_millis(500));
+        let mut done: Vec<u32> = calls.lock().unwrap().iter().map(|(n, _)| *n).collect();
+        done.sort();
+        assert_eq!(done, (0..8).collect::<Vec<_>>());
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_expired_last_lease_is_dead() {
+        let config = JobQueueConfig {
+            max_attempts: 2,
+            visibility_timeout: Duration::from_millis(50),
+            ..test_config()
+        };
+        let queue = JobQueue::new(config);
+        queue.enqueue::<Flaky>(&Work { n: 1 }).await.unwrap();
+        
+        // 两次租约都到期没有交回结果，和 Worker 卡住或崩溃一样
+        for attempt in 1..=2 {
+            let leased = queue.lease().await.unwrap();
+            assert_eq!(leased.attempts, attempt);
+            sleep(Duration::from_millis(80)).await;
+        }
+        
+        assert!(matches!(
+            queue.lease().await,
+            Err(LeaseError::NotReady(None))
+        ));
+        assert_eq!(queue.pending(), 0);
+        let dead = queue.dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(
+            (dead[0].attempts, dead[0].error.as_str()),
+            (2, "超过可见性超时")
+        );
+        
+        // 之前失败过的任务，死信里保留上一次的错误
+        queue.enqueue::<Flaky>(&Work { n: 2 }).await.unwrap();
+        let leased = queue.lease().await.unwrap();
+        queue
+            .finish(&leased, Err(JobFailure::Retry("下游返回 503".to_string())))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(60)).await;
+        let leased = queue.lease().await.unwrap();
+        assert_eq!(leased.attempts, 2);
+        sleep(Duration::from_millis(80)).await;
+        
+        assert!(matches!(
+            queue.lease().await,
+            Err(LeaseError::NotReady(None))
+        ));
+        let dead = queue.dead_letters();
+        assert_eq!(
+            (dead[1].attempts, dead[1].error.as_str()),
+            (2, "下游返回 503")
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_survives_restart() {
+        let path = std::env::temp_dir().join(format!("job_queue_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = JobQueueConfig {
+            visibility_timeout: Duration::from_millis(200),
+            ..test_config()
+        };
+        
+        {
+            let queue = JobQueue::open(&path, config.clone()).unwrap();
+            for n in 0..3 {
+                queue.enqueue::<Flaky>(&Work { n }).await.unwrap();
+            }
+            // 模拟进程在执行第一个任务时崩溃：任务已被取走，租约写进了文件
+            let leased = queue.lease().await.unwrap();
+            assert_eq!((leased.id, leased.attempts), (0, 1));
+        }
+        
+        let queue = JobQueue::open(&path, config).unwrap();
+        assert_eq!(queue.pending(), 3);
+        let (handler, calls) = flaky(0, Duration::ZERO);
+        queue.register(handler);
+        
+        let start = Instant::now();
+        drain(&queue).await;
+        
+        let calls = calls.lock().unwrap();
+        let mut done: Vec<u32> = calls.iter().map(|(n, _)| *n).collect();
+        done.sort();
+        assert_eq!(done, vec![0, 1, 2]);
+        // 崩溃时执行中的任务要等租约到期才会重新执行
+        let (_, retried_at) = calls.iter().find(|(n, _)| *n == 0).unwrap();
+        assert!(*retried_at - start >= Duration::from_millis(100));
+        
+        let saved = JobQueue::open(&path, test_config()).unwrap();
+        assert_eq!(saved.pending(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_persist_failure_is_returned() {
+        let path =
+            std::env::temp_dir().join(format!("job_queue_fail_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let queue = JobQueue::open(&path, test_config()).unwrap();
+        queue.enqueue::<Flaky>(&Work { n: 1 }).await.unwrap();
+        
+        // 换成只读句柄，之后的写入都会失败
+        queue.inner.log.lock().await.as_mut().unwrap().file = std::fs::File::open(&path).unwrap();
+        
+        let err = queue.enqueue::<Flaky>(&Work { n: 2 }).await.unwrap_err();
+        assert!(matches!(err, JobQueueError::Io(_)));
+        assert!(matches!(queue.lease().await, Err(LeaseError::Io(_))));
+        // 内存和文件都停在最后一次成功写入的状态
+        assert_eq!(queue.pending(), 1);
+        assert_eq!(queue.inner.state.lock().unwrap().jobs[0].attempts, 0);
+        
+        let (handler, calls) = flaky(0, Duration::ZERO);
+        queue.register(handler);
+        let result = queue.run(&CancellationToken::new()).await;
+        assert!(matches!(result, Err(JobQueueError::Io(_))));
+        assert!(calls.lock().unwrap().is_empty());
+        
+        let reopened = JobQueue::open(&path, test_config()).unwrap();
+        assert_eq!(reopened.pending(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+    
+    #[tokio::test]
+    async fn test_job_queue_log_is_compacted() {
+        let path =
+            std::env::temp_dir().join(format!("job_queue_compact_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let queue = JobQueue::open(&path, test_config()).unwrap();
+        let (handler, _) = flaky(0, Duration::ZERO);
+        queue.register(handler);
+        
+        // 每个任务写 3 行：入队、租约、完成
+        for n in 0..200 {
+            queue.enqueue::<Flaky>(&Work { n }).await.unwrap();
+        }
+        drain(&queue).await;
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines < COMPACT_MIN_ENTRIES + 1, "{} 行", lines);
+        
+        // 压缩后 id 不会被复用
+        let queue = JobQueue::open(&path, test_config()).unwrap();
+        assert_eq!(queue.pending(), 0);
+        assert_eq!(queue.enqueue::<Flaky>(&Work { n: 0 }).await.unwrap(), 200);
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -1,8 +1,11 @@
 // 通道 (Channels) - 消息传递
 
-use std::sync::mpsc;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// # 基本通道
 pub fn basic_channel_demo() {
@@ -295,12 +298,6 @@ pub fn fan_out_demo() {
     }
 }
 
-use std::{
-    sync::{mpsc, Arc, Mutex},
-    thread,
-    time::Duration,
-};
-
 fn fan_out_arc() {
     let (tx, rx) = mpsc::channel();
     let rx = Arc::new(Mutex::new(rx));
@@ -331,8 +328,6 @@ fn fan_out_arc() {
     }
 }
 
-use std::{thread, time::Duration};
-
 fn fan_out_crossbeam() {
     let (tx, rx) = crossbeam::channel::unbounded();
 
@@ -388,12 +383,390 @@ pub fn pipeline_demo() {
     println!("最终结果: {}", sum);
 }
 
+/// 同时等待多个 `Receiver`，执行第一个就绪的分支
+///
+/// ```ignore
+/// use crate::concurrency::channels::select;
+///
+/// select! {
+///     recv(rx1) -> msg => println!("{:?}", msg),
+///     recv(rx2) -> msg => println!("{:?}", msg),
+///     timeout(Duration::from_secs(1)) => println!("超时"),   // 或 default => ...
+/// }
+/// ```
+///
+/// `msg` 是 `Result<T, RecvError>`，所有发送端关闭时为 `Err`，这种通道也算就绪。
+/// 多个通道同时就绪时随机选择一个，和 Go 的 select 一样不会饿死任何分支。
+/// `recv(...)` 里的表达式只求值一次，按书写顺序。
+/// 分支里可以 `break`/`continue` 外层循环。
+macro_rules! select {
+    ($($arms:tt)*) => {
+        $crate::concurrency::channels::select_internal!(false; $($arms)*)
+    };
+}
+
+/// 和 `select!` 相同，但多个通道同时就绪时选写在前面的
+macro_rules! select_biased {
+    ($($arms:tt)*) => {
+        $crate::concurrency::channels::select_internal!(true; $($arms)*)
+    };
+}
+
+macro_rules! select_internal {
+    ($biased:expr; $(recv($rx:expr) -> $msg:pat => $body:expr,)+ timeout($after:expr) => $on_timeout:expr $(,)?) => {
+        $crate::concurrency::channels::select_internal!(
+            @bind $biased;
+            $crate::concurrency::channels::Wait::Until(std::time::Instant::now() + $after);
+            $on_timeout;
+            [];
+            $(($rx, $msg, $body))+
+        )
+    };
+    ($biased:expr; $(recv($rx:expr) -> $msg:pat => $body:expr,)+ default => $on_default:expr $(,)?) => {
+        $crate::concurrency::channels::select_internal!(
+            @bind $biased;
+            $crate::concurrency::channels::Wait::Never;
+            $on_default;
+            [];
+            $(($rx, $msg, $body))+
+        )
+    };
+    ($biased:expr; $(recv($rx:expr) -> $msg:pat => $body:expr),+ $(,)?) => {
+        $crate::concurrency::channels::select_internal!(
+            @bind $biased;
+            $crate::concurrency::channels::Wait::Forever;
+            unreachable!("没有超时时总有通道就绪");
+            [];
+            $(($rx, $msg, $body))+
+        )
+    };
+    // 每次递归把一个接收端求值后绑定到新的 `rx`，宏卫生让各层的 `rx` 互不遮蔽
+    (@bind $biased:expr; $wait:expr; $otherwise:expr; [$($bound:tt)*]; ($rx:expr, $msg:pat, $body:expr) $($rest:tt)*) => {{
+        let rx = &$rx;
+        $crate::concurrency::channels::select_internal!(
+            @bind $biased; $wait; $otherwise; [$($bound)* (rx, $msg, $body)]; $($rest)*
+        )
+    }};
+    // 全部绑定完：等待，再生成 if take(..) { 分支0 } else if take(..) { 分支1 } ... else { 超时/default }
+    (@bind $biased:expr; $wait:expr; $otherwise:expr; [$(($rx:ident, $msg:pat, $body:expr))+];) => {{
+        let ready = $crate::concurrency::channels::select_ready(
+            &[$($rx as &dyn $crate::concurrency::channels::Selectable),+],
+            $biased,
+            $wait,
+        );
+        let mut remaining = ready.unwrap_or(usize::MAX);
+        $(
+            if $crate::concurrency::channels::take_turn(&mut remaining) {
+                let $msg = $rx.recv_ready();
+                $body
+            } else
+        )+
+        {
+            $otherwise
+        }
+    }};
+}
+
+// 其他模块通过 crate::concurrency::channels::select 等路径使用；select_biased 暂时只有本模块在用
+#[allow(unused_imports)]
+pub(crate) use {select, select_biased, select_internal};
+
+/// 和 `mpsc::channel` 接口相同的无界通道，接收端可以用在 `select!` 中
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let chan = Arc::new(Chan {
+        state: Mutex::new(ChanState {
+            queue: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+            watchers: Vec::new(),
+        }),
+    });
+
+    (
+        Sender {
+            chan: Arc::clone(&chan),
+        },
+        Receiver {
+            chan,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+struct Chan<T> {
+    state: Mutex<ChanState<T>>,
+}
+
+struct ChanState<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+    /// 正在等待这个通道的 select（包括单独的 recv）
+    watchers: Vec<Arc<Signal>>,
+}
+
+impl<T> ChanState<T> {
+    fn is_ready(&self) -> bool {
+        !self.queue.is_empty() || self.senders == 0
+    }
+
+    fn notify(&mut self) {
+        for signal in &self.watchers {
+            signal.fire();
+        }
+    }
+}
+
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// 接收端已经丢弃时返回 Err，和 mpsc 一样把值还给调用方
+    pub fn send(&self, value: T) -> Result<(), mpsc::SendError<T>> {
+        let mut state = self.chan.state.lock().unwrap();
+        if !state.receiver_alive {
+            return Err(mpsc::SendError(value));
+        }
+        state.queue.push_back(value);
+        state.notify();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.state.lock().unwrap().senders += 1;
+        Sender {
+            chan: Arc::clone(&self.chan),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.chan.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            // 断开也是一种就绪，等待的 select 要醒来返回 Err
+            state.notify();
+        }
+    }
+}
+
+/// 和 `mpsc::Receiver` 一样只能有一个消费者：可以 Send，不能 Sync，
+/// 所以 select 看到就绪之后，消息不会被别的线程抢走
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&self) -> Result<T, mpsc::RecvError> {
+        select_ready(&[self as &dyn Selectable], true, Wait::Forever);
+        self.recv_ready()
+    }
+
+    pub fn try_recv(&self) -> Result<T, mpsc::TryRecvError> {
+        let mut state = self.chan.state.lock().unwrap();
+        match state.queue.pop_front() {
+            Some(value) => Ok(value),
+            None if state.senders == 0 => Err(mpsc::TryRecvError::Disconnected),
+            None => Err(mpsc::TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, mpsc::RecvTimeoutError> {
+        let wait = Wait::Until(Instant::now() + timeout);
+        match select_ready(&[self as &dyn Selectable], true, wait) {
+            Some(_) => self
+                .recv_ready()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            None => Err(mpsc::RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// select 判定就绪之后取值：有消息就取消息，否则说明已断开
+    pub(crate) fn recv_ready(&self) -> Result<T, mpsc::RecvError> {
+        self.chan
+            .state
+            .lock()
+            .unwrap()
+            .queue
+            .pop_front()
+            .ok_or(mpsc::RecvError)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.chan.state.lock().unwrap();
+        state.receiver_alive = false;
+        state.queue.clear();
+    }
+}
+
+/// 可以参与 select 的接收端
+pub(crate) trait Selectable {
+    /// 有消息或已断开
+    fn is_ready(&self) -> bool;
+    fn watch(&self, signal: &Arc<Signal>);
+    fn unwatch(&self, signal: &Arc<Signal>);
+}
+
+impl<T> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.chan.state.lock().unwrap().is_ready()
+    }
+
+    fn watch(&self, signal: &Arc<Signal>) {
+        self.chan
+            .state
+            .lock()
+            .unwrap()
+            .watchers
+            .push(Arc::clone(signal));
+    }
+
+    fn unwatch(&self, signal: &Arc<Signal>) {
+        self.chan
+            .state
+            .lock()
+            .unwrap()
+            .watchers
+            .retain(|s| !Arc::ptr_eq(s, signal));
+    }
+}
+
+impl<T: Selectable + ?Sized> Selectable for &T {
+    fn is_ready(&self) -> bool {
+        (**self).is_ready()
+    }
+
+    fn watch(&self, signal: &Arc<Signal>) {
+        (**self).watch(signal)
+    }
+
+    fn unwatch(&self, signal: &Arc<Signal>) {
+        (**self).unwatch(signal)
+    }
+}
+
+/// 一次 select 的唤醒信号，发送端 send 或断开时触发
+pub(crate) struct Signal {
+    fired: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl Signal {
+    fn fire(&self) {
+        *self.fired.lock().unwrap() = true;
+        self.cond.notify_one();
+    }
+}
+
+/// select 最多等待多久
+pub(crate) enum Wait {
+    /// default 分支：只检查一次
+    Never,
+    /// timeout 分支
+    Until(Instant),
+    Forever,
+}
+
+/// 返回就绪的接收端下标；超时或 `Wait::Never` 时没有就绪的接收端返回 None
+///
+/// 先登记信号再检查一次是否就绪，之后的 send 一定会触发信号，不会错过唤醒。
+pub(crate) fn select_ready(
+    receivers: &[&dyn Selectable],
+    biased: bool,
+    wait: Wait,
+) -> Option<usize> {
+    // 公平模式在所有就绪的接收端中等概率选择；从随机位置开始顺序查找的话，
+    // 紧跟在未就绪接收端后面的那个会被多选
+    let first_ready = || {
+        let mut ready = (0..receivers.len()).filter(|&i| receivers[i].is_ready());
+        if biased {
+            return ready.next();
+        }
+        let ready: Vec<usize> = ready.collect();
+        (!ready.is_empty()).then(|| ready[random_index(ready.len())])
+    };
+
+    if let Some(index) = first_ready() {
+        return Some(index);
+    }
+    if let Wait::Never = wait {
+        return None;
+    }
+
+    let signal = Arc::new(Signal {
+        fired: Mutex::new(false),
+        cond: Condvar::new(),
+    });
+    for receiver in receivers {
+        receiver.watch(&signal);
+    }
+
+    let ready = loop {
+        if let Some(index) = first_ready() {
+            break Some(index);
+        }
+
+        let mut fired = signal.fired.lock().unwrap();
+        while !*fired {
+            fired = match wait {
+                Wait::Until(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    signal.cond.wait_timeout(fired, deadline - now).unwrap().0
+                }
+                _ => signal.cond.wait(fired).unwrap(),
+            };
+        }
+        if !*fired {
+            // 超时前最后再看一次
+            break first_ready();
+        }
+        *fired = false;
+    };
+
+    for receiver in receivers {
+        receiver.unwatch(&signal);
+    }
+    ready
+}
+
+/// select! 展开后按顺序调用：轮到下标 0 的分支返回 true，否则减一
+pub(crate) fn take_turn(remaining: &mut usize) -> bool {
+    if *remaining == 0 {
+        true
+    } else {
+        *remaining -= 1;
+        false
+    }
+}
+
+/// 不引入 rand：用标准库 HashMap 的随机种子生成 [0, n) 的随机下标
+fn random_index(n: usize) -> usize {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    (RandomState::new().build_hasher().finish() % n as u64) as usize
+}
+
 /// # 通道选择
+///
+/// 对应 go/04_select_statement.go：同时等待多个通道，谁先就绪处理谁。
+/// std 的 mpsc 没有办法同时等待多个接收端，这里用上面的 `channel()`，
+/// 它的接口和 mpsc 一样，但接收端可以放进 `select!`。
 pub fn channel_select_demo() {
-    println!("\n=== 通道选择 (使用 recv_timeout) ===");
+    println!("\n=== 通道选择 (select!) ===");
 
-    let (tx1, rx1) = mpsc::channel();
-    let (tx2, rx2) = mpsc::channel();
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
 
     thread::spawn(move || {
         thread::sleep(Duration::from_millis(100));
@@ -405,24 +778,47 @@ pub fn channel_select_demo() {
         tx2.send(String::from("通道2")).unwrap();
     });
 
-    // 轮询两个通道
+    // 等待第一个就绪的通道，没有轮询
+    select! {
+        recv(rx1) -> msg => println!("从通道1收到: {}", msg.unwrap()),
+        recv(rx2) -> msg => println!("从通道2收到: {}", msg.unwrap()),
+    }
+
+    // 超时分支
+    select! {
+        recv(rx2) -> msg => println!("从通道2收到: {}", msg.unwrap()),
+        timeout(Duration::from_millis(10)) => println!("10ms 内没有消息"),
+    }
+
+    // default 分支：不阻塞
+    let (_tx, idle) = channel::<i32>();
+    select! {
+        recv(idle) -> msg => println!("收到: {:?}", msg),
+        default => println!("没有就绪的通道，立即返回"),
+    }
+
+    // 循环接收，直到收到完成信号
+    let (tx, numbers) = channel();
+    let (done_tx, done) = channel();
+    thread::spawn(move || {
+        for i in 1..=3 {
+            tx.send(i).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        done_tx.send(()).unwrap();
+    });
     loop {
-        match rx1.recv_timeout(Duration::from_millis(50)) {
-            Ok(val) => {
-                println!("从通道1收到: {}", val);
+        // 多个通道同时就绪时 select! 随机选择，select_biased! 按书写顺序优先，
+        // 这里让完成信号优先，发送端随后关闭时也不会走到 Err 分支
+        select_biased! {
+            recv(done) -> _ => {
+                println!("  完成");
                 break;
-            }
-            Err(mpsc::RecvTimeoutError::Timeout) => {
-                match rx2.recv_timeout(Duration::from_millis(50)) {
-                    Ok(val) => {
-                        println!("从通道2收到: {}", val);
-                        break;
-                    }
-                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
-                    Err(_) => break,
-                }
-            }
-            Err(_) => break,
+            },
+            recv(numbers) -> msg => match msg {
+                Ok(n) => println!("  收到数字 {}", n),
+                Err(_) => break,
+            },
         }
     }
 }
@@ -435,6 +831,7 @@ pub fn channel_best_practices_demo() {
     println!("   - mpsc::channel: 异步，无界");
     println!("   - mpsc::sync_channel: 同步，有界");
     println!("   - 考虑使用 crossbeam 或 flume");
+    println!("   - 同时等待多个通道: channel() + select!");
 
     println!("\n2. 资源管理:");
     println!("   - 及时 drop 不需要的发送者/接收者");
@@ -501,4 +898,163 @@ mod tests {
         values.sort();
         assert_eq!(values, vec![1, 2]);
     }
+
+    /// 从 send 到 select 返回的时间：没有轮询，应该远小于旧实现 50ms 的轮询间隔
+    #[test]
+    fn test_select_wakeup_latency() {
+        let mut worst = Duration::ZERO;
+        for _ in 0..20 {
+            let (_idle_tx, idle) = channel::<Instant>();
+            let (tx, rx) = channel();
+            let sender = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(5));
+                tx.send(Instant::now()).unwrap();
+            });
+
+            let sent_at = select! {
+                recv(idle) -> _ => unreachable!(),
+                recv(rx) -> msg => msg.unwrap(),
+            };
+            worst = worst.max(sent_at.elapsed());
+            sender.join().unwrap();
+        }
+        assert!(worst < Duration::from_millis(25), "唤醒延迟 {:?}", worst);
+    }
+
+    #[test]
+    fn test_select_timeout() {
+        let (_tx, rx) = channel::<i32>();
+        let start = Instant::now();
+        let timed_out = select! {
+            recv(rx) -> _ => false,
+            timeout(Duration::from_millis(50)) => true,
+        };
+        let elapsed = start.elapsed();
+        assert!(timed_out);
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(
+            elapsed < Duration::from_millis(150),
+            "超时用了 {:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_select_default_and_disconnect() {
+        let (tx, rx) = channel();
+        let got = select! {
+            recv(rx) -> msg => msg.ok(),
+            default => None,
+        };
+        assert_eq!(got, None);
+
+        tx.send(1).unwrap();
+        let got = select! {
+            recv(rx) -> msg => msg.ok(),
+            default => None,
+        };
+        assert_eq!(got, Some(1));
+
+        // 发送端全部关闭也算就绪，分支收到 Err
+        drop(tx);
+        let start = Instant::now();
+        let disconnected = select! {
+            recv(rx) -> msg => msg.is_err(),
+            timeout(Duration::from_secs(5)) => false,
+        };
+        assert!(disconnected);
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_select_evaluates_receivers_once() {
+        let (tx1, rx1) = channel();
+        let (_tx2, rx2) = channel::<i32>();
+        let receivers = [rx1, rx2];
+        tx1.send(7).unwrap();
+
+        // 下标表达式有副作用：每个 recv(...) 只求值一次，按书写顺序
+        let mut evaluated = Vec::new();
+        let got = select_biased! {
+            recv(receivers[{ evaluated.push(0); 0 }]) -> msg => msg.ok(),
+            recv(receivers[{ evaluated.push(1); 1 }]) -> msg => msg.ok(),
+            timeout(Duration::from_secs(5)) => None,
+        };
+        assert_eq!(got, Some(7));
+        assert_eq!(evaluated, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_select_fair_and_biased() {
+        let (tx1, rx1) = channel();
+        let (tx2, rx2) = channel();
+        for _ in 0..1000 {
+            tx1.send(()).unwrap();
+            tx2.send(()).unwrap();
+        }
+
+        // 两个通道一直都就绪：select! 大致各选一半
+        let mut counts = [0, 0];
+        for _ in 0..1000 {
+            select! {
+                recv(rx1) -> _ => counts[0] += 1,
+                recv(rx2) -> _ => counts[1] += 1,
+            }
+        }
+        assert!(counts[0] > 350 && counts[1] > 350, "{:?}", counts);
+
+        // select_biased! 总是选写在前面的，rx1 取空之前不会轮到 rx2
+        let remaining = 1000 - counts[0];
+        for _ in 0..remaining {
+            select_biased! {
+                recv(rx1) -> _ => {},
+                recv(rx2) -> _ => panic!("rx1 还有消息"),
+            }
+        }
+        assert_eq!(rx1.try_recv(), Err(mpsc::TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_select_fair_among_ready_only() {
+        let (_idle_tx, idle) = channel::<()>();
+        let (tx2, rx2) = channel();
+        let (tx3, rx3) = channel();
+        for _ in 0..3000 {
+            tx2.send(()).unwrap();
+            tx3.send(()).unwrap();
+        }
+
+        // 只有后两个就绪时各占一半，而不是紧跟 idle 的 rx2 占三分之二
+        let mut counts = [0, 0];
+        for _ in 0..3000 {
+            select! {
+                recv(idle) -> _ => unreachable!("idle 没有消息"),
+                recv(rx2) -> _ => counts[0] += 1,
+                recv(rx3) -> _ => counts[1] += 1,
+            }
+        }
+        assert!(counts[0] < 1750 && counts[1] > 1250, "{:?}", counts);
+    }
+
+    #[test]
+    fn test_select_channel_api() {
+        let (tx, rx) = channel();
+        assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Empty));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(mpsc::RecvTimeoutError::Timeout)
+        );
+
+        let tx2 = tx.clone();
+        thread::spawn(move || tx2.send(7).unwrap());
+        assert_eq!(rx.recv(), Ok(7));
+
+        drop(tx);
+        assert_eq!(rx.recv(), Err(mpsc::RecvError));
+        assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send(1), Err(mpsc::SendError(1)));
+    }
 }
@@ -1,8 +1,15 @@
 // 多线程并发
 
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
 use std::thread;
 use std::time::Duration;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, RwLock};
 
 /// # 创建线程
 pub fn creating_threads_demo() {
@@ -175,74 +182,635 @@ pub fn rwlock_demo() {
 pub fn thread_pool_demo() {
     println!("\n=== 线程池示例 ===");
     
-    use std::sync::mpsc;
+    // 4 个 Worker，队列最多 2 个任务，队列满时 execute 阻塞
+    let pool = ThreadPool::with_queue_capacity(4, 2);
     
-    struct ThreadPool {
-        workers: Vec<Worker>,
-        sender: mpsc::Sender<Job>,
+    for i in 0..8 {
+        pool.execute(move || {
+            println!("    任务 {} 执行", i);
+            thread::sleep(Duration::from_millis(100));
+        });
     }
     
-    type Job = Box<dyn FnOnce() + Send + 'static>;
+    // spawn 返回结果句柄；任务 panic 不会带走 Worker
+    let square = pool.spawn(|| 7 * 7);
+    let failed = pool.spawn(|| -> i32 { panic!("任务出错") });
+    println!("  7 * 7 = {}", square.join().unwrap());
+    println!("  panic 的任务: {}", failed.join().unwrap_err());
+    
+    // 离开作用域时等待队列中的任务全部执行完，再回收所有 Worker
+    drop(pool);
+    println!("  线程池已关闭");
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// 线程池和工作窃取调度器的共同接口
+///
+/// 调用方只依赖这个 trait，就可以在 `ThreadPool` 和 `WorkStealingPool` 之间切换。
+pub trait Executor: Send + Sync {
+    /// 提交一个任务
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static;
     
-    struct Worker {
-        id: usize,
-        thread: thread::JoinHandle<()>,
+    /// 提交一个有返回值的任务，返回可以阻塞等待或 `.await` 的句柄
+    fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        spawn_with(f, |job| self.execute(job))
     }
     
-    impl Worker {
-        fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-            let thread = thread::spawn(move || loop {
-                let job = receiver.lock().unwrap().recv();
+    /// Worker 数量
+    fn size(&self) -> usize;
+}
+
+/// 把 f 包装成捕获 panic、写回结果的任务交给 submit，返回结果句柄
+fn spawn_with<F, T>(f: F, submit: impl FnOnce(Job)) -> TaskHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let slot = Arc::new(TaskSlot {
+        state: Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    
+    let completer = Arc::clone(&slot);
+    submit(Box::new(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "未知 panic".to_string());
+            TaskPanicked { message }
+        });
+        completer.complete(result);
+    }));
+    
+    TaskHandle { slot }
+}
+
+/// 每个 Worker 对应的默认队列长度
+const QUEUE_PER_WORKER: usize = 64;
+
+/// 固定大小的线程池
+///
+/// 所有 Worker 共享同一个有界队列，谁先拿到锁谁执行任务。
+/// 队列满时 `execute`/`spawn` 阻塞调用方，形成背压；任务 panic 被捕获，
+/// Worker 继续处理后面的任务。Drop 时关闭队列，等已提交的任务全部执行完
+/// 再 join 每个 Worker。
+///
+/// `network::tcp_server::TcpServer` 使用它作为连接处理的执行器。
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    /// Drop 时先取出并丢弃，Worker 的 recv 才会返回错误
+    sender: Option<mpsc::SyncSender<Job>>,
+}
+
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        let thread = thread::Builder::new()
+            .name(format!("pool-worker-{}", id))
+            .spawn(move || loop {
+                // 锁只在取任务时持有；即使锁中毒，队列本身仍然可用
+                let job = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
                 
                 match job {
                     Ok(job) => {
-                        println!("  Worker {} 执行任务", id);
-                        job();
-                    }
-                    Err(_) => {
-                        println!("  Worker {} 断开连接", id);
-                        break;
+                        // panic 信息已经由 panic hook 打印，这里只保证 Worker 存活
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
                     }
+                    Err(_) => break,
                 }
-            });
-            
-            Worker { id, thread }
+            })
+            .expect("创建 Worker 线程失败");
+        
+        Worker {
+            id,
+            thread: Some(thread),
         }
     }
+}
+
+impl ThreadPool {
+    /// 创建包含 `size` 个 Worker 的线程池，队列长度为 `size * 64`
+    ///
+    /// # Panics
+    ///
+    /// `size` 为 0 时 panic
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_queue_capacity(size, size * QUEUE_PER_WORKER)
+    }
     
-    impl ThreadPool {
-        fn new(size: usize) -> ThreadPool {
-            let (sender, receiver) = mpsc::channel();
-            let receiver = Arc::new(Mutex::new(receiver));
-            
-            let mut workers = Vec::with_capacity(size);
-            
-            for id in 0..size {
-                workers.push(Worker::new(id, Arc::clone(&receiver)));
+    /// 指定队列长度；为 0 时每个任务都要等到有空闲 Worker 接手才返回
+    ///
+    /// # Panics
+    ///
+    /// `size` 为 0 时 panic
+    pub fn with_queue_capacity(size: usize, capacity: usize) -> ThreadPool {
+        assert!(size > 0, "线程池大小必须大于 0");
+        
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        
+        let mut workers = Vec::with_capacity(size);
+        
+        for id in 0..size {
+            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        }
+        
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+    
+    /// 提交一个任务，队列满时阻塞
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+        self.sender
+            .as_ref()
+            .expect("线程池已关闭")
+            .send(job)
+            .expect("Worker 不会在线程池关闭前退出");
+    }
+    
+    /// 提交一个有返回值的任务，返回可以阻塞等待或 `.await` 的句柄
+    pub fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        spawn_with(f, |job| self.execute(job))
+    }
+    
+    /// Worker 数量
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+    
+    /// 提交一个任务，队列满时不阻塞，直接返回 `QueueFull` 并丢弃任务
+    ///
+    /// 适合不能被阻塞的调用方，例如需要及时响应关闭请求的 accept 循环。
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFull>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+        match self.sender.as_ref().expect("线程池已关闭").try_send(job) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(_)) => Err(QueueFull),
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                unreachable!("Worker 不会在线程池关闭前退出")
             }
-            
-            ThreadPool { workers, sender }
         }
+    }
+}
+
+/// `try_execute` 时队列已满
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "线程池队列已满")
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+impl Executor for ThreadPool {
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        ThreadPool::execute(self, f)
+    }
+    
+    fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        ThreadPool::spawn(self, f)
+    }
+    
+    fn size(&self) -> usize {
+        ThreadPool::size(self)
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // 关闭队列：Worker 取完剩余任务后 recv 返回 Err 并退出
+        drop(self.sender.take());
         
-        fn execute<F>(&self, f: F)
-        where
-            F: FnOnce() + Send + 'static,
-        {
-            let job = Box::new(f);
-            self.sender.send(job).unwrap();
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    eprintln!("Worker {} 异常退出", worker.id);
+                }
+            }
+        }
+    }
+}
+
+/// 任务 panic 时句柄返回的错误
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskPanicked {
+    pub message: String,
+}
+
+impl std::fmt::Display for TaskPanicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "任务 panic: {}", self.message)
+    }
+}
+
+impl std::error::Error for TaskPanicked {}
+
+struct TaskState<T> {
+    result: Option<Result<T, TaskPanicked>>,
+    waker: Option<Waker>,
+}
+
+/// Worker 写入结果，句柄读取；同时支持阻塞等待（Condvar）和异步等待（Waker）
+struct TaskSlot<T> {
+    state: Mutex<TaskState<T>>,
+    done: Condvar,
+}
+
+impl<T> TaskSlot<T> {
+    fn complete(&self, result: Result<T, TaskPanicked>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// `ThreadPool::spawn` 返回的结果句柄，类似 `thread::JoinHandle`
+///
+/// 可以在普通线程里调用 `join` 阻塞等待，也可以在异步代码里 `.await`。
+/// 丢弃句柄不会取消任务。
+pub struct TaskHandle<T> {
+    slot: Arc<TaskSlot<T>>,
+}
+
+impl<T> TaskHandle<T> {
+    /// 阻塞直到任务完成
+    pub fn join(self) -> Result<T, TaskPanicked> {
+        let mut state = self.slot.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.slot.done.wait(state).unwrap();
         }
     }
     
-    let pool = ThreadPool::new(4);
+    /// 任务是否已经完成
+    pub fn is_finished(&self) -> bool {
+        self.slot.state.lock().unwrap().result.is_some()
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, TaskPanicked>;
     
-    for i in 0..8 {
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.slot.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// # 工作窃取调度器
+pub fn work_stealing_demo() {
+    println!("\n=== 工作窃取调度器 ===");
+    
+    let pool = WorkStealingPool::new(4);
+    let sum = Arc::new(AtomicU64::new(0));
+    
+    // 任务里再提交的任务进入当前 Worker 的本地队列，空闲的 Worker 会来偷
+    for chunk in 0..4u64 {
+        let sum = Arc::clone(&sum);
+        let inner = pool.handle();
         pool.execute(move || {
-            println!("    任务 {} 执行", i);
-            thread::sleep(Duration::from_millis(100));
+            for i in chunk * 250..(chunk + 1) * 250 {
+                let sum = Arc::clone(&sum);
+                inner.execute(move || {
+                    sum.fetch_add(i, Ordering::Relaxed);
+                });
+            }
         });
     }
     
-    thread::sleep(Duration::from_secs(1));
+    drop(pool);
+    println!("  0..1000 的和: {}", sum.load(Ordering::Relaxed));
+    
+    println!("  和 ThreadPool 实现同一个 Executor trait，调用方可以直接替换");
+}
+
+/// 用来区分不同的 WorkStealingPool，判断当前线程是不是本池的 Worker
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// 当前线程所属的 (池 id, Worker 下标)
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// 从全局队列一次搬到本地队列的最大任务数
+const INJECTOR_BATCH: usize = 32;
+
+struct StealingShared {
+    id: usize,
+    /// 外部线程提交的任务；关闭标志也在这把锁下设置
+    injector: Mutex<VecDeque<Job>>,
+    /// 每个 Worker 的本地队列：自己从尾部取（LIFO，缓存友好），别人从头部偷
+    locals: Vec<Mutex<VecDeque<Job>>>,
+    /// 已入队但还没被取走的任务数；入队后才加一，可能短暂为负
+    pending: AtomicIsize,
+    /// 没活干、已经 park 的 Worker
+    parked: Mutex<Vec<thread::Thread>>,
+    /// 准备 park 或已经 park 的 Worker 数，没人睡时不碰 parked 锁
+    sleeping: AtomicUsize,
+    shutdown: AtomicBool,
+}
+
+impl StealingShared {
+    /// # Panics
+    ///
+    /// 线程池开始关闭后从外部提交时 panic：Worker 可能已经退出，任务不会被执行
+    fn push(&self, job: Job) {
+        let local = CURRENT_WORKER
+            .with(|w| w.get())
+            .filter(|(pool, _)| *pool == self.id)
+            .map(|(_, index)| index);
+        match local {
+            // 当前 Worker 取完本地队列才会退出，关闭期间提交的子任务也会执行
+            Some(index) => {
+                self.locals[index].lock().unwrap().push_back(job);
+                self.pending.fetch_add(1, Ordering::SeqCst);
+            }
+            None => {
+                // 和 Drop 设置关闭标志在同一把锁下：要么任务在关闭前入队，
+                // Worker 退出前会把它取走，要么这里看到关闭标志
+                let accepted = {
+                    let mut injector = self.injector.lock().unwrap();
+                    let open = !self.shutdown.load(Ordering::SeqCst);
+                    if open {
+                        injector.push_back(job);
+                        self.pending.fetch_add(1, Ordering::SeqCst);
+                    }
+                    open
+                };
+                assert!(accepted, "线程池已关闭，任务不会被执行");
+            }
+        }
+        
+        // 上面先加 pending 再读 sleeping，Worker 先加 sleeping 再读 pending，
+        // 两边至少有一方能看到对方，不会丢失唤醒。有 Worker 在睡就叫醒一个，
+        // 否则其他 Worker 忙着的时候新任务只能等在队列里
+        self.wake_one(self.sleeping.load(Ordering::SeqCst));
+    }
+    
+    /// 只有 Worker 在睡时才去拿 parked 锁，都在忙时提交任务不碰它
+    fn wake_one(&self, sleeping: usize) {
+        if sleeping > 0 {
+            if let Some(thread) = self.parked.lock().unwrap().pop() {
+                thread.unpark();
+            }
+        }
+    }
+    
+    /// 依次尝试：本地队列尾部、全局队列、其他 Worker 队列的头部
+    fn find_job(&self, index: usize) -> Option<Job> {
+        let job = self.locals[index].lock().unwrap().pop_back();
+        let job = job
+            .or_else(|| self.take_from_injector(index))
+            .or_else(|| self.steal(index));
+        
+        if job.is_some() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        job
+    }
+    
+    /// 取一个任务，并顺手把一批搬到本地队列，减少对全局锁的争用
+    fn take_from_injector(&self, index: usize) -> Option<Job> {
+        let mut injector = self.injector.lock().unwrap();
+        let job = injector.pop_front()?;
+        let batch = injector.len().min(INJECTOR_BATCH);
+        let batch: Vec<Job> = injector.drain(..batch).collect();
+        drop(injector);
+        
+        if !batch.is_empty() {
+            self.locals[index].lock().unwrap().extend(batch);
+            // 本地有了多余的任务，叫醒一个来偷
+            self.wake_one(self.sleeping.load(Ordering::SeqCst));
+        }
+        Some(job)
+    }
+    
+    /// 从其他 Worker 偷一半任务；同一时间只持有一把锁，不会死锁
+    fn steal(&self, index: usize) -> Option<Job> {
+        let n = self.locals.len();
+        for offset in 1..n {
+            let victim = (index + offset) % n;
+            let mut stolen = {
+                let mut queue = self.locals[victim].lock().unwrap();
+                let half = queue.len().div_ceil(2);
+                queue.drain(..half).collect::<VecDeque<Job>>()
+            };
+            
+            if let Some(job) = stolen.pop_front() {
+                if !stolen.is_empty() {
+                    self.locals[index].lock().unwrap().extend(stolen);
+                    self.wake_one(self.sleeping.load(Ordering::SeqCst));
+                }
+                return Some(job);
+            }
+        }
+        None
+    }
+    
+    fn run_worker(&self, index: usize) {
+        CURRENT_WORKER.with(|w| w.set(Some((self.id, index))));
+        
+        loop {
+            if let Some(job) = self.find_job(index) {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                continue;
+            }
+            
+            let shutting_down = {
+                let mut parked = self.parked.lock().unwrap();
+                self.sleeping.fetch_add(1, Ordering::SeqCst);
+                // 任务可能正被别的 Worker 搬运，或刚入队还没计数，回去再找
+                if self.pending.load(Ordering::SeqCst) > 0 {
+                    self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                    drop(parked);
+                    thread::yield_now();
+                    continue;
+                }
+                // 和 Drop 的 unpark 在同一把锁下，登记之后不会错过关闭
+                let shutting_down = self.shutdown.load(Ordering::SeqCst);
+                if shutting_down {
+                    self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                } else {
+                    parked.push(thread::current());
+                }
+                shutting_down
+            };
+            if shutting_down {
+                // 看到关闭标志时，关闭前提交成功的任务都已在全局队列里，
+                // 但上面读 pending 可能早于入队，退出前再取一遍
+                match self.find_job(index) {
+                    Some(job) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            thread::park();
+            // 可能是虚假唤醒，把自己从名单里移除，下次睡之前重新登记
+            let me = thread::current().id();
+            self.parked.lock().unwrap().retain(|t| t.id() != me);
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// 工作窃取调度器
+///
+/// `ThreadPool` 的所有 Worker 在同一把锁上取任务，任务很小时这把锁就是瓶颈。
+/// 这里每个 Worker 有自己的队列：外部提交的任务进入全局队列，Worker 成批搬到
+/// 本地；任务里再提交的任务直接进入当前 Worker 的本地队列。本地队列空了就去
+/// 偷别人的，都没有任务时 park，有新任务时被 unpark。
+///
+/// 队列没有上限，任务里可以放心地提交新任务（`ThreadPool` 的有界队列在所有
+/// Worker 都阻塞在提交上时会死锁）。Drop 时等所有任务执行完再 join。
+pub struct WorkStealingPool {
+    shared: Arc<StealingShared>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl WorkStealingPool {
+    /// # Panics
+    ///
+    /// `size` 为 0 时 panic
+    pub fn new(size: usize) -> WorkStealingPool {
+        assert!(size > 0, "线程池大小必须大于 0");
+        
+        let shared = Arc::new(StealingShared {
+            id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            injector: Mutex::new(VecDeque::new()),
+            locals: (0..size).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: AtomicIsize::new(0),
+            parked: Mutex::new(Vec::new()),
+            sleeping: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        
+        let threads = (0..size)
+            .map(|index| {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(format!("stealing-worker-{}", index))
+                    .spawn(move || shared.run_worker(index))
+                    .expect("创建 Worker 线程失败")
+            })
+            .collect();
+        
+        WorkStealingPool { shared, threads }
+    }
+    
+    /// 可以移进任务里的提交句柄，不持有 Worker 线程
+    pub fn handle(&self) -> StealingHandle {
+        StealingHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Executor for WorkStealingPool {
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(Box::new(f));
+    }
+    
+    fn size(&self) -> usize {
+        self.threads.len()
+    }
+}
+
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        {
+            let _injector = self.shared.injector.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+        }
+        for thread in self.shared.parked.lock().unwrap().drain(..) {
+            thread.unpark();
+        }
+        
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                eprintln!("Worker 异常退出");
+            }
+        }
+    }
+}
+
+/// `WorkStealingPool::handle` 返回的提交句柄
+///
+/// 线程池开始 Drop 之后，只有池内任务还能通过它提交；从外部提交会 panic，
+/// 而不是让任务静默丢失、`spawn` 的句柄永远等不到结果。
+#[derive(Clone)]
+pub struct StealingHandle {
+    shared: Arc<StealingShared>,
+}
+
+impl Executor for StealingHandle {
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(Box::new(f));
+    }
+    
+    fn size(&self) -> usize {
+        self.shared.locals.len()
+    }
 }
 
 /// # 线程本地存储
@@ -370,6 +938,7 @@ pub fn run_all() {
     arc_demo();
     rwlock_demo();
     thread_pool_demo();
+    work_stealing_demo();
     thread_local_demo();
     parallel_iteration_demo();
     deadlock_prevention_demo();
@@ -399,4 +968,322 @@ mod tests {
         }
         assert_eq!(*m.lock().unwrap(), 6);
     }
+    
+    #[test]
+    fn test_pool_drop_runs_queued_jobs_then_joins() {
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        let marker = Arc::new(());
+        
+        let pool = ThreadPool::new(2);
+        for i in 0..10 {
+            let finished = Arc::clone(&finished);
+            let marker = Arc::clone(&marker);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(10));
+                finished.lock().unwrap().push(i);
+                drop(marker);
+            });
+        }
+        drop(pool);
+        
+        // Drop 返回时所有任务都已执行完，闭包也已释放
+        let mut finished = finished.lock().unwrap().clone();
+        finished.sort();
+        assert_eq!(finished, (0..10).collect::<Vec<_>>());
+        assert_eq!(Arc::strong_count(&marker), 1);
+    }
+    
+    #[test]
+    fn test_pool_survives_panicking_jobs() {
+        let pool = ThreadPool::new(1);
+        
+        pool.execute(|| panic!("execute 中的 panic"));
+        let failed = pool.spawn(|| -> u32 { panic!("第 {} 个任务失败", 2) });
+        let ok = pool.spawn(|| 40 + 2);
+        
+        assert_eq!(
+            failed.join(),
+            Err(TaskPanicked {
+                message: "第 2 个任务失败".to_string()
+            })
+        );
+        // 唯一的 Worker 还活着
+        assert_eq!(ok.join(), Ok(42));
+        assert_eq!(pool.spawn(|| "still alive").join(), Ok("still alive"));
+    }
+    
+    #[test]
+    fn test_pool_backpressure() {
+        let pool = Arc::new(ThreadPool::with_queue_capacity(1, 1));
+        let (release, gate) = mpsc::channel::<()>();
+        
+        // 第一个任务占住唯一的 Worker，第二个填满队列
+        pool.execute(move || gate.recv().unwrap());
+        pool.execute(|| {});
+        
+        let (submitted, done) = mpsc::channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                pool.execute(|| {});
+                submitted.send(()).unwrap();
+            })
+        };
+        
+        assert!(done.recv_timeout(Duration::from_millis(100)).is_err());
+        release.send(()).unwrap();
+        done.recv_timeout(Duration::from_secs(5)).unwrap();
+        submitter.join().unwrap();
+    }
+    
+    #[test]
+    fn test_pool_try_execute_rejects_when_full() {
+        let pool = ThreadPool::with_queue_capacity(1, 1);
+        let (release, gate) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        
+        pool.execute(move || {
+            started.send(()).unwrap();
+            gate.recv().unwrap();
+        });
+        running.recv().unwrap();
+        
+        let ran = Arc::new(AtomicUsize::new(0));
+        let submit = |n| {
+            let counter = Arc::clone(&ran);
+            pool.try_execute(move || {
+                counter.fetch_add(n, Ordering::SeqCst);
+            })
+        };
+        assert_eq!(submit(1), Ok(()));
+        // 队列已满，立即返回而不是阻塞
+        assert_eq!(submit(10), Err(QueueFull));
+        
+        release.send(()).unwrap();
+        drop(pool);
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+    
+    #[test]
+    fn test_task_handle_is_a_future() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..4u64)
+            .map(|i| {
+                pool.spawn(move || {
+                    thread::sleep(Duration::from_millis(20));
+                    i * i
+                })
+            })
+            .collect();
+        
+        let results = futures::executor::block_on(futures::future::join_all(handles));
+        let results: Vec<u64> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, vec![0, 1, 4, 9]);
+    }
+    
+    #[test]
+    fn test_work_stealing_runs_nested_jobs_before_drop() {
+        let pool = WorkStealingPool::new(4);
+        let count = Arc::new(AtomicUsize::new(0));
+        
+        for _ in 0..8 {
+            let count = Arc::clone(&count);
+            let inner = pool.handle();
+            pool.execute(move || {
+                for _ in 0..100 {
+                    let count = Arc::clone(&count);
+                    inner.execute(move || {
+                        count.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+        }
+        let answer = pool.spawn(|| 6 * 7);
+        assert_eq!(answer.join(), Ok(42));
+        
+        drop(pool);
+        assert_eq!(count.load(Ordering::SeqCst), 800);
+    }
+    
+    #[test]
+    fn test_work_stealing_idle_worker_steals() {
+        let pool = WorkStealingPool::new(2);
+        let workers = Arc::new(Mutex::new(std::collections::HashSet::new()));
+        
+        // 所有子任务都进入第一个 Worker 的本地队列，另一个只能靠偷
+        let inner = pool.handle();
+        let seen = Arc::clone(&workers);
+        pool.execute(move || {
+            for _ in 0..50 {
+                let seen = Arc::clone(&seen);
+                inner.execute(move || {
+                    thread::sleep(Duration::from_millis(2));
+                    let name = thread::current().name().unwrap().to_string();
+                    seen.lock().unwrap().insert(name);
+                });
+            }
+        });
+        
+        drop(pool);
+        assert_eq!(workers.lock().unwrap().len(), 2);
+    }
+    
+    #[test]
+    fn test_work_stealing_wakes_idle_worker_while_one_is_busy() {
+        let pool = WorkStealingPool::new(4);
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            thread::sleep(Duration::from_millis(500));
+        });
+        running.recv().unwrap();
+        // 让其他 Worker 都进入 park
+        thread::sleep(Duration::from_millis(50));
+        
+        let submitted = std::time::Instant::now();
+        let latency = pool.spawn(move || submitted.elapsed());
+        assert!(latency.join().unwrap() < Duration::from_millis(200));
+    }
+    
+    #[test]
+    #[should_panic(expected = "线程池已关闭")]
+    fn test_stealing_handle_rejects_jobs_after_drop() {
+        let pool = WorkStealingPool::new(2);
+        let handle = pool.handle();
+        drop(pool);
+        
+        handle.execute(|| {});
+    }
+    
+    #[test]
+    fn test_work_stealing_push_skips_parked_lock_when_busy() {
+        let pool = WorkStealingPool::new(2);
+        let (started, running) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for _ in 0..2 {
+            let started = started.clone();
+            let released = Arc::clone(&released);
+            pool.execute(move || {
+                started.send(()).unwrap();
+                let _ = released.lock().unwrap().recv();
+            });
+        }
+        running.recv().unwrap();
+        running.recv().unwrap();
+        
+        // 两个 Worker 都在忙，没人睡：外部提交只碰全局队列的锁
+        let count = Arc::new(AtomicUsize::new(0));
+        let (submitted, done) = mpsc::channel();
+        {
+            let _parked = pool.shared.parked.lock().unwrap();
+            let handle = pool.handle();
+            let count = Arc::clone(&count);
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    let count = Arc::clone(&count);
+                    handle.execute(move || {
+                        count.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+                submitted.send(()).unwrap();
+            });
+            assert!(done.recv_timeout(Duration::from_secs(5)).is_ok());
+        }
+        
+        drop(release);
+        drop(pool);
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+    }
+    
+    #[test]
+    fn test_work_stealing_runs_every_job_accepted_before_drop() {
+        let pool = WorkStealingPool::new(4);
+        let executed = Arc::new(AtomicUsize::new(0));
+        let accepted = Arc::new(AtomicUsize::new(0));
+        
+        // 几个外部线程一直提交，和 Drop 竞争：提交要么成功并被执行，要么 panic
+        let submitters: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = pool.handle();
+                let executed = Arc::clone(&executed);
+                let accepted = Arc::clone(&accepted);
+                thread::spawn(move || loop {
+                    let executed = Arc::clone(&executed);
+                    let submit = panic::catch_unwind(AssertUnwindSafe(|| {
+                        handle.execute(move || {
+                            executed.fetch_add(1, Ordering::SeqCst);
+                        })
+                    }));
+                    if submit.is_err() {
+                        return;
+                    }
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        
+        thread::sleep(Duration::from_millis(20));
+        drop(pool);
+        for submitter in submitters {
+            submitter.join().unwrap();
+        }
+        assert!(accepted.load(Ordering::SeqCst) > 0);
+        assert_eq!(
+            executed.load(Ordering::SeqCst),
+            accepted.load(Ordering::SeqCst)
+        );
+    }
+    
+    #[test]
+    fn test_work_stealing_survives_panics() {
+        let pool = WorkStealingPool::new(1);
+        pool.execute(|| panic!("任务 panic"));
+        
+        let failed = pool.spawn(|| -> u8 { panic!("spawn 中的 panic") });
+        assert_eq!(failed.join().unwrap_err().message, "spawn 中的 panic");
+        assert_eq!(pool.spawn(|| 1).join(), Ok(1));
+    }
+    
+    /// 调用方只依赖 Executor，提交大量极小的任务，Drop 时等全部完成
+    fn run_tiny_jobs<E: Executor>(executor: E, jobs: usize) -> Duration {
+        let count = Arc::new(AtomicUsize::new(0));
+        let started = std::time::Instant::now();
+        
+        for _ in 0..jobs {
+            let count = Arc::clone(&count);
+            executor.execute(move || {
+                count.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        drop(executor);
+        
+        let elapsed = started.elapsed();
+        assert_eq!(count.load(Ordering::Relaxed), jobs);
+        elapsed
+    }
+    
+    #[test]
+    fn test_executors_complete_tiny_jobs() {
+        run_tiny_jobs(ThreadPool::new(4), 10_000);
+        run_tiny_jobs(WorkStealingPool::new(4), 10_000);
+    }
+    
+    /// 基准测试，只打印结果：cargo test test_executor_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn test_executor_throughput() {
+        const JOBS: usize = 200_000;
+        
+        let shared = run_tiny_jobs(ThreadPool::new(4), JOBS);
+        let stealing = run_tiny_jobs(WorkStealingPool::new(4), JOBS);
+        
+        let per_sec = |elapsed: Duration| JOBS as f64 / elapsed.as_secs_f64();
+        println!(
+            "{} 个小任务: ThreadPool {:.0}/s, WorkStealingPool {:.0}/s",
+            JOBS,
+            per_sec(shared),
+            per_sec(stealing)
+        );
+    }
 }
@@ -3,6 +3,76 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::io;
+use std::time::SystemTime;
+
+/// 文件或目录的元数据摘要
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub name: String,
+    pub len: u64,
+    pub is_dir: bool,
+    /// 路径本身是符号链接，其余字段描述的是链接指向的目标
+    pub is_symlink: bool,
+    pub modified: Option<SystemTime>,
+}
+
+/// 读取元数据（跟随符号链接）
+pub fn file_info(path: impl AsRef<Path>) -> io::Result<FileInfo> {
+    let path = path.as_ref();
+    let is_symlink = fs::symlink_metadata(path)?.file_type().is_symlink();
+    let metadata = fs::metadata(path)?;
+    
+    Ok(FileInfo {
+        path: path.to_path_buf(),
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        len: metadata.len(),
+        is_dir: metadata.is_dir(),
+        is_symlink,
+        modified: metadata.modified().ok(),
+    })
+}
+
+/// 递归遍历目录树，对每个条目调用 visit(条目, 深度)
+///
+/// 根目录深度为 0，只下探到 max_depth 层；同一目录下的条目按名称排序。
+/// 指向目录的符号链接会被访问但不会进入，链接成环时也不会无限递归
+pub fn walk_dir(
+    path: impl AsRef<Path>,
+    max_depth: usize,
+    visit: &mut dyn FnMut(&FileInfo, usize),
+) -> io::Result<()> {
+    fn walk(
+        info: &FileInfo,
+        depth: usize,
+        max_depth: usize,
+        visit: &mut dyn FnMut(&FileInfo, usize),
+    ) -> io::Result<()> {
+        visit(info, depth);
+        if !info.is_dir || info.is_symlink || depth == max_depth {
+            return Ok(());
+        }
+        
+        let mut children = Vec::new();
+        for entry in fs::read_dir(&info.path)? {
+            // 遍历期间被删除或无法读取的条目跳过
+            if let Ok(child) = file_info(entry?.path()) {
+                children.push(child);
+            }
+        }
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+        
+        for child in &children {
+            walk(child, depth + 1, max_depth, visit)?;
+        }
+        Ok(())
+    }
+    
+    walk(&file_info(path)?, 0, max_depth, visit)
+}
 
 /// # 读取目录
 pub fn read_directory_demo() {
@@ -241,24 +311,14 @@ pub fn walk_directory_tree_demo() {
     fs::write(format!("{}/dir1/subdir1/file3.txt", test_dir), "").ok();
     
     // 递归遍历
-    fn walk_dir(path: &Path, level: usize) -> io::Result<()> {
+    let _ = walk_dir(test_dir, usize::MAX, &mut |info, level| {
         let indent = "  ".repeat(level);
-        
-        if path.is_dir() {
-            println!("{}目录: {:?}", indent, path.file_name().unwrap());
-            
-            for entry in fs::read_dir(path)? {
-                let entry = entry?;
-                walk_dir(&entry.path(), level + 1)?;
-            }
+        if info.is_dir {
+            println!("{}目录: {:?}", indent, info.name);
         } else {
-            println!("{}文件: {:?}", indent, path.file_name().unwrap());
+            println!("{}文件: {:?}", indent, info.name);
         }
-        
-        Ok(())
-    }
-    
-    let _ = walk_dir(Path::new(test_dir), 0);
+    });
     
     // 清理
     let _ = fs::remove_dir_all(test_dir);
@@ -390,6 +450,46 @@ mod tests {
         assert!(!Path::new(dir).exists());
     }
     
+    #[test]
+    fn test_walk_dir() {
+        let dir = std::env::temp_dir().join(format!("test_walk_dir_{}", std::process::id()));
+        fs::create_dir_all(dir.join("b/nested")).unwrap();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+        fs::write(dir.join("b/c.txt"), "").unwrap();
+        
+        let mut seen = Vec::new();
+        walk_dir(&dir, usize::MAX, &mut |info, depth| seen.push((info.name.clone(), depth))).unwrap();
+        let names: Vec<(&str, usize)> = seen[1..].iter().map(|(n, d)| (n.as_str(), *d)).collect();
+        assert_eq!(names, vec![("a.txt", 1), ("b", 1), ("c.txt", 2), ("nested", 2)]);
+        
+        let mut top = Vec::new();
+        walk_dir(&dir, 1, &mut |info, depth| {
+            if depth == 1 {
+                top.push((info.name.clone(), info.len, info.is_dir));
+            }
+        })
+        .unwrap();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0], ("a.txt".to_string(), 3, false));
+        assert!(top[1].2);
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_dir_symlink_cycle() {
+        let dir = std::env::temp_dir().join(format!("test_walk_cycle_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+        
+        let mut seen = Vec::new();
+        walk_dir(&dir, usize::MAX, &mut |info, depth| seen.push((info.name.clone(), depth, info.is_symlink))).unwrap();
+        assert_eq!(seen[1..], [("sub".to_string(), 1, false), ("loop".to_string(), 2, true)]);
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
     #[test]
     fn test_path_operations() {
         let path = Path::new("/tmp/test.txt");
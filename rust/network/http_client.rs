@@ -3,10 +3,13 @@
 // reqwest 是 Rust 中最流行的 HTTP 客户端库
 // 本教程涵盖所有核心功能和实战技巧
 
-use reqwest::{Client, Response, StatusCode};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -547,6 +550,21 @@ async fn demo_client_config() -> Result<(), Box<dyn std::error::Error>> {
     println!("  最多跟随 10 次重定向");
     println!();
     
+    // Cookie 持久化
+    println!("Cookie 持久化:");
+    
+    let cookie_file = std::env::temp_dir().join("reqwest_cookies.json");
+    let jar = Arc::new(CookieJar::load(&cookie_file)?);
+    
+    let client = Client::builder()
+        .cookie_provider(Arc::clone(&jar))
+        .build()?;
+    
+    jar.save(&cookie_file)?;
+    println!("  从 {:?} 加载了 {} 个 Cookie", cookie_file, jar.all().len());
+    println!("  响应的 Set-Cookie 自动存入 jar，jar.save() 写回文件");
+    println!();
+    
     // 连接池
     println!("连接池配置:");
     
@@ -619,6 +637,19 @@ async fn demo_error_handling() -> Result<(), Box<dyn std::error::Error>> {
     }
     println!();
     
+    // 重试与退避
+    println!("重试与退避:");
+    
+    let policy = RetryPolicy::default().base_delay(Duration::from_millis(200));
+    let outcome = policy.send(client.get("https://httpbin.org/status/503")).await;
+    for attempt in &outcome.attempts {
+        println!(
+            "  第 {} 次: {:?}，耗时 {:?}，退避 {:?}",
+            attempt.number, attempt.outcome, attempt.elapsed, attempt.backoff
+        );
+    }
+    println!();
+    
     // 超时错误
     println!("超时处理:");
     
@@ -745,83 +776,119 @@ async fn demo_real_world_examples() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 // 案例 1: REST API 客户端
-async fn rest_api_client_example() -> Result<(), Box<dyn std::error::Error>> {
-    #[derive(Debug, Deserialize)]
-    struct ApiUser {
-        id: u32,
-        name: String,
-        email: String,
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ApiUser {
+    id: u32,
+    name: String,
+    email: String,
+}
+
+/// 封装 base URL、认证、重试和熔断的 API 客户端
+struct ApiClient {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    retry: RetryPolicy,
+    breaker: Option<CircuitBreaker>,
+}
+
+impl ApiClient {
+    fn new(base_url: String, api_key: String) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap();
+        
+        Self {
+            client,
+            base_url,
+            api_key,
+            retry: RetryPolicy::default(),
+            breaker: None,
+        }
     }
     
-    struct ApiClient {
-        client: Client,
-        base_url: String,
-        api_key: String,
+    fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
     }
     
-    impl ApiClient {
-        fn new(base_url: String, api_key: String) -> Self {
-            let client = Client::builder()
-                .timeout(Duration::from_secs(30))
-                .build()
-                .unwrap();
-            
-            Self {
-                client,
-                base_url,
-                api_key,
-            }
-        }
+    /// 熔断器可以在多个客户端之间共享
+    fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+    
+    /// 经过熔断器和重试策略发送请求
+    ///
+    /// 熔断器只看重试用尽后的最终结果，一次调用最多计一次失败。
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, Box<dyn std::error::Error>> {
+        let permit = match &self.breaker {
+            Some(breaker) => Some(breaker.acquire()?),
+            None => None,
+        };
         
-        async fn get_user(&self, id: u32) -> Result<ApiUser, Box<dyn std::error::Error>> {
-            let url = format!("{}/users/{}", self.base_url, id);
-            
-            let user = self.client
-                .get(&url)
-                .header("Authorization", format!("Bearer {}", self.api_key))
-                .send()
-                .await?
-                .error_for_status()?
-                .json::<ApiUser>()
-                .await?;
-            
-            Ok(user)
+        let result = self.retry.send(request).await.result;
+        if let Some(permit) = permit {
+            permit.record(!is_dependency_failure(&result));
         }
         
-        async fn create_post(&self, title: &str, body: &str) -> Result<(), Box<dyn std::error::Error>> {
-            #[derive(Serialize)]
-            struct NewPost<'a> {
-                title: &'a str,
-                body: &'a str,
-                #[serde(rename = "userId")]
-                user_id: u32,
-            }
-            
-            let url = format!("{}/posts", self.base_url);
-            
-            let post = NewPost {
-                title,
-                body,
-                user_id: 1,
-            };
-            
-            self.client
-                .post(&url)
-                .header("Authorization", format!("Bearer {}", self.api_key))
-                .json(&post)
-                .send()
-                .await?
-                .error_for_status()?;
-            
-            Ok(())
+        Ok(result?)
+    }
+    
+    async fn get_user(&self, id: u32) -> Result<ApiUser, Box<dyn std::error::Error>> {
+        let url = format!("{}/users/{}", self.base_url, id);
+        
+        let request = self.client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key));
+        let user = self
+            .execute(request)
+            .await?
+            .error_for_status()?
+            .json::<ApiUser>()
+            .await?;
+        
+        Ok(user)
+    }
+    
+    async fn create_post(&self, title: &str, body: &str) -> Result<(), Box<dyn std::error::Error>> {
+        #[derive(Serialize)]
+        struct NewPost<'a> {
+            title: &'a str,
+            body: &'a str,
+            #[serde(rename = "userId")]
+            user_id: u32,
         }
+        
+        let url = format!("{}/posts", self.base_url);
+        
+        let post = NewPost {
+            title,
+            body,
+            user_id: 1,
+        };
+        
+        let request = self.client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&post);
+        self.execute(request).await?.error_for_status()?;
+        
+        Ok(())
     }
+}
+
+async fn rest_api_client_example() -> Result<(), Box<dyn std::error::Error>> {
+    // 使用 API 客户端（依赖故障时熔断，避免持续打到下游）
+    let breaker = CircuitBreaker::new(CircuitBreakerConfig::default());
+    breaker.on_state_change(|from, to| println!("  熔断器: {:?} -> {:?}", from, to));
     
-    // 使用 API 客户端
     let api = ApiClient::new(
         "https://jsonplaceholder.typicode.com".to_string(),
         "fake-api-key".to_string(),
-    );
+    )
+    .with_circuit_breaker(breaker);
     
     // 获取用户
     match api.get_user(1).await {
@@ -840,6 +907,25 @@ async fn rest_api_client_example() -> Result<(), Box<dyn std::error::Error>> {
         Err(e) => println!("  ✗ 创建失败: {}", e),
     }
     
+    // 类型化客户端：端点在第 16 节声明，对应 http_server 的 complete_api
+    let users = RestClient::new("http://localhost:3000").bearer_auth("fake-api-key");
+    let created = users
+        .send(&CreateUser(NewUser {
+            name: "Alice".to_string(),
+            email: "alice@example.com".to_string(),
+        }))
+        .await;
+    match created {
+        Ok(user) => {
+            println!("  ✓ 创建用户 {}: {}", user.id, user.name);
+            if let Ok(page) = users.send(&ListUsers::default()).await {
+                println!("  共 {} 个用户", page.total);
+            }
+        }
+        Err(RestError::Api { code, message, .. }) => println!("  ✗ API 错误 {}: {}", code, message),
+        Err(e) => println!("  ✗ 请求失败（complete_api 没有运行？）: {}", e),
+    }
+    
     Ok(())
 }
 
@@ -869,24 +955,32 @@ async fn web_scraper_example() -> Result<(), Box<dyn std::error::Error>> {
 
 // 案例 3: 文件下载器
 async fn file_downloader_example() -> Result<(), Box<dyn std::error::Error>> {
-    let client = Client::new();
+    let dir = std::env::temp_dir().join("reqwest_downloads");
+    std::fs::create_dir_all(&dir)?;
     
     // 模拟批量下载
-    let urls = vec![
-        "https://httpbin.org/image/png",
-        "https://httpbin.org/image/jpeg",
+    let jobs = vec![
+        DownloadJob::new("https://httpbin.org/image/png", dir.join("image.png")),
+        DownloadJob::new("https://httpbin.org/image/jpeg", dir.join("image.jpeg")),
     ];
     
-    println!("  开始下载 {} 个文件...", urls.len());
+    println!("  开始下载 {} 个文件...", jobs.len());
     
-    for (i, url) in urls.iter().enumerate() {
-        match client.get(*url).send().await {
-            Ok(response) => {
-                if let Ok(bytes) = response.bytes().await {
-                    println!("    ✓ 文件 {}: {} 字节", i + 1, bytes.len());
-                }
+    let downloader = Downloader::new(Client::new())
+        .concurrency(2)
+        .on_progress(|p| {
+            if Some(p.downloaded) == p.total {
+                println!("    文件 {}: {} 字节已写入", p.index + 1, p.downloaded);
+            }
+        });
+    
+    for (i, result) in downloader.download_all(&jobs).await.iter().enumerate() {
+        match result {
+            Ok(report) => {
+                println!("    ✓ 文件 {}: {:?} sha256={}", i + 1, report.dest, &report.sha256[..16]);
             }
             Err(e) => {
+                // .part 文件保留下来，再次运行会从断点继续
                 println!("    ✗ 文件 {} 下载失败: {}", i + 1, e);
             }
         }
@@ -897,64 +991,2830 @@ async fn file_downloader_example() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-/*
-=== 总结 ===
+// ============================================
+// 13. 重试与退避
+// ============================================
 
-1. Reqwest 核心概念:
+/// 单次尝试的结果
+#[derive(Debug, Clone, PartialEq)]
+enum AttemptOutcome {
+    /// 收到了响应（不论状态码）
+    Status(StatusCode),
+    /// 请求没有拿到响应，如连接失败、超时
+    Error(String),
+}
 
-   基础请求:
-   - GET, POST, PUT, DELETE
-   - 请求构建器模式
-   - 查询参数
-   
-   数据格式:
-   - JSON 序列化/反序列化
-   - 表单提交
-   - Multipart 上传
-   
-   高级功能:
-   - 自定义 Headers
-   - Cookie 处理
-   - 超时和重试
-   - 连接池
+/// 每次尝试的记录
+#[derive(Debug, Clone)]
+struct Attempt {
+    number: u32,
+    outcome: AttemptOutcome,
+    elapsed: Duration,
+    /// 下一次重试前等待的时间；None 表示没有再重试
+    backoff: Option<Duration>,
+}
 
-2. 客户端配置:
+/// 判断一次尝试的结果是否值得重试
+type RetryPredicate = Arc<dyn Fn(&Result<Response, reqwest::Error>) -> bool + Send + Sync>;
 
-   基础:
-   - timeout() - 总超时
-   - connect_timeout() - 连接超时
-   - user_agent() - User-Agent
-   
-   高级:
-   - default_headers() - 默认请求头
-   - pool_max_idle_per_host() - 连接池
-   - redirect() - 重定向策略
+/// 把 [0, 1) 中的随机数映射到退避时间，测试时可以替换成固定值
+type JitterSource = Arc<dyn Fn() -> f64 + Send + Sync>;
 
-3. 最佳实践:
+/// 指数退避 + full jitter 的重试策略
+///
+/// 第 n 次重试前等待 random(0, min(max_delay, base_delay * 2^(n-1)))；
+/// 响应带 Retry-After 时按服务器要求等待，超过 max_delay 则不再重试。
+///
+/// POST/PATCH 这类非幂等请求可能已经被服务器执行，重发会产生重复数据，
+/// 默认只在连接没有建立（`is_connect()`）时重试，见 `retry_non_idempotent`。
+#[derive(Clone)]
+struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    retry_on: Vec<RetryPredicate>,
+    jitter: JitterSource,
+    retry_non_idempotent: bool,
+}
 
-   DO:
-   ✓ 复用 Client 实例
-   ✓ 设置合理的超时
-   ✓ 使用 error_for_status()
-   ✓ 并发请求时使用 tokio::spawn
-   
-   DON'T:
-   ✗ 为每个请求创建新 Client
-   ✗ 忽略错误处理
-   ✗ 阻塞异步任务
+impl Default for RetryPolicy {
+    /// 3 次尝试，重试连接错误、超时、5xx 和 429
+    fn default() -> Self {
+        RetryPolicy::new(3)
+            .retry_on(retry_on_connect_error)
+            .retry_on(retry_on_server_error)
+            .retry_on(retry_on_too_many_requests)
+    }
+}
 
-4. 常用模式:
+impl RetryPolicy {
+    /// 不带任何重试条件的策略
+    fn new(max_attempts: u32) -> Self {
+        assert!(max_attempts > 0, "至少要尝试一次");
+        
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            retry_on: Vec::new(),
+            jitter: Arc::new(random_unit),
+            retry_non_idempotent: false,
+        }
+    }
+    
+    /// 不重试，只发送一次
+    fn none() -> Self {
+        RetryPolicy::new(1)
+    }
+    
+    fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+    
+    fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+    
+    /// 添加重试条件，任一条件满足即重试
+    fn retry_on<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Result<Response, reqwest::Error>) -> bool + Send + Sync + 'static,
+    {
+        self.retry_on.push(Arc::new(predicate));
+        self
+    }
+    
+    /// 非幂等请求也按 retry_on 的条件重试，只在服务器有去重（如 Idempotency-Key）时使用
+    fn retry_non_idempotent(mut self) -> Self {
+        self.retry_non_idempotent = true;
+        self
+    }
+    
+    fn jitter<F>(mut self, source: F) -> Self
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.jitter = Arc::new(source);
+        self
+    }
+    
+    /// 第 attempt 次尝试失败后的退避时间（attempt 从 1 开始）
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        exp.min(self.max_delay)
+            .mul_f64((self.jitter)().clamp(0.0, 1.0))
+    }
+    
+    /// 发送请求并按策略重试
+    ///
+    /// 请求体是流时无法复制，只会发送一次。
+    /// 重试用尽后返回最后一次的结果，4xx/5xx 响应仍然是 Ok，由调用方决定如何处理。
+    async fn send(&self, request: RequestBuilder) -> RetryOutcome {
+        let idempotent = request
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .is_some_and(|r| is_idempotent(r.method()));
+        let mut attempts = Vec::new();
+        let mut request = Some(request);
+        
+        for number in 1..=self.max_attempts {
+            let current = request.take().expect("每次尝试前都已准备好请求");
+            let is_last = number == self.max_attempts;
+            // 先复制一份留给下一次尝试
+            request = if is_last { None } else { current.try_clone() };
+            
+            let started = Instant::now();
+            let result = current.send().await;
+            let outcome = match &result {
+                Ok(response) => AttemptOutcome::Status(response.status()),
+                Err(e) => AttemptOutcome::Error(e.to_string()),
+            };
+            
+            let backoff = match &request {
+                Some(_) if self.should_retry(&result, idempotent) => {
+                    self.delay_for(&result, number)
+                }
+                _ => None,
+            };
+            attempts.push(Attempt {
+                number,
+                outcome,
+                elapsed: started.elapsed(),
+                backoff,
+            });
+            
+            match backoff {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return RetryOutcome { result, attempts },
+            }
+        }
+        
+        unreachable!("最后一次尝试总会返回")
+    }
+    
+    fn should_retry(&self, result: &Result<Response, reqwest::Error>, idempotent: bool) -> bool {
+        if !idempotent && !self.retry_non_idempotent {
+            // 连接都没建立，请求一定没有到达服务器
+            return matches!(result, Err(e) if e.is_connect());
+        }
+        self.retry_on.iter().any(|p| p(result))
+    }
+    
+    /// Retry-After 优先于计算出的退避时间；要求等待太久时放弃重试
+    fn delay_for(
+        &self,
+        result: &Result<Response, reqwest::Error>,
+        attempt: u32,
+    ) -> Option<Duration> {
+        match result.as_ref().ok().and_then(retry_after) {
+            Some(wait) if wait > self.max_delay => None,
+            Some(wait) => Some(wait),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+}
 
-   API 客户端:
-   - 封装 Client
-   - 统一错误处理
-   - 重试逻辑
-   
-   并发请求:
-   - tokio::spawn
-   - futures::join_all
-   - 控制并发数
+/// 重试的最终结果和每次尝试的记录
+struct RetryOutcome {
+    result: Result<Response, reqwest::Error>,
+    attempts: Vec<Attempt>,
+}
 
-运行示例:
-  cargo run --bin reqwest_detailed
-*/
+/// RFC 9110 §9.2.2：重复发送和发送一次效果相同的方法
+fn is_idempotent(method: &reqwest::Method) -> bool {
+    use reqwest::Method;
+    
+    [
+        Method::GET,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::PUT,
+        Method::DELETE,
+    ]
+    .contains(method)
+}
+
+/// 连接失败或超时
+fn retry_on_connect_error(result: &Result<Response, reqwest::Error>) -> bool {
+    matches!(result, Err(e) if e.is_connect() || e.is_timeout())
+}
+
+/// 5xx；501 表示服务器不支持，重试没有意义
+fn retry_on_server_error(result: &Result<Response, reqwest::Error>) -> bool {
+    matches!(result, Ok(r) if r.status().is_server_error() && r.status() != StatusCode::NOT_IMPLEMENTED)
+}
+
+fn retry_on_too_many_requests(result: &Result<Response, reqwest::Error>) -> bool {
+    matches!(result, Ok(r) if r.status() == StatusCode::TOO_MANY_REQUESTS)
+}
+
+/// 解析 Retry-After：秒数或 HTTP 日期
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// 不引入 rand：用标准库 HashMap 的随机种子生成 [0, 1) 的随机数
+fn random_unit() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+// ============================================
+// 14. 熔断器
+// ============================================
+
+/// 熔断器对外可见的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CircuitState {
+    /// 正常放行，统计失败率
+    Closed,
+    /// 直接拒绝，等待冷却
+    Open,
+    /// 冷却结束，放行少量探测请求
+    HalfOpen,
+}
+
+#[derive(Debug, Clone)]
+struct CircuitBreakerConfig {
+    /// 统计失败率的滚动窗口
+    window: Duration,
+    /// 窗口内至少有这么多次调用才计算失败率
+    minimum_calls: usize,
+    /// 失败率达到该值（0.0 - 1.0）时熔断
+    failure_rate_threshold: f64,
+    /// 熔断后等待多久再探测
+    cool_down: Duration,
+    /// 半开状态下放行的探测请求数，全部成功才恢复
+    half_open_probes: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            window: Duration::from_secs(10),
+            minimum_calls: 5,
+            failure_rate_threshold: 0.5,
+            cool_down: Duration::from_secs(30),
+            half_open_probes: 1,
+        }
+    }
+}
+
+/// 熔断器内部状态
+///
+/// 和 enums::state_machine_demo 一样，每个转换消费旧状态并返回新状态，
+/// 只在对应状态下才有的数据放在变体里。
+#[derive(Debug)]
+enum Circuit {
+    Closed { calls: CallWindow },
+    Open { until: Instant },
+    HalfOpen { in_flight: u32, successes: u32 },
+}
+
+impl Circuit {
+    fn closed() -> Circuit {
+        Circuit::Closed {
+            calls: CallWindow::default(),
+        }
+    }
+    
+    fn state(&self) -> CircuitState {
+        match self {
+            Circuit::Closed { .. } => CircuitState::Closed,
+            Circuit::Open { .. } => CircuitState::Open,
+            Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+    
+    /// 冷却时间到了就进入半开
+    fn tick(self, now: Instant) -> Circuit {
+        match self {
+            Circuit::Open { until } if now >= until => Circuit::HalfOpen {
+                in_flight: 0,
+                successes: 0,
+            },
+            other => other,
+        }
+    }
+    
+    fn record(self, success: bool, now: Instant, config: &CircuitBreakerConfig) -> Circuit {
+        match self {
+            Circuit::Closed { mut calls } => {
+                let (total, failures) = calls.record(success, now, config.window);
+                let rate = failures as f64 / total as f64;
+                if total as usize >= config.minimum_calls && rate >= config.failure_rate_threshold {
+                    Circuit::Open {
+                        until: now + config.cool_down,
+                    }
+                } else {
+                    Circuit::Closed { calls }
+                }
+            }
+            Circuit::HalfOpen {
+                in_flight,
+                successes,
+            } if success => {
+                if successes + 1 >= config.half_open_probes {
+                    Circuit::closed()
+                } else {
+                    Circuit::HalfOpen {
+                        in_flight: in_flight - 1,
+                        successes: successes + 1,
+                    }
+                }
+            }
+            Circuit::HalfOpen { .. } => Circuit::Open {
+                until: now + config.cool_down,
+            },
+            open @ Circuit::Open { .. } => open,
+        }
+    }
+}
+
+/// 滚动窗口分成的时间桶数
+const WINDOW_BUCKETS: usize = 10;
+
+/// 滚动窗口内的调用统计
+///
+/// 窗口分成 `WINDOW_BUCKETS` 个固定时间桶，每个桶只存计数，内存占用和 QPS 无关；
+/// 代价是窗口按桶的粒度滑动，最旧的一个桶可能只剩部分时间。
+#[derive(Debug, Default)]
+struct CallWindow {
+    /// 第一次记录的时间，桶序号从这里算起
+    origin: Option<Instant>,
+    buckets: [Bucket; WINDOW_BUCKETS],
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Bucket {
+    /// 桶覆盖的时间段序号，和当前序号相差 WINDOW_BUCKETS 以上的桶已经过期
+    index: u64,
+    calls: u32,
+    failures: u32,
+}
+
+impl CallWindow {
+    /// 记录一次调用，返回窗口内的 (调用数, 失败数)
+    fn record(&mut self, success: bool, now: Instant, window: Duration) -> (u32, u32) {
+        let origin = *self.origin.get_or_insert(now);
+        let width = (window / WINDOW_BUCKETS as u32).max(Duration::from_millis(1));
+        let current = (now.saturating_duration_since(origin).as_nanos() / width.as_nanos()) as u64;
+        
+        let bucket = &mut self.buckets[current as usize % WINDOW_BUCKETS];
+        if bucket.index != current {
+            *bucket = Bucket {
+                index: current,
+                ..Bucket::default()
+            };
+        }
+        bucket.calls += 1;
+        bucket.failures += u32::from(!success);
+        
+        self.buckets
+            .iter()
+            .filter(|b| current.saturating_sub(b.index) < WINDOW_BUCKETS as u64)
+            .fold((0, 0), |(calls, failures), b| (calls + b.calls, failures + b.failures))
+    }
+}
+
+/// 熔断期间的调用被拒绝
+#[derive(Debug, Clone, PartialEq)]
+struct CircuitOpenError {
+    /// 距离下一次允许探测的时间
+    retry_in: Duration,
+}
+
+impl std::fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "熔断器已打开，{:?} 后重试", self.retry_in)
+    }
+}
+
+impl std::error::Error for CircuitOpenError {}
+
+/// 状态变化回调：(旧状态, 新状态)
+type StateListener = Arc<dyn Fn(CircuitState, CircuitState) + Send + Sync>;
+
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+struct BreakerInner {
+    circuit: Option<Circuit>,
+    /// 每次状态变化加一，用来丢弃旧状态下发出的许可
+    generation: u64,
+}
+
+/// 熔断器，可以在多个 ApiClient 或任务之间共享
+///
+/// ```ignore
+/// let permit = breaker.acquire()?;
+/// let result = do_request().await;
+/// permit.record(result.is_ok());
+/// ```
+#[derive(Clone)]
+struct CircuitBreaker {
+    inner: Arc<Mutex<BreakerInner>>,
+    config: Arc<CircuitBreakerConfig>,
+    listeners: Arc<Mutex<Vec<StateListener>>>,
+    clock: Clock,
+}
+
+impl CircuitBreaker {
+    fn new(config: CircuitBreakerConfig) -> Self {
+        // 没有探测名额时半开状态拒绝所有调用，永远无法恢复
+        assert!(config.half_open_probes > 0, "half_open_probes 必须大于 0");
+        
+        CircuitBreaker {
+            inner: Arc::new(Mutex::new(BreakerInner {
+                circuit: Some(Circuit::closed()),
+                generation: 0,
+            })),
+            config: Arc::new(config),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+            clock: Arc::new(Instant::now),
+        }
+    }
+    
+    fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> Instant + Send + Sync + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+    
+    /// 注册状态变化回调，回调在锁外执行
+    fn on_state_change<F>(&self, listener: F)
+    where
+        F: Fn(CircuitState, CircuitState) + Send + Sync + 'static,
+    {
+        self.listeners.lock().unwrap().push(Arc::new(listener));
+    }
+    
+    fn state(&self) -> CircuitState {
+        self.transition(None, |circuit, _| circuit).1
+    }
+    
+    /// 申请一次调用许可；熔断期间或半开探测名额用完时返回错误
+    fn acquire(&self) -> Result<CallPermit, CircuitOpenError> {
+        let probes = self.config.half_open_probes;
+        let mut rejected = None;
+        
+        let (generation, _) = self.transition(None, |circuit, now| match circuit {
+            Circuit::Open { until } => {
+                rejected = Some(until.saturating_duration_since(now));
+                circuit
+            }
+            Circuit::HalfOpen {
+                in_flight,
+                successes,
+            } if in_flight + successes >= probes => {
+                rejected = Some(Duration::ZERO);
+                circuit
+            }
+            Circuit::HalfOpen {
+                in_flight,
+                successes,
+            } => Circuit::HalfOpen {
+                in_flight: in_flight + 1,
+                successes,
+            },
+            closed => closed,
+        });
+        
+        match rejected {
+            Some(retry_in) => Err(CircuitOpenError { retry_in }),
+            None => Ok(CallPermit {
+                breaker: self.clone(),
+                generation,
+                done: false,
+            }),
+        }
+    }
+    
+    fn record(&self, generation: u64, success: bool) {
+        let config = Arc::clone(&self.config);
+        self.transition(Some(generation), |circuit, now| {
+            circuit.record(success, now, &config)
+        });
+    }
+    
+    /// 许可没有报告结果就被丢弃时，归还半开状态的探测名额
+    fn release(&self, generation: u64) {
+        self.transition(Some(generation), |circuit, _| match circuit {
+            Circuit::HalfOpen {
+                in_flight,
+                successes,
+            } => Circuit::HalfOpen {
+                in_flight: in_flight - 1,
+                successes,
+            },
+            other => other,
+        });
+    }
+    
+    /// 在锁内推进状态机，然后在锁外通知状态变化
+    ///
+    /// expected 是许可发出时的 generation，不一致说明状态已经变过，结果作废。
+    fn transition<F>(&self, expected: Option<u64>, f: F) -> (u64, CircuitState)
+    where
+        F: FnOnce(Circuit, Instant) -> Circuit,
+    {
+        let now = (self.clock)();
+        let mut changes = Vec::new();
+        
+        let (generation, state) = {
+            let mut inner = self.inner.lock().unwrap();
+            let mut circuit = inner.circuit.take().expect("状态总是存在");
+            
+            let before = circuit.state();
+            circuit = circuit.tick(now);
+            if circuit.state() != before {
+                inner.generation += 1;
+                changes.push((before, circuit.state()));
+            }
+            
+            if expected.is_none_or(|g| g == inner.generation) {
+                let before = circuit.state();
+                circuit = f(circuit, now);
+                if circuit.state() != before {
+                    inner.generation += 1;
+                    changes.push((before, circuit.state()));
+                }
+            }
+            
+            let state = circuit.state();
+            inner.circuit = Some(circuit);
+            (inner.generation, state)
+        };
+        
+        if !changes.is_empty() {
+            let listeners = self.listeners.lock().unwrap().clone();
+            for (from, to) in changes {
+                for listener in &listeners {
+                    listener(from, to);
+                }
+            }
+        }
+        (generation, state)
+    }
+}
+
+/// 一次调用的许可，用 record 报告结果；直接丢弃视为调用被取消，不计入统计
+struct CallPermit {
+    breaker: CircuitBreaker,
+    generation: u64,
+    done: bool,
+}
+
+impl CallPermit {
+    fn record(mut self, success: bool) {
+        self.done = true;
+        self.breaker.record(self.generation, success);
+    }
+}
+
+impl Drop for CallPermit {
+    fn drop(&mut self) {
+        if !self.done {
+            self.breaker.release(self.generation);
+        }
+    }
+}
+
+/// 依赖不健康的信号：没有拿到响应、5xx 或 429；其余 4xx 是调用方的问题
+fn is_dependency_failure(result: &Result<Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(response) => {
+            response.status().is_server_error()
+                || response.status() == StatusCode::TOO_MANY_REQUESTS
+        }
+        Err(_) => true,
+    }
+}
+
+// ============================================
+// 15. 断点续传下载器
+// ============================================
+
+/// 一个下载任务
+#[derive(Debug, Clone)]
+struct DownloadJob {
+    url: String,
+    dest: PathBuf,
+    /// 期望的 SHA-256（十六进制），提供时下载完成后校验
+    sha256: Option<String>,
+}
+
+impl DownloadJob {
+    fn new(url: impl Into<String>, dest: impl Into<PathBuf>) -> Self {
+        DownloadJob {
+            url: url.into(),
+            dest: dest.into(),
+            sha256: None,
+        }
+    }
+    
+    fn sha256(mut self, hex: impl Into<String>) -> Self {
+        self.sha256 = Some(hex.into());
+        self
+    }
+    
+    /// 下载中的数据先写到 dest.part，校验通过后再改名
+    fn part_path(&self) -> PathBuf {
+        let mut name = self.dest.as_os_str().to_owned();
+        name.push(".part");
+        PathBuf::from(name)
+    }
+    
+    /// .part 对应的远端版本（ETag 或 Last-Modified），续传时放进 If-Range
+    fn validator_path(&self) -> PathBuf {
+        let mut name = self.dest.as_os_str().to_owned();
+        name.push(".part.validator");
+        PathBuf::from(name)
+    }
+}
+
+/// 单个文件的下载进度
+#[derive(Debug, Clone, PartialEq)]
+struct DownloadProgress {
+    /// 任务在 download_all 参数中的下标
+    index: usize,
+    /// 已写入磁盘的字节数，包含续传前已有的部分
+    downloaded: u64,
+    /// 文件总大小；服务器没有告知时为 None
+    total: Option<u64>,
+}
+
+type ProgressCallback = Arc<dyn Fn(&DownloadProgress) + Send + Sync>;
+
+#[derive(Debug)]
+enum DownloadError {
+    Request(reqwest::Error),
+    Io(std::io::Error),
+    /// 服务器返回了无法处理的状态码
+    Status(StatusCode),
+    /// 206 响应的 Content-Range 和请求的起点对不上
+    BadRange(String),
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Request(e) => write!(f, "请求失败: {}", e),
+            DownloadError::Io(e) => write!(f, "写入失败: {}", e),
+            DownloadError::Status(status) => write!(f, "服务器返回 {}", status),
+            DownloadError::BadRange(range) => write!(f, "Content-Range 不匹配: {}", range),
+            DownloadError::ChecksumMismatch { expected, actual } => {
+                write!(f, "SHA-256 不匹配: 期望 {}, 实际 {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::Request(e) => Some(e),
+            DownloadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        DownloadError::Request(e)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::Io(e)
+    }
+}
+
+/// 下载完成后的结果
+#[derive(Debug, Clone, PartialEq)]
+struct DownloadReport {
+    dest: PathBuf,
+    bytes: u64,
+    /// 续传的起点；0 表示从头下载
+    resumed_from: u64,
+    sha256: String,
+}
+
+/// 流式写盘、支持断点续传的并发下载器
+///
+/// 数据边下载边写入 dest.part，中断后再次下载同一个任务时
+/// 用 Range: bytes=N- 从已有的位置继续；服务器不支持 Range
+/// （返回 200）时从头下载。
+///
+/// 开始下载时把响应的 ETag（强校验）或 Last-Modified 存在 dest.part.validator，
+/// 续传时通过 If-Range 带上：远端文件变了服务器返回完整的 200，不会把新内容
+/// 拼到旧数据后面。没有保存版本的 .part 无法判断是否过期，直接从头下载。
+struct Downloader {
+    client: Client,
+    concurrency: usize,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl Downloader {
+    fn new(client: Client) -> Self {
+        Downloader {
+            client,
+            concurrency: 4,
+            on_progress: None,
+        }
+    }
+    
+    /// 同时进行的下载数上限
+    fn concurrency(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "并发数至少为 1");
+        self.concurrency = limit;
+        self
+    }
+    
+    /// 每写入一块数据回调一次
+    fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&DownloadProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+    
+    /// 并发下载所有任务，结果顺序和 jobs 一致
+    async fn download_all(
+        &self,
+        jobs: &[DownloadJob],
+    ) -> Vec<Result<DownloadReport, DownloadError>> {
+        use futures::future::join_all;
+        
+        let semaphore = tokio::sync::Semaphore::new(self.concurrency);
+        let downloads = jobs.iter().enumerate().map(|(index, job)| {
+            let semaphore = &semaphore;
+            async move {
+                let _permit = semaphore.acquire().await.expect("信号量不会被关闭");
+                self.download(index, job).await
+            }
+        });
+        join_all(downloads).await
+    }
+    
+    async fn download(
+        &self,
+        index: usize,
+        job: &DownloadJob,
+    ) -> Result<DownloadReport, DownloadError> {
+        use reqwest::header::{CONTENT_RANGE, IF_RANGE, RANGE};
+        use sha2::{Digest, Sha256};
+        use tokio::io::AsyncWriteExt;
+        
+        let part = job.part_path();
+        let validator_path = job.validator_path();
+        let validator = read_validator(&validator_path).await?;
+        let mut hasher = Sha256::new();
+        // 已有的部分也要参与校验
+        let existing = match &validator {
+            Some(_) => hash_file(&part, &mut hasher).await?,
+            None => 0,
+        };
+        
+        let mut request = self.client.get(&job.url);
+        if let (Some(validator), true) = (&validator, existing > 0) {
+            request = request
+                .header(RANGE, format!("bytes={}-", existing))
+                .header(IF_RANGE, validator);
+        }
+        let mut response = request.send().await?;
+        let content_range = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        
+        let (resumed_from, total) = match response.status() {
+            StatusCode::PARTIAL_CONTENT if existing > 0 => {
+                let range = content_range.unwrap_or_default();
+                match parse_content_range(&range) {
+                    Some((Some(start), total)) if start == existing => (existing, total),
+                    _ => return Err(DownloadError::BadRange(range)),
+                }
+            }
+            StatusCode::OK => {
+                // 没有请求 Range，或服务器忽略了它
+                hasher = Sha256::new();
+                (0, response.content_length())
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE if existing > 0 => {
+                let range = content_range.unwrap_or_default();
+                match parse_content_range(&range) {
+                    // .part 已经是完整的文件，只差校验和改名
+                    Some((None, Some(total))) if total == existing => (existing, Some(total)),
+                    _ => {
+                        // 远端文件变短了，丢掉本地的部分，下次从头开始
+                        tokio::fs::remove_file(&part).await?;
+                        remove_if_exists(&validator_path).await?;
+                        return Err(DownloadError::Status(response.status()));
+                    }
+                }
+            }
+            status => return Err(DownloadError::Status(status)),
+        };
+        
+        let mut file = if resumed_from > 0 {
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&part)
+                .await?
+        } else {
+            // 先记下新内容的版本再写数据，中断后续传时才能确认远端没变
+            match response_validator(&response) {
+                Some(validator) => tokio::fs::write(&validator_path, validator).await?,
+                None => remove_if_exists(&validator_path).await?,
+            }
+            tokio::fs::File::create(&part).await?
+        };
+        
+        let mut downloaded = resumed_from;
+        self.report(index, downloaded, total);
+        if response.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+                hasher.update(&chunk);
+                downloaded += chunk.len() as u64;
+                self.report(index, downloaded, total);
+            }
+        }
+        file.flush().await?;
+        drop(file);
+        
+        let actual = to_hex(&hasher.finalize());
+        if let Some(expected) = &job.sha256 {
+            if !expected.eq_ignore_ascii_case(&actual) {
+                // 内容不可信，不能留着续传
+                tokio::fs::remove_file(&part).await?;
+                remove_if_exists(&validator_path).await?;
+                return Err(DownloadError::ChecksumMismatch {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        tokio::fs::rename(&part, &job.dest).await?;
+        remove_if_exists(&validator_path).await?;
+        
+        Ok(DownloadReport {
+            dest: job.dest.clone(),
+            bytes: downloaded,
+            resumed_from,
+            sha256: actual,
+        })
+    }
+    
+    fn report(&self, index: usize, downloaded: u64, total: Option<u64>) {
+        if let Some(callback) = &self.on_progress {
+            callback(&DownloadProgress {
+                index,
+                downloaded,
+                total,
+            });
+        }
+    }
+}
+
+/// 把已有文件的内容喂给 hasher，返回字节数；文件不存在时返回 0
+async fn hash_file(path: &std::path::Path, hasher: &mut sha2::Sha256) -> std::io::Result<u64> {
+    use sha2::Digest;
+    use tokio::io::AsyncReadExt;
+    
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    
+    let mut buf = vec![0; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(len);
+        }
+        hasher.update(&buf[..n]);
+        len += n as u64;
+    }
+}
+
+/// 可以放进 If-Range 的版本标识：强 ETag 优先，其次 Last-Modified
+///
+/// 弱 ETag（W/ 开头）不能用于 If-Range，服务器会当作不匹配。
+fn response_validator(response: &Response) -> Option<String> {
+    use reqwest::header::{ETAG, LAST_MODIFIED};
+    
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    header(ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED))
+}
+
+/// 读取保存的版本标识；没有保存时返回 None
+async fn read_validator(path: &std::path::Path) -> std::io::Result<Option<String>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(validator) if !validator.is_empty() => Ok(Some(validator)),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+async fn remove_if_exists(path: &std::path::Path) -> std::io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// 解析 Content-Range: bytes start-end/total
+///
+/// 返回 (start, total)；416 响应的 bytes */total 没有 start，total 为 * 时没有 total。
+fn parse_content_range(value: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    let start = match range {
+        "*" => None,
+        range => Some(range.split_once('-')?.0.parse().ok()?),
+    };
+    Some((start, total))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ============================================
+// 16. 类型化 REST 客户端
+// ============================================
+
+/// 一个 REST 端点的声明
+///
+/// 方法、路径模板和各部分的类型是关联项，实现者只需要给出
+/// 路径参数、查询参数和请求体的值；不需要的部分类型写 ()。
+trait Endpoint {
+    const METHOD: reqwest::Method;
+    /// 路径模板，参数写成 {name}
+    const PATH: &'static str;
+    
+    type Query: Serialize;
+    type Body: Serialize;
+    /// 响应体为空（如 204）时用 ()
+    type Response: DeserializeOwned;
+    
+    fn path_params(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+    
+    fn query(&self) -> Option<&Self::Query> {
+        None
+    }
+    
+    fn body(&self) -> Option<&Self::Body> {
+        None
+    }
+}
+
+/// RestClient 的错误
+#[derive(Debug)]
+enum RestError {
+    /// 请求没有拿到响应
+    Transport(reqwest::Error),
+    /// 服务器按统一格式返回的错误：{ code, message, details, request_id }
+    Api {
+        status: StatusCode,
+        code: String,
+        message: String,
+        details: serde_json::Value,
+        request_id: Option<String>,
+    },
+    /// 非 2xx，响应体不是统一的错误格式
+    Status { status: StatusCode, body: String },
+    /// 2xx，但响应体不能解析成端点声明的类型
+    Decode(serde_json::Error),
+}
+
+impl RestError {
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            RestError::Api { status, .. } | RestError::Status { status, .. } => Some(*status),
+            RestError::Transport(e) => e.status(),
+            RestError::Decode(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestError::Transport(e) => write!(f, "请求失败: {}", e),
+            RestError::Api {
+                status,
+                code,
+                message,
+                ..
+            } => write!(f, "{} {}: {}", status, code, message),
+            RestError::Status { status, body } => write!(f, "服务器返回 {}: {}", status, body),
+            RestError::Decode(e) => write!(f, "响应解析失败: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RestError::Transport(e) => Some(e),
+            RestError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for RestError {
+    fn from(e: reqwest::Error) -> Self {
+        RestError::Transport(e)
+    }
+}
+
+/// 按 Endpoint 声明发送请求的客户端
+///
+/// ```ignore
+/// let api = RestClient::new("http://localhost:3000").bearer_auth("token");
+/// let user: ApiUser = api.send(&GetUser { id: 1 }).await?;
+/// ```
+#[derive(Clone)]
+struct RestClient {
+    client: Client,
+    base_url: String,
+    headers: reqwest::header::HeaderMap,
+}
+
+impl RestClient {
+    fn new(base_url: impl Into<String>) -> Self {
+        RestClient {
+            client: Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            headers: reqwest::header::HeaderMap::new(),
+        }
+    }
+    
+    /// 复用已有的 Client（连接池、超时等配置）
+    fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+    
+    /// 每个请求都带上的请求头
+    fn header(
+        mut self,
+        name: reqwest::header::HeaderName,
+        value: reqwest::header::HeaderValue,
+    ) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+    
+    fn bearer_auth(self, token: &str) -> Self {
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .expect("token 只能包含可见 ASCII 字符");
+        value.set_sensitive(true);
+        self.header(reqwest::header::AUTHORIZATION, value)
+    }
+    
+    fn url<E: Endpoint>(&self, endpoint: &E) -> String {
+        format!(
+            "{}{}",
+            self.base_url,
+            render_path(E::PATH, &endpoint.path_params())
+        )
+    }
+    
+    async fn send<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, RestError> {
+        let mut request = self
+            .client
+            .request(E::METHOD, self.url(endpoint))
+            .headers(self.headers.clone());
+        if let Some(query) = endpoint.query() {
+            request = request.query(query);
+        }
+        if let Some(body) = endpoint.body() {
+            request = request.json(body);
+        }
+        
+        let response = request.send().await?;
+        let status = response.status();
+        let bytes = response.bytes().await?;
+        
+        if !status.is_success() {
+            return Err(api_error(status, &bytes));
+        }
+        let body: &[u8] = if bytes.is_empty() { b"null" } else { &bytes };
+        serde_json::from_slice(body).map_err(RestError::Decode)
+    }
+}
+
+/// 把非 2xx 响应转换成 RestError
+fn api_error(status: StatusCode, body: &[u8]) -> RestError {
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        code: String,
+        message: String,
+        #[serde(default)]
+        details: serde_json::Value,
+        request_id: Option<String>,
+    }
+    
+    match serde_json::from_slice::<ErrorBody>(body) {
+        Ok(error) => RestError::Api {
+            status,
+            code: error.code,
+            message: error.message,
+            details: error.details,
+            request_id: error.request_id,
+        },
+        Err(_) => RestError::Status {
+            status,
+            body: String::from_utf8_lossy(body).into_owned(),
+        },
+    }
+}
+
+/// 用参数填充路径模板，参数值按路径段做百分号编码
+///
+/// 模板里的参数缺失是声明错误，直接 panic。
+fn render_path(template: &str, params: &[(&'static str, String)]) -> String {
+    template
+        .split('/')
+        .map(
+            |segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => {
+                    let (_, value) = params
+                        .iter()
+                        .find(|(param, _)| *param == name)
+                        .unwrap_or_else(|| panic!("{} 缺少路径参数 {}", template, name));
+                    encode_path_segment(value)
+                }
+                None => segment.to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn encode_path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// complete_api（http_server.rs）的用户接口
+
+#[derive(Debug, Clone, Serialize)]
+struct NewUser {
+    name: String,
+    email: String,
+}
+
+/// 只发送设置了的字段
+#[derive(Debug, Clone, Default, Serialize)]
+struct UserPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsersPage {
+    data: Vec<ApiUser>,
+    total: usize,
+    page: u32,
+    limit: u32,
+}
+
+/// GET /users?page=&limit=&sort=&email_contains=
+#[derive(Debug, Default, Serialize)]
+struct ListUsers {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u32>,
+    /// 如 "name,-id"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_contains: Option<String>,
+}
+
+impl Endpoint for ListUsers {
+    const METHOD: reqwest::Method = reqwest::Method::GET;
+    const PATH: &'static str = "/users";
+    type Query = Self;
+    type Body = ();
+    type Response = UsersPage;
+    
+    fn query(&self) -> Option<&Self> {
+        Some(self)
+    }
+}
+
+struct GetUser {
+    id: u32,
+}
+
+impl Endpoint for GetUser {
+    const METHOD: reqwest::Method = reqwest::Method::GET;
+    const PATH: &'static str = "/users/{id}";
+    type Query = ();
+    type Body = ();
+    type Response = ApiUser;
+    
+    fn path_params(&self) -> Vec<(&'static str, String)> {
+        vec![("id", self.id.to_string())]
+    }
+}
+
+struct CreateUser(NewUser);
+
+impl Endpoint for CreateUser {
+    const METHOD: reqwest::Method = reqwest::Method::POST;
+    const PATH: &'static str = "/users";
+    type Query = ();
+    type Body = NewUser;
+    type Response = ApiUser;
+    
+    fn body(&self) -> Option<&NewUser> {
+        Some(&self.0)
+    }
+}
+
+struct UpdateUser {
+    id: u32,
+    patch: UserPatch,
+}
+
+impl Endpoint for UpdateUser {
+    const METHOD: reqwest::Method = reqwest::Method::PUT;
+    const PATH: &'static str = "/users/{id}";
+    type Query = ();
+    type Body = UserPatch;
+    type Response = ApiUser;
+    
+    fn path_params(&self) -> Vec<(&'static str, String)> {
+        vec![("id", self.id.to_string())]
+    }
+    
+    fn body(&self) -> Option<&UserPatch> {
+        Some(&self.patch)
+    }
+}
+
+struct DeleteUser {
+    id: u32,
+}
+
+impl Endpoint for DeleteUser {
+    const METHOD: reqwest::Method = reqwest::Method::DELETE;
+    const PATH: &'static str = "/users/{id}";
+    type Query = ();
+    type Body = ();
+    /// 204 No Content
+    type Response = ();
+    
+    fn path_params(&self) -> Vec<(&'static str, String)> {
+        vec![("id", self.id.to_string())]
+    }
+}
+
+// ============================================
+// 17. Cookie 持久化
+// ============================================
+
+/// Cookie 存储中的一条记录
+///
+/// 时间都存成 Unix 秒，方便序列化成 JSON。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct StoredCookie {
+    name: String,
+    value: String,
+    /// 小写，不带前导点
+    domain: String,
+    /// 没有 Domain 属性时只发回设置它的主机，不包括子域名
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    /// None 表示会话 Cookie
+    expires: Option<i64>,
+    /// 创建顺序，同名 Cookie 更新时保留原值，用于 Cookie 头的排序
+    created: u64,
+}
+
+impl StoredCookie {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|at| at <= now)
+    }
+    
+    /// RFC 6265 5.4：域名、路径、Secure 都匹配且没有过期
+    fn matches(&self, url: &reqwest::Url, now: i64) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        domain_ok
+            && path_match(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired(now)
+    }
+}
+
+/// 按 RFC 6265 匹配域名、路径和过期时间的 Cookie 存储
+///
+/// 实现了 reqwest 的 CookieStore，可以直接交给 Client：
+///
+/// ```ignore
+/// let jar = Arc::new(CookieJar::load("session.json")?);
+/// let client = Client::builder().cookie_provider(Arc::clone(&jar)).build()?;
+/// // ... 登录、请求 ...
+/// jar.save("session.json")?;
+/// ```
+///
+/// 和浏览器不同，会话 Cookie 也会保存，这样脚本化的登录会话在重启后仍然有效。
+#[derive(Debug, Default)]
+struct CookieJar {
+    cookies: Mutex<Vec<StoredCookie>>,
+}
+
+impl CookieJar {
+    fn new() -> Self {
+        CookieJar::default()
+    }
+    
+    /// 从 JSON 文件加载；文件不存在时返回空的存储
+    fn load(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let cookies: Vec<StoredCookie> = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        
+        let now = unix_now();
+        let cookies = cookies.into_iter().filter(|c| !c.is_expired(now)).collect();
+        Ok(CookieJar {
+            cookies: Mutex::new(cookies),
+        })
+    }
+    
+    /// 保存所有没有过期的 Cookie；先写临时文件再改名，写到一半不会破坏旧文件
+    ///
+    /// 文件里有会话令牌，Unix 上只允许所有者读写（0600）。
+    fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let now = unix_now();
+        let cookies: Vec<StoredCookie> = self
+            .cookies
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| !c.is_expired(now))
+            .cloned()
+            .collect();
+        
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        // mode 只在创建时生效，上次留下的临时文件可能权限更宽
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        
+        use std::io::Write;
+        file.write_all(&serde_json::to_vec_pretty(&cookies)?)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp, path)
+    }
+    
+    /// 处理一个 Set-Cookie 头；不合法或域名不匹配的 Cookie 被忽略
+    fn store(&self, set_cookie: &str, url: &reqwest::Url, now: i64) {
+        let Some(mut cookie) = parse_set_cookie(set_cookie, url, now) else {
+            return;
+        };
+        
+        let mut cookies = self.cookies.lock().unwrap();
+        let existing = cookies.iter().position(|c| {
+            c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path
+        });
+        if let Some(index) = existing {
+            cookie.created = cookies.remove(index).created;
+        } else {
+            cookie.created = cookies.iter().map(|c| c.created + 1).max().unwrap_or(0);
+        }
+        
+        // 过期时间在过去就是删除
+        if !cookie.is_expired(now) {
+            cookies.push(cookie);
+        }
+    }
+    
+    /// 要发给 url 的 Cookie，路径长的在前，同样长度按创建顺序
+    fn matching(&self, url: &reqwest::Url, now: i64) -> Vec<StoredCookie> {
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| !c.is_expired(now));
+        
+        let mut matched: Vec<StoredCookie> = cookies
+            .iter()
+            .filter(|c| c.matches(url, now))
+            .cloned()
+            .collect();
+        matched.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created.cmp(&b.created))
+        });
+        matched
+    }
+    
+    /// 当前所有没有过期的 Cookie
+    fn all(&self) -> Vec<StoredCookie> {
+        let now = unix_now();
+        self.cookies
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| !c.is_expired(now))
+            .cloned()
+            .collect()
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(
+        &self,
+        cookie_headers: &mut dyn Iterator<Item = &reqwest::header::HeaderValue>,
+        url: &reqwest::Url,
+    ) {
+        let now = unix_now();
+        for header in cookie_headers {
+            if let Ok(value) = header.to_str() {
+                self.store(value, url, now);
+            }
+        }
+    }
+    
+    fn cookies(&self, url: &reqwest::Url) -> Option<reqwest::header::HeaderValue> {
+        let header = self
+            .matching(url, unix_now())
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        
+        if header.is_empty() {
+            None
+        } else {
+            reqwest::header::HeaderValue::from_str(&header).ok()
+        }
+    }
+}
+
+/// 按 RFC 6265 5.2 解析 Set-Cookie，返回的 created 由调用方填写
+fn parse_set_cookie(header: &str, url: &reqwest::Url, now: i64) -> Option<StoredCookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut parts = header.split(';');
+    
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    
+    let mut cookie = StoredCookie {
+        name: name.to_string(),
+        value: value.trim().to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url.path()),
+        secure: false,
+        http_only: false,
+        expires: None,
+        created: 0,
+    };
+    // Max-Age 优先于 Expires，不论出现的顺序
+    let mut max_age = None;
+    
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), ""),
+        };
+        
+        match key.to_ascii_lowercase().as_str() {
+            "expires" => {
+                if let Some(at) = parse_cookie_date(value) {
+                    cookie.expires = Some(at);
+                }
+            }
+            "max-age" => {
+                if let Ok(seconds) = value.parse::<i64>() {
+                    max_age = Some(now.saturating_add(seconds.max(0)));
+                }
+            }
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                // 只能设置给自己或上级域名
+                if !domain_match(&host, &domain) {
+                    return None;
+                }
+                // RFC 6265 5.3 第 5 步：Domain 是公共后缀时，只有主机名本身等于它
+                // 才接受（当作 host-only），否则就是发给整个 .com 的超级 Cookie
+                if is_public_suffix(&domain) {
+                    if domain != host {
+                        return None;
+                    }
+                    continue;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            _ => {}
+        }
+    }
+    
+    if max_age.is_some() {
+        cookie.expires = max_age;
+    }
+    Some(cookie)
+}
+
+/// 默认路径：请求路径去掉最后一段
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => path[..end].to_string(),
+    }
+}
+
+/// 常见的多级公共后缀
+///
+/// 没有打包完整的 Public Suffix List，只列出最常见的一部分；
+/// 单级域名（com、org、localhost 等）在 `is_public_suffix` 中统一拒绝。
+const PUBLIC_SUFFIXES: &[&str] = &[
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "com.au", "net.au", "org.au", "co.jp", "ne.jp",
+    "or.jp", "com.cn", "net.cn", "org.cn", "gov.cn", "com.hk", "com.tw", "co.kr", "co.nz",
+    "com.br", "co.in", "github.io", "gitlab.io", "herokuapp.com", "appspot.com",
+    "blogspot.com", "pages.dev", "vercel.app", "netlify.app",
+];
+
+/// 不能作为 Cookie Domain 的公共后缀
+fn is_public_suffix(domain: &str) -> bool {
+    !domain.contains('.') || PUBLIC_SUFFIXES.contains(&domain)
+}
+
+/// host 等于 domain，或者是 domain 的子域名（IP 地址只能完全相等）
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    host.parse::<std::net::IpAddr>().is_err()
+        && host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// 请求路径等于 Cookie 路径，或以它为前缀且在 / 处分隔
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Expires 常见的两种格式：RFC 1123 和旧的 Netscape 格式（日期用 - 连接）
+fn parse_cookie_date(value: &str) -> Option<i64> {
+    if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+        return Some(date.timestamp());
+    }
+    chrono::NaiveDateTime::parse_from_str(value, "%a, %d-%b-%Y %H:%M:%S GMT")
+        .ok()
+        .map(|date| date.and_utc().timestamp())
+}
+
+fn unix_now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/*
+=== 总结 ===
+
+1. Reqwest 核心概念:
+
+   基础请求:
+   - GET, POST, PUT, DELETE
+   - 请求构建器模式
+   - 查询参数
+   
+   数据格式:
+   - JSON 序列化/反序列化
+   - 表单提交
+   - Multipart 上传
+   
+   高级功能:
+   - 自定义 Headers
+   - Cookie 处理
+   - 超时和重试
+   - 连接池
+
+2. 客户端配置:
+
+   基础:
+   - timeout() - 总超时
+   - connect_timeout() - 连接超时
+   - user_agent() - User-Agent
+   
+   高级:
+   - default_headers() - 默认请求头
+   - pool_max_idle_per_host() - 连接池
+   - redirect() - 重定向策略
+
+3. 最佳实践:
+
+   DO:
+   ✓ 复用 Client 实例
+   ✓ 设置合理的超时
+   ✓ 使用 error_for_status()
+   ✓ 并发请求时使用 tokio::spawn
+   
+   DON'T:
+   ✗ 为每个请求创建新 Client
+   ✗ 忽略错误处理
+   ✗ 阻塞异步任务
+
+4. 常用模式:
+
+   API 客户端:
+   - 封装 Client
+   - 统一错误处理
+   - 重试逻辑
+
+   Cookie:
+   - CookieJar 实现 reqwest::cookie::CookieStore
+   - 按 RFC 6265 匹配 Domain、Path、Secure 和过期时间
+   - save()/load() 保存到 JSON，会话 Cookie 也保留
+
+   类型化客户端:
+   - 实现 Endpoint 声明方法、路径模板、查询、请求体和响应类型
+   - RestClient::send 统一注入 base URL 和认证头
+   - RestError 区分传输错误、API 错误和解析错误
+
+   重试:
+   - RetryPolicy::default() 重试连接错误、5xx、429
+   - 非幂等请求（POST/PATCH）默认只在连接失败时重试
+   - 指数退避 + full jitter
+   - 遵守 Retry-After，过长则放弃
+   - try_clone() 复制请求，流式请求体只发一次
+
+   熔断:
+   - Closed -> Open: 滚动窗口内失败率超过阈值
+   - Open -> HalfOpen: 冷却结束
+   - HalfOpen -> Closed/Open: 探测全部成功/任一失败
+   - on_state_change 观察状态变化
+
+   下载:
+   - chunk() 流式写入 .part，校验通过再改名
+   - Range: bytes=N- 断点续传，200 时从头下载
+   - 保存 ETag/Last-Modified，续传时用 If-Range 防止拼接新旧内容
+   - Semaphore 限制并发数
+   - 可选 SHA-256 校验，不匹配时删除 .part
+   
+   并发请求:
+   - tokio::spawn
+   - futures::join_all
+   - 控制并发数
+
+运行示例:
+  cargo run --bin reqwest_detailed
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::HeaderMap;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    /// 前 failures 次请求返回 status，之后正常返回用户
+    #[derive(Clone)]
+    struct Flaky {
+        failures: usize,
+        status: axum::http::StatusCode,
+        retry_after: Option<&'static str>,
+        hits: Arc<AtomicUsize>,
+    }
+    
+    async fn flaky_user(
+        State(flaky): State<Flaky>,
+        Path(id): Path<u32>,
+        headers: HeaderMap,
+    ) -> axum::response::Response {
+        let hit = flaky.hits.fetch_add(1, Ordering::SeqCst);
+        if hit < flaky.failures {
+            let mut response = flaky.status.into_response();
+            if let Some(value) = flaky.retry_after {
+                response
+                    .headers_mut()
+                    .insert("retry-after", value.parse().unwrap());
+            }
+            return response;
+        }
+        if headers.get("authorization").map(|v| v.as_bytes()) != Some(b"Bearer test-key") {
+            return axum::http::StatusCode::UNAUTHORIZED.into_response();
+        }
+        
+        Json(serde_json::json!({"id": id, "name": "alice", "email": "alice@example.com"}))
+            .into_response()
+    }
+    
+    async fn start_flaky(
+        failures: usize,
+        status: u16,
+        retry_after: Option<&'static str>,
+    ) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let flaky = Flaky {
+            failures,
+            status: axum::http::StatusCode::from_u16(status).unwrap(),
+            retry_after,
+            hits: Arc::clone(&hits),
+        };
+        let app = Router::new()
+            .route("/users/:id", get(flaky_user).post(flaky_user))
+            .with_state(flaky);
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, hits)
+    }
+    
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(max_attempts)
+            .base_delay(Duration::from_millis(1))
+            .jitter(|| 1.0)
+            .retry_on(retry_on_server_error)
+            .retry_on(retry_on_too_many_requests)
+            .retry_on(retry_on_connect_error)
+    }
+    
+    fn statuses(attempts: &[Attempt]) -> Vec<AttemptOutcome> {
+        attempts.iter().map(|a| a.outcome.clone()).collect()
+    }
+    
+    fn authed(base: &str) -> RequestBuilder {
+        Client::new()
+            .get(format!("{}/users/1", base))
+            .bearer_auth("test-key")
+    }
+    
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let (base, hits) = start_flaky(2, 500, None).await;
+        
+        let outcome = fast_policy(3).send(authed(&base)).await;
+        assert_eq!(outcome.result.unwrap().status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            statuses(&outcome.attempts),
+            vec![
+                AttemptOutcome::Status(StatusCode::INTERNAL_SERVER_ERROR),
+                AttemptOutcome::Status(StatusCode::INTERNAL_SERVER_ERROR),
+                AttemptOutcome::Status(StatusCode::OK),
+            ]
+        );
+        let backoffs: Vec<_> = outcome.attempts.iter().map(|a| a.backoff).collect();
+        assert_eq!(
+            backoffs,
+            vec![
+                Some(Duration::from_millis(1)),
+                Some(Duration::from_millis(2)),
+                None
+            ]
+        );
+        assert_eq!(outcome.attempts[2].number, 3);
+    }
+    
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let (base, hits) = start_flaky(10, 503, None).await;
+        
+        let outcome = fast_policy(3).send(authed(&base)).await;
+        assert_eq!(
+            outcome.result.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert_eq!(outcome.attempts.last().unwrap().backoff, None);
+    }
+    
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let (base, hits) = start_flaky(1, 404, None).await;
+        
+        let outcome = fast_policy(5).send(authed(&base)).await;
+        assert_eq!(outcome.result.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        
+        let (base, hits) = start_flaky(1, 500, None).await;
+        let outcome = RetryPolicy::none().send(authed(&base)).await;
+        assert_eq!(outcome.attempts.len(), 1);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_honors_retry_after() {
+        let (base, hits) = start_flaky(1, 429, Some("1")).await;
+        
+        let started = Instant::now();
+        let outcome = fast_policy(3).send(authed(&base)).await;
+        assert_eq!(outcome.result.unwrap().status(), StatusCode::OK);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(outcome.attempts[0].backoff, Some(Duration::from_secs(1)));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        
+        // 服务器要求的等待超过 max_delay 时直接放弃
+        let (base, hits) = start_flaky(1, 429, Some("120")).await;
+        let outcome = fast_policy(3)
+            .max_delay(Duration::from_secs(5))
+            .send(authed(&base))
+            .await;
+        assert_eq!(
+            outcome.result.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_retries_connect_errors() {
+        // 绑定后立即释放，得到一个没有人监听的端口
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        
+        let outcome = fast_policy(2)
+            .send(Client::new().get(format!("http://{}/", addr)))
+            .await;
+        assert!(outcome.result.unwrap_err().is_connect());
+        assert_eq!(outcome.attempts.len(), 2);
+        assert!(outcome
+            .attempts
+            .iter()
+            .all(|a| matches!(a.outcome, AttemptOutcome::Error(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_post_not_retried_after_reaching_server() {
+        let (base, hits) = start_flaky(2, 500, None).await;
+        let post = || {
+            Client::new()
+                .post(format!("{}/users/1", base))
+                .bearer_auth("test-key")
+        };
+        
+        // 服务器可能已经执行了这个 POST，5xx 不重试
+        let outcome = fast_policy(3).send(post()).await;
+        assert_eq!(
+            outcome.result.unwrap().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        
+        let outcome = fast_policy(3).retry_non_idempotent().send(post()).await;
+        assert_eq!(outcome.result.unwrap().status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        
+        // 连接失败时请求没有发出去，POST 也可以重试
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let outcome = fast_policy(2)
+            .send(Client::new().post(format!("http://{}/", addr)))
+            .await;
+        assert!(outcome.result.unwrap_err().is_connect());
+        assert_eq!(outcome.attempts.len(), 2);
+    }
+    
+    #[test]
+    fn test_backoff_full_jitter() {
+        let policy = RetryPolicy::new(10)
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_secs(1))
+            .jitter(|| 1.0);
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(64), Duration::from_secs(1));
+        
+        let policy = policy.jitter(|| 0.25);
+        assert_eq!(policy.backoff(3), Duration::from_millis(100));
+        
+        // 默认随机源落在 [0, 1)，退避不会超过上限
+        let policy = RetryPolicy::new(10).max_delay(Duration::from_millis(50));
+        for attempt in 1..200 {
+            let unit = random_unit();
+            assert!((0.0..1.0).contains(&unit));
+            assert!(policy.backoff(attempt) <= Duration::from_millis(50));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_api_client_retries_get_user() {
+        let (base, hits) = start_flaky(2, 503, None).await;
+        
+        let api = ApiClient::new(base, "test-key".to_string())
+            .with_retry(RetryPolicy::default().base_delay(Duration::from_millis(1)));
+        let user = api.get_user(7).await.unwrap();
+        assert_eq!(user.id, 7);
+        assert_eq!(user.name, "alice");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+    
+    /// 手动推进的时钟
+    #[derive(Clone)]
+    struct ManualClock(Arc<Mutex<Instant>>);
+    
+    impl ManualClock {
+        fn new() -> Self {
+            ManualClock(Arc::new(Mutex::new(Instant::now())))
+        }
+        
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+        
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+    
+    /// 记录下来的状态变化
+    type StateChanges = Arc<Mutex<Vec<(CircuitState, CircuitState)>>>;
+    
+    fn test_breaker(clock: &ManualClock) -> (CircuitBreaker, StateChanges) {
+        let config = CircuitBreakerConfig {
+            window: Duration::from_secs(10),
+            minimum_calls: 4,
+            failure_rate_threshold: 0.5,
+            cool_down: Duration::from_secs(30),
+            half_open_probes: 2,
+        };
+        let now = clock.clone();
+        let breaker = CircuitBreaker::new(config).with_clock(move || now.now());
+        
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&changes);
+        breaker.on_state_change(move |from, to| log.lock().unwrap().push((from, to)));
+        (breaker, changes)
+    }
+    
+    fn call(breaker: &CircuitBreaker, success: bool) {
+        breaker.acquire().unwrap().record(success);
+    }
+    
+    #[test]
+    fn test_circuit_breaker_state_machine() {
+        use CircuitState::*;
+        
+        let clock = ManualClock::new();
+        let (breaker, changes) = test_breaker(&clock);
+        
+        // 调用数不足 minimum_calls 时不熔断
+        call(&breaker, false);
+        call(&breaker, false);
+        call(&breaker, false);
+        assert_eq!(breaker.state(), Closed);
+        // 第 4 次调用后开始计算失败率：3/4
+        call(&breaker, true);
+        assert_eq!(breaker.state(), Open);
+        
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            breaker.acquire().err(),
+            Some(CircuitOpenError {
+                retry_in: Duration::from_secs(20)
+            })
+        );
+        
+        // 冷却结束进入半开，只放行 2 个探测
+        clock.advance(Duration::from_secs(20));
+        let first = breaker.acquire().unwrap();
+        let second = breaker.acquire().unwrap();
+        assert_eq!(breaker.state(), HalfOpen);
+        assert!(breaker.acquire().is_err());
+        
+        // 丢弃的许可归还名额
+        drop(second);
+        let second = breaker.acquire().unwrap();
+        first.record(true);
+        assert_eq!(breaker.state(), HalfOpen);
+        second.record(false);
+        assert_eq!(breaker.state(), Open);
+        
+        clock.advance(Duration::from_secs(30));
+        call(&breaker, true);
+        call(&breaker, true);
+        assert_eq!(breaker.state(), Closed);
+        
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![
+                (Closed, Open),
+                (Open, HalfOpen),
+                (HalfOpen, Open),
+                (Open, HalfOpen),
+                (HalfOpen, Closed),
+            ]
+        );
+    }
+    
+    #[test]
+    fn test_circuit_breaker_rolling_window() {
+        let clock = ManualClock::new();
+        let (breaker, _) = test_breaker(&clock);
+        
+        for _ in 0..3 {
+            call(&breaker, false);
+        }
+        // 旧的失败滑出窗口后不再计入
+        clock.advance(Duration::from_secs(11));
+        call(&breaker, false);
+        call(&breaker, true);
+        call(&breaker, true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        
+        // 熔断前发出的许可在状态变化后报告的结果被忽略
+        let stale = breaker.acquire().unwrap();
+        call(&breaker, false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        stale.record(true);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    }
+    
+    #[test]
+    fn test_call_window_counts_per_bucket() {
+        let start = Instant::now();
+        let window = Duration::from_secs(10);
+        let mut calls = CallWindow::default();
+        
+        // 大量调用只改变计数，不增加占用
+        for i in 0..10_000 {
+            calls.record(i % 2 == 0, start, window);
+        }
+        assert_eq!(calls.record(false, start, window), (10_001, 5_001));
+        
+        // 9 秒后还在窗口内，10 秒后第一个桶过期
+        let later = start + Duration::from_secs(9);
+        assert_eq!(calls.record(true, later, window), (10_002, 5_001));
+        let expired = start + Duration::from_secs(10);
+        assert_eq!(calls.record(true, expired, window), (2, 0));
+    }
+    
+    #[test]
+    #[should_panic(expected = "half_open_probes")]
+    fn test_circuit_breaker_rejects_zero_probes() {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            half_open_probes: 0,
+            ..CircuitBreakerConfig::default()
+        });
+    }
+    
+    #[tokio::test]
+    async fn test_api_client_stops_calling_open_circuit() {
+        let (base, hits) = start_flaky(usize::MAX, 503, None).await;
+        
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            minimum_calls: 3,
+            ..CircuitBreakerConfig::default()
+        });
+        let api = ApiClient::new(base, "test-key".to_string())
+            .with_retry(RetryPolicy::none())
+            .with_circuit_breaker(breaker.clone());
+        
+        for _ in 0..3 {
+            assert!(api.get_user(1).await.is_err());
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+        
+        let err = api.get_user(1).await.unwrap_err();
+        assert!(err.downcast_ref::<CircuitOpenError>().is_some());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+    
+    /// ranged 响应的 ETag
+    const BLOB_ETAG: &str = "\"v1\"";
+    
+    /// 固定内容的下载源；ranged 支持 Range 和 If-Range，plain 总是返回完整内容
+    #[derive(Clone, Default)]
+    struct Blob {
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+    }
+    
+    fn blob_bytes() -> Vec<u8> {
+        (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+    
+    fn blob_sha256() -> String {
+        use sha2::Digest;
+        to_hex(&sha2::Sha256::digest(blob_bytes()))
+    }
+    
+    async fn serve_blob(
+        State(blob): State<Blob>,
+        Path(name): Path<String>,
+        headers: HeaderMap,
+    ) -> axum::response::Response {
+        let range = headers
+            .get("range")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        blob.ranges.lock().unwrap().push(range.clone());
+        
+        // 响应前停一会儿，让并发的请求有机会重叠
+        let active = blob.active.fetch_add(1, Ordering::SeqCst) + 1;
+        blob.max_active.fetch_max(active, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        blob.active.fetch_sub(1, Ordering::SeqCst);
+        
+        let body = blob_bytes();
+        let len = body.len();
+        // If-Range 和当前版本不符时忽略 Range，返回完整内容
+        let unchanged = headers
+            .get("if-range")
+            .is_none_or(|v| v.as_bytes() == BLOB_ETAG.as_bytes());
+        let start = range
+            .as_deref()
+            .and_then(|r| r.strip_prefix("bytes="))
+            .and_then(|r| r.strip_suffix('-'))
+            .and_then(|r| r.parse::<usize>().ok())
+            .filter(|_| unchanged);
+        match (name.as_str(), start) {
+            ("ranged", Some(start)) if start >= len => (
+                axum::http::StatusCode::RANGE_NOT_SATISFIABLE,
+                [("content-range", format!("bytes */{}", len))],
+            )
+                .into_response(),
+            ("ranged", Some(start)) => (
+                axum::http::StatusCode::PARTIAL_CONTENT,
+                [
+                    ("content-range", format!("bytes {}-{}/{}", start, len - 1, len)),
+                    ("etag", BLOB_ETAG.to_string()),
+                ],
+                body[start..].to_vec(),
+            )
+                .into_response(),
+            ("ranged", None) => ([("etag", BLOB_ETAG)], body).into_response(),
+            _ => body.into_response(),
+        }
+    }
+    
+    async fn start_blob() -> (String, Blob) {
+        let blob = Blob::default();
+        let app = Router::new()
+            .route("/files/:name", get(serve_blob))
+            .with_state(blob.clone());
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, blob)
+    }
+    
+    fn download_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("http_client_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    
+    #[tokio::test]
+    async fn test_download_all_streams_to_disk() {
+        let (base, blob) = start_blob().await;
+        let dir = download_dir("download_all");
+        
+        let jobs: Vec<_> = (0..5)
+            .map(|i| {
+                DownloadJob::new(
+                    format!("{}/files/ranged", base),
+                    dir.join(format!("{}.bin", i)),
+                )
+                .sha256(blob_sha256().to_uppercase())
+            })
+            .collect();
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&progress);
+        let downloader = Downloader::new(Client::new())
+            .concurrency(2)
+            .on_progress(move |p| log.lock().unwrap().push(p.clone()));
+        
+        let results = downloader.download_all(&jobs).await;
+        for (job, result) in jobs.iter().zip(results) {
+            let report = result.unwrap();
+            assert_eq!(report.bytes, 100_000);
+            assert_eq!(report.resumed_from, 0);
+            assert_eq!(std::fs::read(&job.dest).unwrap(), blob_bytes());
+            assert!(!job.part_path().exists());
+            assert!(!job.validator_path().exists());
+        }
+        assert_eq!(blob.max_active.load(Ordering::SeqCst), 2);
+        
+        // 每个文件的进度单调递增，最后一次等于总大小
+        let progress = progress.lock().unwrap();
+        for index in 0..5 {
+            let own: Vec<_> = progress.iter().filter(|p| p.index == index).collect();
+            assert!(own.windows(2).all(|w| w[0].downloaded <= w[1].downloaded));
+            let last = own.last().unwrap();
+            assert_eq!((last.downloaded, last.total), (100_000, Some(100_000)));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_download_resumes_partial_file() {
+        let (base, blob) = start_blob().await;
+        let dir = download_dir("resume");
+        let downloader = Downloader::new(Client::new());
+        
+        let job = DownloadJob::new(format!("{}/files/ranged", base), dir.join("ranged.bin"))
+            .sha256(blob_sha256());
+        std::fs::write(job.part_path(), &blob_bytes()[..30_000]).unwrap();
+        std::fs::write(job.validator_path(), BLOB_ETAG).unwrap();
+        let report = downloader.download(0, &job).await.unwrap();
+        assert_eq!(report.resumed_from, 30_000);
+        assert_eq!(report.bytes, 100_000);
+        assert_eq!(std::fs::read(&job.dest).unwrap(), blob_bytes());
+        
+        // .part 已经完整时服务器返回 416，只做校验和改名
+        std::fs::write(job.part_path(), blob_bytes()).unwrap();
+        std::fs::write(job.validator_path(), BLOB_ETAG).unwrap();
+        let report = downloader.download(0, &job).await.unwrap();
+        assert_eq!(report.resumed_from, 100_000);
+        assert_eq!(std::fs::read(&job.dest).unwrap(), blob_bytes());
+        
+        // 服务器忽略 Range 时从头下载，不会把完整内容追加到旧数据后面
+        let job = DownloadJob::new(format!("{}/files/plain", base), dir.join("plain.bin"))
+            .sha256(blob_sha256());
+        std::fs::write(job.part_path(), &blob_bytes()[..30_000]).unwrap();
+        std::fs::write(job.validator_path(), "Tue, 01 Sep 2026 00:00:00 GMT").unwrap();
+        let report = downloader.download(0, &job).await.unwrap();
+        assert_eq!(report.resumed_from, 0);
+        assert_eq!(std::fs::read(&job.dest).unwrap(), blob_bytes());
+        
+        assert_eq!(
+            *blob.ranges.lock().unwrap(),
+            vec![
+                Some("bytes=30000-".to_string()),
+                Some("bytes=100000-".to_string()),
+                Some("bytes=30000-".to_string()),
+            ]
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_download_restarts_when_remote_changed() {
+        let (base, blob) = start_blob().await;
+        let dir = download_dir("changed");
+        let downloader = Downloader::new(Client::new());
+        
+        // .part 来自旧版本的文件；没有 sha256 也不能把新内容拼上去
+        let job = DownloadJob::new(format!("{}/files/ranged", base), dir.join("changed.bin"));
+        std::fs::write(job.part_path(), vec![0xAA; 30_000]).unwrap();
+        std::fs::write(job.validator_path(), "\"v0\"").unwrap();
+        let report = downloader.download(0, &job).await.unwrap();
+        assert_eq!(report.resumed_from, 0);
+        assert_eq!(report.sha256, blob_sha256());
+        assert_eq!(std::fs::read(&job.dest).unwrap(), blob_bytes());
+        
+        // 没有保存版本的 .part 无法确认，不发 Range
+        std::fs::write(job.part_path(), vec![0xAA; 30_000]).unwrap();
+        let report = downloader.download(0, &job).await.unwrap();
+        assert_eq!(report.resumed_from, 0);
+        assert_eq!(std::fs::read(&job.dest).unwrap(), blob_bytes());
+        
+        assert_eq!(
+            *blob.ranges.lock().unwrap(),
+            vec![Some("bytes=30000-".to_string()), None]
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_download_saves_validator_for_resume() {
+        let (base, _) = start_blob().await;
+        let dir = download_dir("validator");
+        
+        // 下载过程中 .part 旁边一直有版本标识，中断后可以安全续传
+        let job = DownloadJob::new(format!("{}/files/ranged", base), dir.join("v.bin"));
+        let validator = job.validator_path();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let downloader = Downloader::new(Client::new()).on_progress(move |_| {
+            log.lock().unwrap().push(std::fs::read_to_string(&validator).ok());
+        });
+        downloader.download(0, &job).await.unwrap();
+        
+        let seen = seen.lock().unwrap();
+        assert!(!seen.is_empty());
+        assert!(seen.iter().all(|v| v.as_deref() == Some(BLOB_ETAG)));
+        assert!(!job.validator_path().exists());
+    }
+    
+    #[tokio::test]
+    async fn test_download_rejects_bad_checksum() {
+        let (base, _) = start_blob().await;
+        let dir = download_dir("checksum");
+        let downloader = Downloader::new(Client::new());
+        
+        let job = DownloadJob::new(format!("{}/files/ranged", base), dir.join("bad.bin"))
+            .sha256("00".repeat(32));
+        match downloader.download(0, &job).await {
+            Err(DownloadError::ChecksumMismatch { actual, .. }) => {
+                assert_eq!(actual, blob_sha256())
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // 损坏的数据既不改名也不留作续传
+        assert!(!job.dest.exists());
+        assert!(!job.part_path().exists());
+        
+        let job = DownloadJob::new(format!("{}/missing", base), dir.join("missing.bin"));
+        assert!(matches!(
+            downloader.download(0, &job).await,
+            Err(DownloadError::Status(StatusCode::NOT_FOUND))
+        ));
+    }
+    
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 10-99/100"),
+            Some((Some(10), Some(100)))
+        );
+        assert_eq!(parse_content_range("bytes 10-99/*"), Some((Some(10), None)));
+        assert_eq!(parse_content_range("bytes */100"), Some((None, Some(100))));
+        assert_eq!(parse_content_range("bytes 10/100"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+    
+    /// complete_api 外面套上服务端的认证中间件，确认 RestClient 注入了认证头
+    async fn start_complete_api() -> String {
+        use crate::network::http_server::{
+            auth_middleware, complete_api, Access, Auth, InMemoryUserRepository, KeyStore,
+            Principal,
+        };
+        
+        let keys = KeyStore::default().with_bearer_token("test-key", Principal::new("test", &[]));
+        let app = complete_api(Arc::new(InMemoryUserRepository::default())).layer(
+            axum::middleware::from_fn_with_state(
+                Auth::new(keys).guard_state(Access::Authenticated),
+                auth_middleware,
+            ),
+        );
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+        // complete_api 按客户端 IP 限流，需要 ConnectInfo
+        let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base
+    }
+    
+    fn new_user(name: &str, email: &str) -> CreateUser {
+        CreateUser(NewUser {
+            name: name.to_string(),
+            email: email.to_string(),
+        })
+    }
+    
+    #[tokio::test]
+    async fn test_rest_client_user_crud() {
+        let api = RestClient::new(start_complete_api().await).bearer_auth("test-key");
+        
+        let alice = api
+            .send(&new_user("alice", "alice@example.com"))
+            .await
+            .unwrap();
+        let bob = api.send(&new_user("bob", "bob@example.com")).await.unwrap();
+        assert_eq!(api.send(&GetUser { id: alice.id }).await.unwrap(), alice);
+        
+        let page = api
+            .send(&ListUsers {
+                limit: Some(1),
+                sort: Some("-name".to_string()),
+                ..ListUsers::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!((page.total, page.page, page.limit), (2, 1, 1));
+        assert_eq!(page.data, vec![bob]);
+        
+        let updated = api
+            .send(&UpdateUser {
+                id: alice.id,
+                patch: UserPatch {
+                    email: Some("alice@rust.dev".to_string()),
+                    ..UserPatch::default()
+                },
+            })
+            .await
+            .unwrap();
+        assert_eq!(updated.name, "alice");
+        assert_eq!(updated.email, "alice@rust.dev");
+        
+        api.send(&DeleteUser { id: alice.id }).await.unwrap();
+        match api.send(&GetUser { id: alice.id }).await {
+            Err(RestError::Api {
+                status,
+                code,
+                request_id,
+                ..
+            }) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(code, "not_found");
+                assert!(request_id.is_some());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_rest_client_errors() {
+        let base = start_complete_api().await;
+        let api = RestClient::new(base.clone()).bearer_auth("test-key");
+        
+        match api.send(&new_user("a", "not-an-email")).await {
+            Err(RestError::Api {
+                status,
+                code,
+                details,
+                ..
+            }) => {
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+                assert_eq!(code, "validation_failed");
+                assert!(details.to_string().contains("email"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        
+        // 没有认证头或 Token 不对：服务端认证中间件返回统一格式的 401
+        for client in [
+            RestClient::new(base.clone()),
+            RestClient::new(base.clone()).bearer_auth("wrong-key"),
+        ] {
+            match client.send(&GetUser { id: 1 }).await {
+                Err(RestError::Api { status, code, .. }) => {
+                    assert_eq!(status, StatusCode::UNAUTHORIZED);
+                    assert_eq!(code, "unauthorized");
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+        
+        // 响应体不是统一错误格式：未匹配的路由返回空的 404
+        struct Missing;
+        impl Endpoint for Missing {
+            const METHOD: reqwest::Method = reqwest::Method::GET;
+            const PATH: &'static str = "/missing";
+            type Query = ();
+            type Body = ();
+            type Response = ();
+        }
+        let err = api.send(&Missing).await.unwrap_err();
+        assert!(matches!(err, RestError::Status { .. }));
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        
+        // 响应类型和声明不符
+        struct Health;
+        impl Endpoint for Health {
+            const METHOD: reqwest::Method = reqwest::Method::GET;
+            const PATH: &'static str = "/health";
+            type Query = ();
+            type Body = ();
+            type Response = ApiUser;
+        }
+        assert!(matches!(api.send(&Health).await, Err(RestError::Decode(_))));
+    }
+    
+    #[test]
+    fn test_render_path() {
+        assert_eq!(render_path("/users", &[]), "/users");
+        assert_eq!(
+            render_path("/users/{id}", &[("id", "42".to_string())]),
+            "/users/42"
+        );
+        assert_eq!(
+            render_path("/files/{name}/raw", &[("name", "a b/c".to_string())]),
+            "/files/a%20b%2Fc/raw"
+        );
+    }
+    
+    /// 登录后设置会话 Cookie 的服务
+    async fn start_login_server() -> String {
+        use axum::routing::post;
+        
+        async fn login(body: String) -> axum::response::Response {
+            if body != "user=alice&password=secret" {
+                return axum::http::StatusCode::UNAUTHORIZED.into_response();
+            }
+            let mut response = axum::http::StatusCode::NO_CONTENT.into_response();
+            let headers = response.headers_mut();
+            for cookie in [
+                "session=alice-token; Path=/; HttpOnly",
+                "theme=dark; Path=/prefs; Max-Age=3600",
+                // 域名不匹配，必须被拒绝
+                "tracker=1; Domain=example.com",
+            ] {
+                headers.append("set-cookie", cookie.parse().unwrap());
+            }
+            response
+        }
+        
+        async fn me(headers: HeaderMap) -> axum::response::Response {
+            let cookie = headers
+                .get("cookie")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            if cookie.split("; ").any(|c| c == "session=alice-token") {
+                cookie.to_string().into_response()
+            } else {
+                axum::http::StatusCode::UNAUTHORIZED.into_response()
+            }
+        }
+        
+        async fn logout() -> axum::response::Response {
+            let mut response = axum::http::StatusCode::NO_CONTENT.into_response();
+            response
+                .headers_mut()
+                .insert("set-cookie", "session=; Path=/; Max-Age=0".parse().unwrap());
+            response
+        }
+        
+        let app = Router::new()
+            .route("/login", post(login))
+            .route("/me", get(me))
+            .route("/logout", post(logout));
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base
+    }
+    
+    fn client_with(jar: &Arc<CookieJar>) -> Client {
+        Client::builder()
+            .cookie_provider(Arc::clone(jar))
+            .build()
+            .unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_cookie_session_survives_restart() {
+        let base = start_login_server().await;
+        let file =
+            std::env::temp_dir().join(format!("http_client_cookies_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        
+        let jar = Arc::new(CookieJar::load(&file).unwrap());
+        let client = client_with(&jar);
+        let me = format!("{}/me", base);
+        assert_eq!(
+            client.get(&me).send().await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        
+        let login = client
+            .post(format!("{}/login", base))
+            .body("user=alice&password=secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(login.status(), StatusCode::NO_CONTENT);
+        let mut names: Vec<_> = jar.all().into_iter().map(|c| c.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["session", "theme"]);
+        jar.save(&file).unwrap();
+        
+        // 模拟重启：新的存储和 Client 从文件恢复会话；/me 不在 /prefs 下，不带 theme
+        let jar = Arc::new(CookieJar::load(&file).unwrap());
+        let client = client_with(&jar);
+        let response = client.get(&me).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "session=alice-token");
+        
+        client
+            .post(format!("{}/logout", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            client.get(&me).send().await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(jar.all().iter().all(|c| c.name != "session"));
+    }
+    
+    fn names(jar: &CookieJar, url: &str, now: i64) -> Vec<String> {
+        let url = reqwest::Url::parse(url).unwrap();
+        jar.matching(&url, now)
+            .into_iter()
+            .map(|c| c.name)
+            .collect()
+    }
+    
+    #[test]
+    fn test_cookie_matching_rules() {
+        let now = 1_700_000_000;
+        let jar = CookieJar::new();
+        let origin = reqwest::Url::parse("https://www.example.com/docs/page").unwrap();
+        for header in [
+            "host=1",
+            "domain=1; Domain=.Example.COM; Path=/",
+            "docs=1; Path=/docs/",
+            "secure=1; Secure; Path=/",
+            "short=1; Path=/; Max-Age=10",
+            // Max-Age 优先于 Expires
+            "kept=1; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=100",
+            "old=1; Path=/; Expires=Thu, 01-Jan-1970 00:00:00 GMT",
+            "other=1; Domain=example.org",
+            // 公共后缀上的超级 Cookie
+            "tld=1; Domain=com; Path=/",
+            "dottld=1; Domain=.COM; Path=/",
+            "=nameless",
+        ] {
+            jar.store(header, &origin, now);
+        }
+        
+        // 默认路径是 /docs；路径长的排在前面
+        assert_eq!(
+            names(&jar, "https://www.example.com/docs/page", now),
+            vec!["docs", "host", "domain", "secure", "short", "kept"]
+        );
+        // Secure 不发给 http，host-only 不发给子域名和上级域名
+        assert_eq!(
+            names(&jar, "http://www.example.com/", now),
+            vec!["domain", "short", "kept"]
+        );
+        assert_eq!(names(&jar, "https://a.www.example.com/", now), vec!["domain"]);
+        assert_eq!(names(&jar, "https://example.com/docs/page", now), vec!["domain"]);
+        assert_eq!(
+            names(&jar, "https://badexample.com/", now),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            names(&jar, "https://www.example.com/", now + 10),
+            vec!["domain", "secure", "kept"]
+        );
+        
+        // 同名同域同路径覆盖原值，保留创建顺序；过期时间在过去即删除
+        // （short 在上面 now + 10 的查询中已经过期清除）
+        jar.store("domain=2; Domain=example.com; Path=/", &origin, now);
+        jar.store("secure=; Path=/; Max-Age=0", &origin, now);
+        let cookies = jar.matching(&origin, now);
+        let pairs: Vec<_> = cookies
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        assert_eq!(pairs, vec!["docs=1", "host=1", "domain=2", "kept=1"]);
+    }
+    
+    #[test]
+    fn test_cookie_path_helpers() {
+        assert_eq!(default_path("/"), "/");
+        assert_eq!(default_path("/login"), "/");
+        assert_eq!(default_path("/a/b/c"), "/a/b");
+        assert!(path_match("/a/b", "/a"));
+        assert!(path_match("/a/", "/a/"));
+        assert!(!path_match("/ab", "/a"));
+        assert!(domain_match("a.b.example.com", "example.com"));
+        assert!(!domain_match("127.0.0.1", "0.0.1"));
+    }
+    
+    #[test]
+    fn test_cookie_rejects_public_suffix_domain() {
+        let now = 1_700_000_000;
+        let parse = |header: &str, url: &str| {
+            parse_set_cookie(header, &reqwest::Url::parse(url).unwrap(), now)
+        };
+        
+        assert!(parse("a=1; Domain=com", "https://www.example.com/").is_none());
+        assert!(parse("a=1; Domain=co.uk", "https://shop.example.co.uk/").is_none());
+        assert!(parse("a=1; Domain=github.io", "https://alice.github.io/").is_none());
+        let cookie = parse("a=1; Domain=example.co.uk", "https://shop.example.co.uk/").unwrap();
+        assert_eq!((cookie.domain.as_str(), cookie.host_only), ("example.co.uk", false));
+        
+        // 主机名本身就是公共后缀时退化成 host-only
+        let cookie = parse("a=1; Domain=localhost", "http://localhost:8080/").unwrap();
+        assert_eq!((cookie.domain.as_str(), cookie.host_only), ("localhost", true));
+        let jar = CookieJar::new();
+        jar.store("a=1; Domain=localhost", &reqwest::Url::parse("http://localhost/").unwrap(), now);
+        assert!(names(&jar, "http://sub.localhost/", now).is_empty());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_cookie_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let file =
+            std::env::temp_dir().join(format!("http_client_private_{}.json", std::process::id()));
+        let mut tmp = file.as_os_str().to_owned();
+        tmp.push(".tmp");
+        // 上次中断留下的临时文件权限更宽
+        std::fs::write(&tmp, "").unwrap();
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o644)).unwrap();
+        
+        let jar = CookieJar::new();
+        let origin = reqwest::Url::parse("https://example.com/").unwrap();
+        jar.store("session=secret; Path=/", &origin, unix_now());
+        jar.save(&file).unwrap();
+        
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(CookieJar::load(&file).unwrap().all().len(), 1);
+        std::fs::remove_file(&file).unwrap();
+    }
+}
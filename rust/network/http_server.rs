@@ -9,13 +9,22 @@ use axum::{
     http::{HeaderMap, HeaderValue, StatusCode},
     response::{Html, IntoResponse, Response},
     routing::{get, post},
-    Router,
+    Extension, Router,
 };
+use hyper_util::server::graceful::GracefulShutdown;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::task::{JoinHandle, JoinSet};
 use tower_http::cors::CorsLayer;
 
+use crate::types::regex_examples::USERNAME_PATTERN;
+
 #[tokio::main]
 async fn main() {
     println!("=== Axum Web 框架详解 ===\n");
@@ -83,7 +92,7 @@ async fn run_basic_server() {
     println!("    GET  /search?q=...");
     println!();
 
-    println!("  按 Ctrl+C 停止服务器");
+    println!("  按 Ctrl+C 停止服务器（最多等待 30 秒处理完进行中的请求）");
     println!();
 
     // 启动服务器
@@ -91,7 +100,11 @@ async fn run_basic_server() {
         .await
         .unwrap();
 
-    axum::serve(listener, app).await.unwrap();
+    let handle = ServerHandle::start(listener, app, ShutdownConfig::default()).unwrap();
+    match handle.wait().await {
+        Ok(outcome) => println!("  服务器已停止: {:?}", outcome),
+        Err(e) => println!("  服务器异常退出: {}", e),
+    }
 }
 
 // 根路径处理器
@@ -178,7 +191,7 @@ async fn path_extractor_multiple(Path((user_id, post_id)): Path<(u32, u32)>) ->
 }
 
 // Query 提取器
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 struct Pagination {
     #[serde(default = "default_page")]
     page: u32,
@@ -320,31 +333,37 @@ async fn result_response() -> Result<Json<ApiResponse>, StatusCode> {
 #[derive(Clone)]
 struct AppState {
     db: Arc<Mutex<HashMap<u32, String>>>,
-    counter: Arc<Mutex<u32>>,
+    // 计数器注册在指标系统中，同时出现在 /metrics
+    counter: Counter,
 }
 
 fn state_example() -> Router {
+    let metrics = Arc::new(Metrics::default());
+
     // 初始化状态
     let state = AppState {
         db: Arc::new(Mutex::new(HashMap::new())),
-        counter: Arc::new(Mutex::new(0)),
+        counter: metrics.counter("demo_counter_total", "POST /counter 的调用次数"),
     };
 
     Router::new()
         .route("/counter", get(get_counter).post(increment_counter))
         .route("/data/:key", get(get_data).post(set_data))
         .with_state(state)
+        .route("/metrics", get(metrics_handler))
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::clone(&metrics),
+            track_metrics,
+        ))
+        .layer(Extension(metrics))
 }
 
 async fn get_counter(State(state): State<AppState>) -> String {
-    let count = state.counter.lock().unwrap();
-    format!("Counter: {}", *count)
+    format!("Counter: {}", state.counter.get())
 }
 
 async fn increment_counter(State(state): State<AppState>) -> String {
-    let mut count = state.counter.lock().unwrap();
-    *count += 1;
-    format!("Counter: {}", *count)
+    format!("Counter: {}", state.counter.inc())
 }
 
 async fn get_data(
@@ -366,73 +385,447 @@ async fn set_data(State(state): State<AppState>, Path(key): Path<u32>, body: Str
 // ============================================
 
 // 自定义错误类型
-enum AppError {
-    NotFound,
+//
+// 所有错误统一返回 JSON:
+//   {"code": "not_found", "message": "...", "details": null, "request_id": "..."}
+#[derive(Debug)]
+pub(crate) enum AppError {
+    NotFound(String),
     BadRequest(String),
-    InternalError,
+    // 查询参数不合法，details 中给出字段名
+    InvalidQuery {
+        field: &'static str,
+        message: String,
+    },
+    Conflict(String),
+    // 缺少或无效的凭证
+    Unauthorized(String),
+    // 凭证有效但权限不足
+    Forbidden(String),
+    // 请求体校验失败，列出每个字段的原因
+    Validation(Vec<FieldError>),
+    RateLimited {
+        retry_after: Duration,
+    },
+    Unavailable(String),
+    // 内部错误的上下文只记录日志，不返回给客户端
+    InternalError(String),
+}
+
+// 统一的错误响应体
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct ErrorBody {
+    code: String,
+    message: String,
+    details: serde_json::Value,
+    request_id: Option<String>,
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) | AppError::InvalidQuery { .. } => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::InvalidQuery { .. } => "invalid_query",
+            AppError::Conflict(_) => "conflict",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::Validation(_) => "validation_failed",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::Unavailable(_) => "service_unavailable",
+            AppError::InternalError(_) => "internal_error",
+        }
+    }
+}
+
+impl AppError {
+    // 状态码和错误响应体；原始 socket 服务器也用它渲染同样的错误
+    pub(crate) fn into_parts(self) -> (StatusCode, ErrorBody) {
+        let status = self.status();
+        let code = self.code();
+        let request_id = current_request_id();
+
+        let (message, details) = match self {
+            AppError::NotFound(msg)
+            | AppError::BadRequest(msg)
+            | AppError::Conflict(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Forbidden(msg)
+            | AppError::Unavailable(msg) => (msg, serde_json::Value::Null),
+            AppError::InvalidQuery { field, message } => {
+                (message, serde_json::json!({ "field": field }))
+            }
+            AppError::Validation(fields) => (
+                "请求参数校验失败".to_string(),
+                serde_json::json!({ "fields": fields }),
+            ),
+            AppError::RateLimited { retry_after: wait } => {
+                let secs = retry_after_secs(wait);
+                (
+                    format!("请求过于频繁，请 {} 秒后重试", secs),
+                    serde_json::json!({ "retry_after_secs": secs }),
+                )
+            }
+            AppError::InternalError(context) => {
+                eprintln!(
+                    "[{}] 内部错误: {}",
+                    request_id.as_deref().unwrap_or("-"),
+                    context
+                );
+                ("Internal Server Error".to_string(), serde_json::Value::Null)
+            }
+        };
+
+        let body = ErrorBody {
+            code: code.to_string(),
+            message,
+            details,
+            request_id,
+        };
+        (status, body)
+    }
+}
+
+fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, message) = match self {
-            AppError::NotFound => (StatusCode::NOT_FOUND, "Not Found"),
-            AppError::BadRequest(msg) => {
-                return (StatusCode::BAD_REQUEST, msg).into_response();
-            }
-            AppError::InternalError => (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
+        let retry_after = match &self {
+            AppError::RateLimited { retry_after } => Some(retry_after_secs(*retry_after)),
+            _ => None,
         };
+        let challenge = matches!(self, AppError::Unauthorized(_));
+
+        let (status, body) = self.into_parts();
+        let mut response = (status, Json(body)).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(axum::http::header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        if challenge {
+            response.headers_mut().insert(
+                axum::http::header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer realm=\"api\""),
+            );
+        }
+        response
+    }
+}
+
+// 领域错误通过 From 转换，处理器里直接用 ?
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::InternalError(format!("存储错误: {}", e))
+    }
+}
+
+impl From<crate::types::thiserror_examples::DatabaseError> for AppError {
+    fn from(e: crate::types::thiserror_examples::DatabaseError) -> Self {
+        use crate::types::thiserror_examples::DatabaseError;
+
+        match e {
+            DatabaseError::NotFound { table, id } => {
+                AppError::NotFound(format!("{} 中不存在 id={}", table, id))
+            }
+            DatabaseError::DuplicateKey { key } => AppError::Conflict(format!("{} 已存在", key)),
+            DatabaseError::PoolExhausted | DatabaseError::ConnectionFailed(_) => {
+                eprintln!("数据库不可用: {}", e);
+                AppError::Unavailable("数据库暂时不可用，请稍后重试".to_string())
+            }
+            other => AppError::InternalError(other.to_string()),
+        }
+    }
+}
+
+impl From<crate::types::thiserror_examples::ConfigError> for AppError {
+    fn from(e: crate::types::thiserror_examples::ConfigError) -> Self {
+        AppError::InternalError(format!("配置错误: {}", e))
+    }
+}
+
+// 请求 ID：由中间件放入 task-local，错误响应体从这里读取
+const X_REQUEST_ID: &str = "x-request-id";
 
-        (status, message).into_response()
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+// 请求 ID 中间件：沿用客户端传入的 X-Request-Id，否则生成一个，并写回响应头
+pub(crate) async fn request_id(
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let id = req
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(new_request_id);
+
+    let mut response = REQUEST_ID.scope(id.clone(), next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
     }
+    response
+}
+
+fn new_request_id() -> String {
+    use std::sync::atomic::AtomicU64;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    format!(
+        "{:x}-{:04x}",
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+    )
 }
 
 async fn error_handler() -> Result<String, AppError> {
     // 可以返回自定义错误
-    Err(AppError::NotFound)
+    Err(AppError::NotFound("resource not found".to_string()))
 }
 
 // ============================================
 // 7. 中间件
 // ============================================
 
-fn middleware_example() -> Router {
+fn middleware_example(keys: KeyStore) -> Router {
     use tower_http::trace::TraceLayer;
 
+    let auth = Auth::new(keys);
+
     Router::new()
+        .route("/me", get(whoami))
+        // 认证：route_layer 只作用于在它之前注册的路由，未匹配的路径仍然返回 404
+        .route_layer(axum::middleware::from_fn_with_state(
+            auth.guard_state(Access::Authenticated),
+            auth_middleware,
+        ))
+        // 在认证层之后注册的路由是公开的
         .route("/", get(root_handler))
+        // 请求 ID（错误响应体中的 request_id）
+        .layer(axum::middleware::from_fn(request_id))
         // 添加 CORS
         .layer(CorsLayer::permissive())
         // 添加日志追踪
         .layer(TraceLayer::new_for_http())
 }
 
+// 认证：Bearer Token 或 X-API-Key
+//
+// 已认证的调用方放入请求扩展，处理器用 Extension<Principal> 取出
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Principal {
+    name: String,
+    scopes: Vec<String>,
+}
+
+impl Principal {
+    pub(crate) fn new(name: &str, scopes: &[&str]) -> Self {
+        Principal {
+            name: name.to_string(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+const X_API_KEY: &str = "x-api-key";
+
+// 凭证存储
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KeyStore {
+    bearer_tokens: HashMap<String, Principal>,
+    api_keys: HashMap<String, Principal>,
+}
+
+impl KeyStore {
+    pub(crate) fn with_bearer_token(mut self, token: &str, principal: Principal) -> Self {
+        self.bearer_tokens.insert(token.to_string(), principal);
+        self
+    }
+
+    pub(crate) fn with_api_key(mut self, key: &str, principal: Principal) -> Self {
+        self.api_keys.insert(key.to_string(), principal);
+        self
+    }
+
+    // 优先使用 Authorization 头，其次 X-API-Key
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AppError> {
+        if let Some(value) = headers.get(axum::http::header::AUTHORIZATION) {
+            let value = value
+                .to_str()
+                .map_err(|_| AppError::Unauthorized("Authorization 头格式错误".to_string()))?;
+            let token = match value.split_once(' ') {
+                Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+                _ => return Err(AppError::Unauthorized("仅支持 Bearer 认证".to_string())),
+            };
+            return self
+                .bearer_tokens
+                .get(token)
+                .cloned()
+                .ok_or_else(|| AppError::Unauthorized("无效的 Bearer Token".to_string()));
+        }
+
+        if let Some(key) = headers.get(X_API_KEY) {
+            return key
+                .to_str()
+                .ok()
+                .and_then(|key| self.api_keys.get(key))
+                .cloned()
+                .ok_or_else(|| AppError::Unauthorized("无效的 API Key".to_string()));
+        }
+
+        Err(AppError::Unauthorized("缺少认证信息".to_string()))
+    }
+}
+
+// 路由访问策略
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Access {
+    Public,
+    // 任意有效凭证
+    Authenticated,
+    // 需要指定权限
+    Scope(&'static str),
+}
+
+#[derive(Clone)]
+pub(crate) struct AuthGuard {
+    keys: Arc<KeyStore>,
+    access: Access,
+}
+
+// 按路由挂载认证中间件
+#[derive(Clone)]
+pub(crate) struct Auth {
+    keys: Arc<KeyStore>,
+}
+
+impl Auth {
+    pub(crate) fn new(keys: KeyStore) -> Self {
+        Auth {
+            keys: Arc::new(keys),
+        }
+    }
+
+    // 用 route_layer 包装单个方法路由，不影响同一路径上的其他方法
+    fn guard<S>(
+        &self,
+        access: Access,
+        route: axum::routing::MethodRouter<S>,
+    ) -> axum::routing::MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        match access {
+            Access::Public => route,
+            _ => route.route_layer(axum::middleware::from_fn_with_state(
+                self.guard_state(access),
+                auth_middleware,
+            )),
+        }
+    }
+
+    // auth_middleware 的状态，整个 Router 用同一策略时直接配合 route_layer
+    pub(crate) fn guard_state(&self, access: Access) -> AuthGuard {
+        AuthGuard {
+            keys: Arc::clone(&self.keys),
+            access,
+        }
+    }
+}
+
+pub(crate) async fn auth_middleware(
+    State(guard): State<AuthGuard>,
+    mut req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Result<Response, AppError> {
+    let principal = guard.keys.authenticate(req.headers())?;
+
+    if let Access::Scope(scope) = guard.access {
+        if !principal.has_scope(scope) {
+            return Err(AppError::Forbidden(format!("需要权限 {}", scope)));
+        }
+    }
+
+    req.extensions_mut().insert(principal);
+    Ok(next.run(req).await)
+}
+
 // ============================================
 // 8. 嵌套路由
 // ============================================
 
-fn nested_routes_example() -> Router {
-    // API 路由
+fn nested_routes_example(keys: KeyStore) -> Router {
+    let auth = Auth::new(keys);
+
+    // API 路由 - 每个方法单独声明访问策略
     let api_routes = Router::new()
-        .route("/users", get(get_users).post(create_user_handler))
+        .route(
+            "/users",
+            auth.guard(Access::Public, get(get_users))
+                .merge(auth.guard(Access::Scope("users:write"), post(create_user_handler))),
+        )
         .route(
             "/users/:id",
-            get(get_user_handler).delete(delete_user_handler),
+            auth.guard(Access::Authenticated, get(get_user_handler))
+                .merge(auth.guard(
+                    Access::Scope("users:admin"),
+                    axum::routing::delete(delete_user_handler),
+                )),
+        )
+        .route(
+            "/posts",
+            auth.guard(Access::Public, get(get_posts))
+                .merge(auth.guard(Access::Authenticated, post(create_post_handler))),
         )
-        .route("/posts", get(get_posts).post(create_post_handler));
+        .route("/me", auth.guard(Access::Authenticated, get(whoami)));
 
     // 主路由
     Router::new()
         .route("/", get(root_handler))
         .route("/health", get(health_check))
         .nest("/api/v1", api_routes)
+        .layer(axum::middleware::from_fn(request_id))
 }
 
 async fn get_users() -> &'static str {
     "GET /api/v1/users"
 }
 
-async fn create_user_handler() -> &'static str {
-    "POST /api/v1/users"
+async fn create_user_handler(Extension(principal): Extension<Principal>) -> String {
+    format!("POST /api/v1/users by {}", principal.name)
 }
 
 async fn get_user_handler(Path(id): Path<u32>) -> String {
@@ -451,8 +844,18 @@ async fn create_post_handler() -> &'static str {
     "POST /api/v1/posts"
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+async fn whoami(Extension(principal): Extension<Principal>) -> Json<Principal> {
+    Json(principal)
+}
+
+// 健康检查 - 由 ServerHandle 启动时，排空阶段返回 503
+async fn health_check(readiness: Option<Extension<Readiness>>) -> (StatusCode, &'static str) {
+    match readiness {
+        Some(Extension(readiness)) if readiness.is_draining() => {
+            (StatusCode::SERVICE_UNAVAILABLE, "DRAINING")
+        }
+        _ => (StatusCode::OK, "OK"),
+    }
 }
 
 // ============================================
@@ -460,214 +863,4097 @@ async fn health_check() -> &'static str {
 // ============================================
 
 // 数据模型
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct User {
+//
+// JsonSchema 生成 OpenAPI 文档中的 Schema，校验规则与 Validate 使用同一份常量
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct User {
+    #[schemars(range(min = 1))]
     id: u32,
     name: String,
+    #[schemars(email)]
     email: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct CreateUserRequest {
+impl User {
+    pub(crate) fn new(id: u32, name: impl Into<String>, email: impl Into<String>) -> Self {
+        User {
+            id,
+            name: name.into(),
+            email: email.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct CreateUserRequest {
+    #[schemars(regex = "USERNAME_PATTERN")]
     name: String,
+    #[schemars(email)]
     email: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct UpdateUserRequest {
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct UpdateUserRequest {
+    #[schemars(regex = "USERNAME_PATTERN")]
     name: Option<String>,
+    #[schemars(email)]
     email: Option<String>,
 }
 
-// 应用状态
-#[derive(Clone)]
-struct ApiState {
-    users: Arc<Mutex<HashMap<u32, User>>>,
-    next_id: Arc<Mutex<u32>>,
+// 用户存储
+//
+// 处理器只依赖这个 trait，具体存储在构建 complete_api() 时选择。
+// 方法是 async 的，需要写文件的实现在 spawn_blocking 中进行，不阻塞处理器
+#[axum::async_trait]
+pub(crate) trait UserRepository: Send + Sync {
+    async fn list(&self) -> std::io::Result<Vec<User>>;
+    async fn get(&self, id: u32) -> std::io::Result<Option<User>>;
+    async fn create(&self, req: CreateUserRequest) -> std::io::Result<User>;
+    async fn update(&self, id: u32, req: UpdateUserRequest) -> std::io::Result<Option<User>>;
+    async fn delete(&self, id: u32) -> std::io::Result<bool>;
 }
 
-// 完整 API 路由
-fn complete_api() -> Router {
-    let state = ApiState {
-        users: Arc::new(Mutex::new(HashMap::new())),
-        next_id: Arc::new(Mutex::new(1)),
-    };
-
-    Router::new()
-        // 根路径
-        .route("/", get(api_root))
-        // 用户 CRUD
-        .route("/users", get(list_users).post(create_user_api))
-        .route(
-            "/users/:id",
-            get(get_user_api)
-                .put(update_user_api)
-                .delete(delete_user_api),
-        )
-        // 健康检查
-        .route("/health", get(health_check))
-        // 添加状态
-        .with_state(state)
-        // 添加 CORS
-        .layer(CorsLayer::permissive())
+// 两种实现共用的内存表
+#[derive(Debug)]
+struct UserTable {
+    users: HashMap<u32, User>,
+    next_id: u32,
 }
 
-// API 根路径
-async fn api_root() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
-        "name": "User API",
-        "version": "1.0.0",
-        "endpoints": {
-            "users": "/users",
-            "health": "/health"
+impl Default for UserTable {
+    fn default() -> Self {
+        UserTable {
+            users: HashMap::new(),
+            next_id: 1,
         }
-    }))
+    }
 }
 
-// 列出所有用户
-async fn list_users(State(state): State<ApiState>) -> Json<Vec<User>> {
-    let users = state.users.lock().unwrap();
-    let user_list: Vec<User> = users.values().cloned().collect();
-    Json(user_list)
-}
+impl UserTable {
+    fn new_user(&self, req: CreateUserRequest) -> User {
+        User {
+            id: self.next_id,
+            name: req.name,
+            email: req.email,
+        }
+    }
 
-// 创建用户
-async fn create_user_api(
-    State(state): State<ApiState>,
-    Json(payload): Json<CreateUserRequest>,
-) -> (StatusCode, Json<User>) {
-    let mut next_id = state.next_id.lock().unwrap();
-    let id = *next_id;
-    *next_id += 1;
-
-    let user = User {
-        id,
-        name: payload.name,
-        email: payload.email,
-    };
+    fn updated_user(&self, id: u32, req: UpdateUserRequest) -> Option<User> {
+        let mut user = self.users.get(&id)?.clone();
+        if let Some(name) = req.name {
+            user.name = name;
+        }
+        if let Some(email) = req.email {
+            user.email = email;
+        }
+        Some(user)
+    }
 
-    let mut users = state.users.lock().unwrap();
-    users.insert(id, user.clone());
+    fn put(&mut self, user: User) {
+        self.next_id = self.next_id.max(user.id + 1);
+        self.users.insert(user.id, user);
+    }
 
-    (StatusCode::CREATED, Json(user))
+    fn apply(&mut self, entry: UserLogEntry) {
+        match entry {
+            UserLogEntry::NextId { next_id } => self.next_id = self.next_id.max(next_id),
+            UserLogEntry::Put { user } => self.put(user),
+            UserLogEntry::Delete { id } => {
+                self.users.remove(&id);
+            }
+        }
+    }
 }
 
-// 获取单个用户
-async fn get_user_api(
-    State(state): State<ApiState>,
-    Path(id): Path<u32>,
-) -> Result<Json<User>, StatusCode> {
-    let users = state.users.lock().unwrap();
-    users
-        .get(&id)
-        .cloned()
-        .map(Json)
-        .ok_or(StatusCode::NOT_FOUND)
+// 内存存储 - 重启即丢失
+#[derive(Default)]
+pub(crate) struct InMemoryUserRepository {
+    table: Mutex<UserTable>,
 }
 
-// 更新用户
-async fn update_user_api(
-    State(state): State<ApiState>,
-    Path(id): Path<u32>,
-    Json(payload): Json<UpdateUserRequest>,
-) -> Result<Json<User>, StatusCode> {
-    let mut users = state.users.lock().unwrap();
+#[axum::async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn list(&self) -> std::io::Result<Vec<User>> {
+        Ok(self.table.lock().unwrap().users.values().cloned().collect())
+    }
 
-    let user = users.get_mut(&id).ok_or(StatusCode::NOT_FOUND)?;
+    async fn get(&self, id: u32) -> std::io::Result<Option<User>> {
+        Ok(self.table.lock().unwrap().users.get(&id).cloned())
+    }
 
-    if let Some(name) = payload.name {
-        user.name = name;
+    async fn create(&self, req: CreateUserRequest) -> std::io::Result<User> {
+        let mut table = self.table.lock().unwrap();
+        let user = table.new_user(req);
+        table.put(user.clone());
+        Ok(user)
     }
-    if let Some(email) = payload.email {
-        user.email = email;
+
+    async fn update(&self, id: u32, req: UpdateUserRequest) -> std::io::Result<Option<User>> {
+        let mut table = self.table.lock().unwrap();
+        let user = table.updated_user(id, req);
+        if let Some(user) = &user {
+            table.put(user.clone());
+        }
+        Ok(user)
     }
 
-    Ok(Json(user.clone()))
+    async fn delete(&self, id: u32) -> std::io::Result<bool> {
+        Ok(self.table.lock().unwrap().users.remove(&id).is_some())
+    }
 }
 
-// 删除用户
-async fn delete_user_api(State(state): State<ApiState>, Path(id): Path<u32>) -> StatusCode {
-    let mut users = state.users.lock().unwrap();
+// 日志中的一条记录（JSON Lines，每行一条）
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum UserLogEntry {
+    // 记录 id 分配进度，保证删除后 id 不会被复用
+    NextId { next_id: u32 },
+    Put { user: User },
+    Delete { id: u32 },
+}
 
-    if users.remove(&id).is_some() {
-        StatusCode::NO_CONTENT
-    } else {
-        StatusCode::NOT_FOUND
-    }
+// 文件存储 - 追加写日志，启动时重放并压缩
+//
+// 每次修改先追加一行日志并 sync 再更新内存表，写失败时内存和文件都保持原样。
+// 写文件在 spawn_blocking 中进行，读只访问内存表，不会等写文件。
+// 只有最后一行允许不完整（崩溃时写了一半），中间的坏行让 open 返回错误。
+// 启动时把日志重放成当前状态，再写入临时文件并 rename 替换，
+// 旧文件中的历史修改和被删除的用户都会被清理掉。
+pub(crate) struct FileUserRepository {
+    table: Mutex<UserTable>,
+    // 串行化写入；写文件期间 UserLog 被移进 blocking 任务，任务 panic 后为 None
+    log: tokio::sync::Mutex<Option<UserLog>>,
 }
 
-/*
-=== 总结 ===
+// 打开的日志文件
+struct UserLog {
+    file: std::fs::File,
+    // 文件中完整记录的长度，写失败时截断到这里，不在日志中间留下半行
+    len: u64,
+}
 
-1. Axum 核心概念:
+impl UserLog {
+    // 整行一次 write_all 再 sync_data，返回成功时这次修改已经落盘；
+    // 只有崩溃会留下不完整的最后一行，open 时会丢弃
+    fn append(&mut self, entry: &UserLogEntry) -> std::io::Result<()> {
+        use std::io::Write;
 
-   路由:
-   - Router::new()
-   - route() - 定义路由
-   - nest() - 嵌套路由
-   - method_router() - 多方法路由
+        let line = encode_log_entry(entry)?;
+        let written = self
+            .file
+            .write_all(&line)
+            .and_then(|()| self.file.sync_data());
+        if let Err(e) = written {
+            // 失败时内存表没有更新，文件里也不能留下这一行（哪怕已经完整写入）
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        self.len += line.len() as u64;
+        Ok(())
+    }
+}
 
-   提取器:
-   - Path - 路径参数
-   - Query - 查询参数
-   - Json - JSON 请求体
-   - State - 共享状态
-   - Headers - 请求头
+impl FileUserRepository {
+    pub(crate) fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<FileUserRepository> {
+        use std::io::Write;
 
-2. 响应类型:
+        let path = path.as_ref();
+        let mut table = UserTable::default();
 
-   简单:
-   - &str - 纯文本
-   - String - 字符串
-   - Html<T> - HTML
-   - Json<T> - JSON
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                let lines: Vec<(usize, &str)> = content
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !line.trim().is_empty())
+                    .collect();
+                let last = lines.len().saturating_sub(1);
 
-   组合:
-   - (StatusCode, Json<T>)
-   - (HeaderMap, Body)
-   - Result<T, E>
-   - Response - 自定义响应
+                for (i, &(n, line)) in lines.iter().enumerate() {
+                    match serde_json::from_str(line) {
+                        Ok(entry) => table.apply(entry),
+                        // 崩溃时可能留下写了一半的最后一行，丢弃即可
+                        Err(e) if i == last => eprintln!("丢弃不完整的最后一行 {}: {}", n + 1, e),
+                        // 中间的行损坏说明文件被改坏了，不能静默丢数据
+                        Err(e) => {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                format!("{} 第 {} 行无法解析: {}", path.display(), n + 1, e),
+                            ))
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
 
-3. 状态管理:
+        // 压缩：只保留当前状态
+        let tmp_path = path.with_extension("compact.tmp");
+        {
+            let mut tmp = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+            let mut users: Vec<&User> = table.users.values().collect();
+            users.sort_by_key(|u| u.id);
 
-   模式:
-   - Arc<Mutex<T>> - 可变状态
-   - Arc<T> - 只读状态
-   - with_state() - 附加状态
-   - State<T> - 提取状态
+            tmp.write_all(&encode_log_entry(&UserLogEntry::NextId {
+                next_id: table.next_id,
+            })?)?;
+            for user in users {
+                tmp.write_all(&encode_log_entry(&UserLogEntry::Put {
+                    user: user.clone(),
+                })?)?;
+            }
+            tmp.flush()?;
+            tmp.get_ref().sync_all()?;
+        }
+        std::fs::rename(&tmp_path, path)?;
 
-4. 最佳实践:
+        let file = std::fs::OpenOptions::new().append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(FileUserRepository {
+            table: Mutex::new(table),
+            log: tokio::sync::Mutex::new(Some(UserLog { file, len })),
+        })
+    }
 
-   DO:
-   ✓ 使用类型安全的提取器
-   ✓ 实现 IntoResponse for 自定义错误
-   ✓ 使用 Router 组织路由
-   ✓ Arc + Mutex 管理共享状态
+    // 先写日志再改内存表，写失败时内存保持原样，和文件一致
+    //
+    // 调用方持有 log 锁，从读取内存表到这里之间没有别的修改插进来。
+    async fn commit(&self, log: &mut Option<UserLog>, entry: UserLogEntry) -> std::io::Result<()> {
+        let mut writer = log
+            .take()
+            .ok_or_else(|| std::io::Error::other("之前写日志的任务异常退出，日志不可用"))?;
+        let (writer, written, entry) = tokio::task::spawn_blocking(move || {
+            let written = writer.append(&entry);
+            (writer, written, entry)
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+        *log = Some(writer);
+        written?;
 
-   DON'T:
-   ✗ 在处理器中阻塞
-   ✗ 长时间持有锁
-   ✗ 忽略错误处理
+        self.table.lock().unwrap().apply(entry);
+        Ok(())
+    }
+}
 
-5. 常用模式:
+// 一条日志编码成完整的一行
+fn encode_log_entry(entry: &UserLogEntry) -> std::io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    Ok(line)
+}
 
-   REST API:
-   - CRUD 操作
-   - 状态管理
-   - 错误处理
-   - 中间件
+#[axum::async_trait]
+impl UserRepository for FileUserRepository {
+    async fn list(&self) -> std::io::Result<Vec<User>> {
+        Ok(self.table.lock().unwrap().users.values().cloned().collect())
+    }
 
-   路由组织:
-   - nest() 分组
-   - 版本化 API
-   - 模块化处理器
+    async fn get(&self, id: u32) -> std::io::Result<Option<User>> {
+        Ok(self.table.lock().unwrap().users.get(&id).cloned())
+    }
 
-运行示例:
-  cargo run --bin axum_detailed
+    async fn create(&self, req: CreateUserRequest) -> std::io::Result<User> {
+        let mut log = self.log.lock().await;
+        let user = self.table.lock().unwrap().new_user(req);
+        self.commit(&mut log, UserLogEntry::Put { user: user.clone() })
+            .await?;
+        Ok(user)
+    }
 
-测试 API:
-  curl http://localhost:3000/
-  curl http://localhost:3000/hello
-  curl http://localhost:3000/users/123
-  curl http://localhost:3000/search?q=rust
+    async fn update(&self, id: u32, req: UpdateUserRequest) -> std::io::Result<Option<User>> {
+        let mut log = self.log.lock().await;
+        let Some(user) = self.table.lock().unwrap().updated_user(id, req) else {
+            return Ok(None);
+        };
+        self.commit(&mut log, UserLogEntry::Put { user: user.clone() })
+            .await?;
+        Ok(Some(user))
+    }
+
+    async fn delete(&self, id: u32) -> std::io::Result<bool> {
+        let mut log = self.log.lock().await;
+        if !self.table.lock().unwrap().users.contains_key(&id) {
+            return Ok(false);
+        }
+        self.commit(&mut log, UserLogEntry::Delete { id }).await?;
+        Ok(true)
+    }
+}
+
+// 应用状态
+#[derive(Clone)]
+struct ApiState {
+    users: Arc<dyn UserRepository>,
+    events: UserEvents,
+}
+
+// 完整 API 路由
+//
+// 内存存储: complete_api(Arc::new(InMemoryUserRepository::default()))
+// 文件存储: complete_api(Arc::new(FileUserRepository::open("users.jsonl")?))
+//
+// 按客户端 IP 限流，需要用 into_make_service_with_connect_info::<SocketAddr>() 启动，
+// 否则限流的路由返回 500
+pub(crate) fn complete_api(users: Arc<dyn UserRepository>) -> Router {
+    let state = ApiState {
+        users,
+        events: UserEvents::new(USER_EVENTS_CAPACITY),
+    };
+    let metrics = Arc::new(Metrics::default());
+    let rate_limits = Arc::new(RateLimitMetrics::registered(&metrics));
+    let (routes, _) = complete_api_routes(rate_limits);
+
+    routes
+        // 添加状态
+        .with_state(state)
+        // 请求指标（在请求 ID 内层，能看到 AppError 的最终状态码）
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::clone(&metrics),
+            track_metrics,
+        ))
+        .layer(Extension(metrics))
+        // 请求 ID（错误响应体中的 request_id）
+        .layer(axum::middleware::from_fn(request_id))
+        // 添加 CORS
+        .layer(CorsLayer::permissive())
+}
+
+// complete_api 的全部路由
+//
+// 通过 RouteRecorder 连同文档一起注册，openapi_spec() 由这里的记录生成
+fn complete_api_routes(rate_limits: Arc<RateLimitMetrics>) -> (Router<ApiState>, Vec<RouteDoc>) {
+    let limiter = Arc::new(RateLimiter::new(
+        API_RATE_LIMIT,
+        Arc::new(SystemClock),
+        rate_limits,
+    ));
+    let user_op = |op: OperationDoc| op.path_param::<u32>("id");
+
+    RouteRecorder::new()
+        // 根路径
+        .route(
+            "/",
+            get(api_root),
+            [OperationDoc::get("API 概览").json::<JsonObject>(200, "名称、版本和端点列表")],
+        )
+        // 用户 CRUD
+        .route(
+            "/users",
+            get(list_users).post(create_user_api),
+            [
+                OperationDoc::get("分页列出用户")
+                    .query::<ListUsersParams>()
+                    .json::<UserPage>(200, "用户分页")
+                    .error(400, "查询参数不合法"),
+                OperationDoc::post("创建用户")
+                    .body::<CreateUserRequest>()
+                    .json::<User>(201, "已创建")
+                    .error(400, "JSON 格式错误")
+                    .error(422, "字段校验失败"),
+            ],
+        )
+        .route(
+            "/users/:id",
+            get(get_user_api)
+                .put(update_user_api)
+                .delete(delete_user_api),
+            [
+                user_op(OperationDoc::get("获取用户"))
+                    .json::<User>(200, "用户")
+                    .error(404, "用户不存在"),
+                user_op(OperationDoc::put("更新用户"))
+                    .body::<UpdateUserRequest>()
+                    .json::<User>(200, "更新后的用户")
+                    .error(404, "用户不存在")
+                    .error(422, "字段校验失败"),
+                user_op(OperationDoc::delete("删除用户"))
+                    .empty(204, "已删除")
+                    .error(404, "用户不存在"),
+            ],
+        )
+        // OpenAPI 文档
+        .route(
+            "/openapi.json",
+            get(openapi_json),
+            [OperationDoc::get("本文档").json::<JsonObject>(200, "OpenAPI 3 文档")],
+        )
+        // 用户变更推送
+        .route(
+            "/ws/users",
+            get(ws_users),
+            [OperationDoc::get("用户变更推送（WebSocket）")
+                .description("升级为 WebSocket 后，每条文本消息是一个 UserEvent JSON")
+                .empty(101, "切换到 WebSocket 协议")
+                .empty(400, "不是 WebSocket 升级请求")],
+        )
+        // 以上路由按客户端限流，之后注册的健康检查和指标不限流
+        .map(|router| {
+            router.route_layer(axum::middleware::from_fn_with_state(
+                limiter,
+                rate_limit_middleware,
+            ))
+        })
+        // 健康检查
+        .route(
+            "/health",
+            get(health_check),
+            [OperationDoc::get("就绪检查")
+                .text(200, "OK")
+                .text(503, "DRAINING，正在优雅关闭")],
+        )
+        // Prometheus 指标
+        .route(
+            "/metrics",
+            get(metrics_handler),
+            [OperationDoc::get("Prometheus 指标").text(200, "Prometheus 文本格式")],
+        )
+        .finish()
+}
+
+// API 根路径 - 端点列表取自 OpenAPI 文档，不再手写
+async fn api_root() -> Json<serde_json::Value> {
+    let spec = openapi_spec();
+    let endpoints: Vec<&String> = spec["paths"]
+        .as_object()
+        .map(|paths| paths.keys().collect())
+        .unwrap_or_default();
+
+    Json(serde_json::json!({
+        "name": spec["info"]["title"],
+        "version": spec["info"]["version"],
+        "openapi": "/openapi.json",
+        "endpoints": endpoints,
+    }))
+}
+
+// 列表查询参数：/users?page=&limit=&sort=name,-id&email_contains=
+//
+// 先按字符串接收，自己校验，才能给出具体是哪个字段出错；
+// schemars 属性给出实际接受的类型，OpenAPI 文档的查询参数由此生成
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub(crate) struct ListUsersParams {
+    // with 换成了非 Option 的类型，serde(default) 让文档中仍然是可选参数
+    #[schemars(
+        with = "u32",
+        range(min = 1),
+        default = "default_page",
+        description = "页码"
+    )]
+    #[serde(default)]
+    page: Option<String>,
+    #[schemars(
+        with = "u32",
+        range(min = 1, max = "MAX_LIMIT"),
+        default = "default_limit",
+        description = "每页数量"
+    )]
+    #[serde(default)]
+    limit: Option<String>,
+    #[schemars(description = "逗号分隔的 id/name/email，前缀 - 表示降序，如 name,-id")]
+    sort: Option<String>,
+    #[schemars(description = "按邮箱子串过滤（不区分大小写）")]
+    email_contains: Option<String>,
+}
+
+const MAX_LIMIT: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UserSortField {
+    Id,
+    Name,
+    Email,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct UserSortKey {
+    field: UserSortField,
+    descending: bool,
+}
+
+// 校验后的查询
+#[derive(Debug)]
+struct ListUsersQuery {
+    pagination: Pagination,
+    sort: Vec<UserSortKey>,
+    email_contains: Option<String>,
+}
+
+impl ListUsersQuery {
+    fn parse(params: ListUsersParams) -> Result<ListUsersQuery, AppError> {
+        let page = parse_bounded("page", params.page, default_page(), 1, u32::MAX)?;
+        let limit = parse_bounded("limit", params.limit, default_limit(), 1, MAX_LIMIT)?;
+
+        let mut sort = Vec::new();
+        if let Some(raw) = params.sort.as_deref().filter(|s| !s.is_empty()) {
+            for key in raw.split(',') {
+                let (descending, name) = match key.strip_prefix('-') {
+                    Some(name) => (true, name),
+                    None => (false, key),
+                };
+                let field = match name {
+                    "id" => UserSortField::Id,
+                    "name" => UserSortField::Name,
+                    "email" => UserSortField::Email,
+                    _ => {
+                        return Err(invalid_query(
+                            "sort",
+                            format!(
+                                "不支持的排序字段 '{}'，可选 id、name、email，前缀 - 表示降序",
+                                key
+                            ),
+                        ))
+                    }
+                };
+                if sort.iter().any(|k: &UserSortKey| k.field == field) {
+                    return Err(invalid_query("sort", format!("排序字段 '{}' 重复", name)));
+                }
+                sort.push(UserSortKey { field, descending });
+            }
+        }
+
+        Ok(ListUsersQuery {
+            pagination: Pagination { page, limit },
+            sort,
+            email_contains: params.email_contains.filter(|s| !s.is_empty()),
+        })
+    }
+
+    // 过滤、排序（最后总是按 id 升序兜底，保证顺序稳定）
+    fn apply(&self, mut users: Vec<User>) -> Vec<User> {
+        if let Some(needle) = &self.email_contains {
+            let needle = needle.to_lowercase();
+            users.retain(|u| u.email.to_lowercase().contains(&needle));
+        }
+
+        users.sort_by(|a, b| {
+            self.sort
+                .iter()
+                .map(|key| {
+                    let ord = match key.field {
+                        UserSortField::Id => a.id.cmp(&b.id),
+                        UserSortField::Name => a.name.cmp(&b.name),
+                        UserSortField::Email => a.email.cmp(&b.email),
+                    };
+                    if key.descending {
+                        ord.reverse()
+                    } else {
+                        ord
+                    }
+                })
+                .find(|ord| ord.is_ne())
+                .unwrap_or_else(|| a.id.cmp(&b.id))
+        });
+        users
+    }
+
+    // 生成指向某一页的链接，保留排序和过滤条件
+    fn link(&self, page: u32) -> String {
+        let mut link = format!("/users?page={}&limit={}", page, self.pagination.limit);
+        if !self.sort.is_empty() {
+            let sort: Vec<String> = self
+                .sort
+                .iter()
+                .map(|key| {
+                    let name = match key.field {
+                        UserSortField::Id => "id",
+                        UserSortField::Name => "name",
+                        UserSortField::Email => "email",
+                    };
+                    format!("{}{}", if key.descending { "-" } else { "" }, name)
+                })
+                .collect();
+            link.push_str(&format!("&sort={}", sort.join(",")));
+        }
+        if let Some(needle) = &self.email_contains {
+            link.push_str(&format!("&email_contains={}", percent_encode(needle)));
+        }
+        link
+    }
+}
+
+fn parse_bounded(
+    field: &'static str,
+    raw: Option<String>,
+    default: u32,
+    min: u32,
+    max: u32,
+) -> Result<u32, AppError> {
+    let value = match raw.as_deref() {
+        None | Some("") => return Ok(default),
+        Some(raw) => raw
+            .parse::<u32>()
+            .map_err(|_| invalid_query(field, format!("'{}' 不是有效的正整数", raw)))?,
+    };
+    if value < min || value > max {
+        return Err(invalid_query(
+            field,
+            format!("取值范围为 {}..={}，实际为 {}", min, max, value),
+        ));
+    }
+    Ok(value)
+}
+
+// 查询字符串中的值编码（保留 RFC 3986 非保留字符）
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// 分页响应
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct UserPage {
+    data: Vec<User>,
+    total: usize,
+    #[schemars(range(min = 1))]
+    page: u32,
+    #[schemars(range(min = 1, max = "MAX_LIMIT"))]
+    limit: u32,
+    links: PageLinks,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct PageLinks {
+    #[serde(rename = "self")]
+    current: String,
+    next: Option<String>,
+    prev: Option<String>,
+}
+
+fn invalid_query(field: &'static str, message: String) -> AppError {
+    AppError::InvalidQuery { field, message }
+}
+
+// 列出用户（分页、排序、过滤）
+async fn list_users(
+    State(state): State<ApiState>,
+    Query(params): Query<ListUsersParams>,
+) -> Result<Json<UserPage>, AppError> {
+    let users = state.users.list().await?;
+    Ok(Json(list_users_page(params, users)?))
+}
+
+// 校验查询参数并生成一页结果；原始 socket 版本的 /users 也走这里
+pub(crate) fn list_users_page(
+    params: ListUsersParams,
+    users: Vec<User>,
+) -> Result<UserPage, AppError> {
+    let query = ListUsersQuery::parse(params)?;
+    let users = query.apply(users);
+
+    let Pagination { page, limit } = query.pagination;
+    let total = users.len();
+    let start = (page as usize - 1).saturating_mul(limit as usize);
+    let data: Vec<User> = users.into_iter().skip(start).take(limit as usize).collect();
+
+    let has_next = start.saturating_add(limit as usize) < total;
+    let links = PageLinks {
+        current: query.link(page),
+        next: has_next.then(|| query.link(page + 1)),
+        prev: (page > 1).then(|| query.link(page - 1)),
+    };
+
+    Ok(UserPage {
+        data,
+        total,
+        page,
+        limit,
+        links,
+    })
+}
+
+// 创建用户
+async fn create_user_api(
+    State(state): State<ApiState>,
+    ValidatedJson(payload): ValidatedJson<CreateUserRequest>,
+) -> Result<(StatusCode, Json<User>), AppError> {
+    let events = state.events.lock().await;
+    let user = state.users.create(payload).await?;
+    events.publish(UserEvent::Created { user: user.clone() });
+
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+// 获取单个用户
+async fn get_user_api(
+    State(state): State<ApiState>,
+    Path(id): Path<u32>,
+) -> Result<Json<User>, AppError> {
+    state
+        .users
+        .get(id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| user_not_found(id))
+}
+
+// 更新用户
+async fn update_user_api(
+    State(state): State<ApiState>,
+    Path(id): Path<u32>,
+    ValidatedJson(payload): ValidatedJson<UpdateUserRequest>,
+) -> Result<Json<User>, AppError> {
+    let events = state.events.lock().await;
+    let user = state
+        .users
+        .update(id, payload)
+        .await?
+        .ok_or_else(|| user_not_found(id))?;
+    events.publish(UserEvent::Updated { user: user.clone() });
+
+    Ok(Json(user))
+}
+
+// 删除用户
+async fn delete_user_api(
+    State(state): State<ApiState>,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, AppError> {
+    let events = state.events.lock().await;
+    if state.users.delete(id).await? {
+        events.publish(UserEvent::Deleted { id });
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(user_not_found(id))
+    }
+}
+
+fn user_not_found(id: u32) -> AppError {
+    AppError::NotFound(format!("用户 {} 不存在", id))
+}
+
+// ============================================
+// 10. 优雅关闭
+// ============================================
+
+// 关闭配置
+#[derive(Debug, Clone)]
+struct ShutdownConfig {
+    // 停止接受连接后，等待进行中请求完成的最长时间
+    drain_timeout: Duration,
+    // 触发关闭后 /health 先返回 503，继续接受连接这么久，
+    // 让负载均衡有时间把实例摘掉
+    readiness_delay: Duration,
+    // 是否监听 Ctrl+C
+    ctrl_c: bool,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            drain_timeout: Duration::from_secs(30),
+            readiness_delay: Duration::ZERO,
+            ctrl_c: true,
+        }
+    }
+}
+
+// 就绪状态，通过 Extension 注入给 health_check 和长连接处理器
+#[derive(Clone)]
+struct Readiness(Arc<watch::Sender<bool>>);
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Readiness(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl Readiness {
+    fn is_draining(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    fn set_draining(&self) {
+        self.0.send_replace(true);
+    }
+
+    // 等到开始关闭；WebSocket 这类不会自己结束的连接据此退出，否则排空一定超时
+    async fn draining(&self) {
+        let mut rx = self.0.subscribe();
+        let _ = rx.wait_for(|draining| *draining).await;
+    }
+}
+
+// 关闭结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShutdownOutcome {
+    // 所有进行中的请求都已完成
+    Drained,
+    // 超过 drain_timeout，剩余连接连同其中的请求被中止
+    DeadlineExceeded,
+}
+
+// 服务器句柄
+//
+// 关闭流程:
+//   1. Ctrl+C 或 shutdown() 触发
+//   2. /health 变为 503，等待 readiness_delay
+//   3. 停止接受新连接，进行中的请求继续处理
+//   4. 全部完成后返回；超过 drain_timeout 时中止剩余连接再返回
+struct ServerHandle {
+    local_addr: SocketAddr,
+    readiness: Readiness,
+    trigger: watch::Sender<bool>,
+    task: JoinHandle<std::io::Result<ShutdownOutcome>>,
+}
+
+impl ServerHandle {
+    fn start(
+        listener: tokio::net::TcpListener,
+        app: Router,
+        config: ShutdownConfig,
+    ) -> std::io::Result<ServerHandle> {
+        let local_addr = listener.local_addr()?;
+        let readiness = Readiness::default();
+        let (trigger, trigger_rx) = watch::channel(false);
+
+        let app = app.layer(Extension(readiness.clone()));
+        let task = tokio::spawn(run_until_shutdown(
+            listener,
+            app,
+            config,
+            readiness.clone(),
+            trigger_rx,
+        ));
+
+        Ok(ServerHandle {
+            local_addr,
+            readiness,
+            trigger,
+            task,
+        })
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+
+    // 以编程方式触发关闭
+    fn shutdown(&self) {
+        let _ = self.trigger.send(true);
+    }
+
+    // 等待服务器退出
+    async fn wait(self) -> std::io::Result<ShutdownOutcome> {
+        match self.task.await {
+            Ok(result) => result,
+            Err(e) => Err(std::io::Error::other(e)),
+        }
+    }
+}
+
+async fn run_until_shutdown(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    config: ShutdownConfig,
+    readiness: Readiness,
+    mut trigger: watch::Receiver<bool>,
+) -> std::io::Result<ShutdownOutcome> {
+    // 不用 axum::serve：它不暴露连接任务，超过 drain_timeout 后没法中止还在处理的请求
+    let graceful = GracefulShutdown::new();
+    let mut connections = JoinSet::new();
+
+    let ctrl_c = async {
+        if config.ctrl_c {
+            let _ = tokio::signal::ctrl_c().await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+    let triggered = async {
+        tokio::select! {
+            _ = trigger.wait_for(|stop| *stop) => {}
+            _ = ctrl_c => {}
+        }
+    };
+    accept_until(&listener, &app, &graceful, &mut connections, triggered).await;
+
+    readiness.set_draining();
+    let delay = tokio::time::sleep(config.readiness_delay);
+    accept_until(&listener, &app, &graceful, &mut connections, delay).await;
+    drop(listener);
+
+    // 通知所有连接处理完当前请求后关闭，超时的连接连同其中的请求一起中止
+    match tokio::time::timeout(config.drain_timeout, graceful.shutdown()).await {
+        Ok(()) => Ok(ShutdownOutcome::Drained),
+        Err(_) => {
+            connections.shutdown().await;
+            Ok(ShutdownOutcome::DeadlineExceeded)
+        }
+    }
+}
+
+// 接受连接直到 stop 完成，每个连接在 connections 中单独运行
+async fn accept_until(
+    listener: &tokio::net::TcpListener,
+    app: &Router,
+    graceful: &GracefulShutdown,
+    connections: &mut JoinSet<()>,
+    stop: impl std::future::Future<Output = ()>,
+) {
+    use axum::extract::ConnectInfo;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto;
+    use hyper_util::service::TowerToHyperService;
+    use tower::ServiceExt;
+
+    tokio::pin!(stop);
+    loop {
+        let (stream, peer) = tokio::select! {
+            _ = &mut stop => return,
+            // 顺便回收已经结束的连接
+            Some(_) = connections.join_next() => continue,
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // 文件描述符耗尽这类错误会立即重现，稍等再试
+                    eprintln!("接受连接失败: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+        };
+
+        // 带上 ConnectInfo，限流等中间件可以拿到客户端地址
+        let service = app.clone().map_request(
+            move |mut request: axum::http::Request<hyper::body::Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                request
+            },
+        );
+        let connection = auto::Builder::new(TokioExecutor::new())
+            .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(service))
+            .into_owned();
+        let connection = graceful.watch(connection);
+        connections.spawn(async move {
+            let _ = connection.await;
+        });
+    }
+}
+
+// ============================================
+// 11. 请求校验
+// ============================================
+
+// 单个字段的校验错误
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub(crate) struct FieldError {
+    field: String,
+    reason: String,
+}
+
+// 校验规则收集器，复用 types::regex 中的 Validator
+struct Rules {
+    errors: Vec<FieldError>,
+}
+
+impl Rules {
+    fn check(&mut self, field: &str, ok: bool, reason: &str) -> &mut Self {
+        if !ok {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+        self
+    }
+
+    fn username(&mut self, field: &str, value: &str) -> &mut Self {
+        let ok = validator().validate_username(value);
+        self.check(field, ok, "必须是 3-20 位字母、数字或下划线")
+    }
+
+    fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        let ok = validator().validate_email(value);
+        self.check(field, ok, "不是有效的邮箱地址")
+    }
+}
+
+fn validator() -> &'static crate::types::regex_examples::Validator {
+    static VALIDATOR: std::sync::OnceLock<crate::types::regex_examples::Validator> =
+        std::sync::OnceLock::new();
+    VALIDATOR.get_or_init(Default::default)
+}
+
+// 需要校验的请求体实现这个 trait，在 rules 中声明规则
+trait Validate {
+    fn rules(&self, rules: &mut Rules);
+
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut rules = Rules { errors: Vec::new() };
+        self.rules(&mut rules);
+        if rules.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(rules.errors)
+        }
+    }
+}
+
+impl Validate for CreateUserRequest {
+    fn rules(&self, rules: &mut Rules) {
+        rules
+            .username("name", &self.name)
+            .email("email", &self.email);
+    }
+}
+
+impl Validate for UpdateUserRequest {
+    fn rules(&self, rules: &mut Rules) {
+        // 只校验提供了的字段
+        if let Some(name) = &self.name {
+            rules.username("name", name);
+        }
+        if let Some(email) = &self.email {
+            rules.email("email", email);
+        }
+        rules.check(
+            "body",
+            self.name.is_some() || self.email.is_some(),
+            "至少需要提供 name 或 email",
+        );
+    }
+}
+
+// 先按 Json 解析，再执行声明的规则
+//
+// JSON 格式错误 -> 400，规则不通过 -> 422
+struct ValidatedJson<T>(T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequest<S> for ValidatedJson<T>
+where
+    T: serde::de::DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+
+        value.validate().map_err(AppError::Validation)?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+// ============================================
+// 12. 限流 (令牌桶)
+// ============================================
+
+// 时钟抽象，测试中用手动推进的时钟代替真实时间
+trait Clock: Send + Sync {
+    fn now(&self) -> std::time::Instant;
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> std::time::Instant {
+        std::time::Instant::now()
+    }
+}
+
+// 每个路由组一份配置
+#[derive(Debug, Clone, Copy)]
+struct RateLimitConfig {
+    // 桶容量，即允许的突发请求数
+    capacity: u32,
+    // 每秒补充的令牌数
+    refill_per_sec: f64,
+}
+
+// 限流统计，多个路由组共享
+#[derive(Debug, Default)]
+struct RateLimitMetrics {
+    allowed: Counter,
+    rejected: Counter,
+}
+
+impl RateLimitMetrics {
+    // 计数器注册到指标表中，随 /metrics 一起导出
+    fn registered(metrics: &Metrics) -> Self {
+        RateLimitMetrics {
+            allowed: metrics.counter("rate_limit_allowed_total", "限流放行的请求数"),
+            rejected: metrics.counter("rate_limit_rejected_total", "被限流拒绝 (429) 的请求数"),
+        }
+    }
+
+    fn allowed(&self) -> u64 {
+        self.allowed.get()
+    }
+
+    fn rejected(&self) -> u64 {
+        self.rejected.get()
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: std::time::Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RateDecision {
+    Allowed {
+        remaining: u32,
+        // 桶重新装满还需要多久
+        reset: Duration,
+    },
+    Limited {
+        // 下一个令牌还需要多久
+        retry_after: Duration,
+    },
+}
+
+// complete_api 的限流：每个客户端突发 100 次，每秒补 50 个
+const API_RATE_LIMIT: RateLimitConfig = RateLimitConfig {
+    capacity: 100,
+    refill_per_sec: 50.0,
+};
+
+// 桶的数量超过这个值时清理已经装满的桶，避免按客户端无限增长
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+#[derive(Debug)]
+struct Buckets {
+    by_client: HashMap<String, TokenBucket>,
+    // 下一次清理的阈值：清理后设为剩余数量的两倍，均摊到每个请求是 O(1)
+    sweep_at: usize,
+}
+
+struct RateLimiter {
+    config: RateLimitConfig,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<RateLimitMetrics>,
+    // 用来识别已认证的客户端，没有时只按 IP 限流
+    keys: Option<Arc<KeyStore>>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    fn new(config: RateLimitConfig, clock: Arc<dyn Clock>, metrics: Arc<RateLimitMetrics>) -> Self {
+        // 补充速率为 0 时桶永远不会恢复，计算等待时间也会溢出
+        assert!(
+            config.refill_per_sec.is_finite() && config.refill_per_sec > 0.0,
+            "refill_per_sec 必须是正数"
+        );
+        assert!(config.capacity > 0, "capacity 必须大于 0");
+
+        RateLimiter {
+            config,
+            clock,
+            metrics,
+            keys: None,
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                sweep_at: MAX_IDLE_BUCKETS,
+            }),
+        }
+    }
+
+    // 凭证有效的请求按调用方单独计数
+    fn with_keys(mut self, keys: Arc<KeyStore>) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    // 取一个令牌
+    fn check(&self, client: &str) -> RateDecision {
+        let now = self.clock.now();
+        let capacity = self.config.capacity as f64;
+        let rate = self.config.refill_per_sec;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.by_client.len() > buckets.sweep_at {
+            buckets.by_client.retain(|_, b| {
+                b.tokens + now.saturating_duration_since(b.updated).as_secs_f64() * rate < capacity
+            });
+            buckets.sweep_at = MAX_IDLE_BUCKETS.max(buckets.by_client.len() * 2);
+        }
+
+        let bucket = buckets
+            .by_client
+            .entry(client.to_string())
+            .or_insert(TokenBucket {
+                tokens: capacity,
+                updated: now,
+            });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            self.metrics.allowed.inc();
+            RateDecision::Allowed {
+                remaining: bucket.tokens.floor() as u32,
+                reset: Duration::from_secs_f64((capacity - bucket.tokens) / rate),
+            }
+        } else {
+            self.metrics.rejected.inc();
+            RateDecision::Limited {
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / rate),
+            }
+        }
+    }
+
+    // 客户端标识：优先使用已认证的调用方，否则使用对端 IP
+    //
+    // 不能直接用请求头里的 X-API-Key：随便换一个 Key 就能拿到新的桶，绕过按 IP 的限制。
+    // 拿不到对端地址时返回 None：所有匿名客户端共用一个桶的话，一个客户端就能让所有人 429
+    fn client_key(&self, req: &axum::extract::Request) -> Option<String> {
+        let principal = req.extensions().get::<Principal>().cloned().or_else(|| {
+            self.keys
+                .as_ref()
+                .and_then(|keys| keys.authenticate(req.headers()).ok())
+        });
+        if let Some(principal) = principal {
+            return Some(format!("principal:{}", principal.name));
+        }
+        req.extensions()
+            .get::<axum::extract::ConnectInfo<SocketAddr>>()
+            .map(|info| format!("ip:{}", info.0.ip()))
+    }
+}
+
+// 路由组中间件：.layer(from_fn_with_state(limiter, rate_limit_middleware))
+//
+// 按 IP 限流需要 ConnectInfo，服务要用 into_make_service_with_connect_info 启动；
+// 缺少时拒绝请求（500）而不是放行，部署错误会立刻暴露出来
+async fn rate_limit_middleware(
+    State(limiter): State<Arc<RateLimiter>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let Some(key) = limiter.client_key(&req) else {
+        return AppError::InternalError(
+            "限流需要 ConnectInfo<SocketAddr>，请用 into_make_service_with_connect_info 启动服务"
+                .to_string(),
+        )
+        .into_response();
+    };
+
+    let limit = limiter.config.capacity;
+    let (mut response, remaining, reset) = match limiter.check(&key) {
+        RateDecision::Allowed { remaining, reset } => (next.run(req).await, remaining, reset),
+        RateDecision::Limited { retry_after } => {
+            let response = AppError::RateLimited { retry_after }.into_response();
+            (response, 0, retry_after)
+        }
+    };
+
+    let headers = response.headers_mut();
+    headers.insert("x-ratelimit-limit", HeaderValue::from(limit));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from(remaining));
+    headers.insert(
+        "x-ratelimit-reset",
+        HeaderValue::from(reset.as_secs_f64().ceil() as u64),
+    );
+    response
+}
+
+// 按路由组配置不同的限流
+fn rate_limited_routes(
+    clock: Arc<dyn Clock>,
+    metrics: Arc<RateLimitMetrics>,
+    keys: KeyStore,
+) -> Router {
+    let keys = Arc::new(keys);
+    // 搜索开销大：突发 5 次，每秒补 1 个
+    let search = Arc::new(
+        RateLimiter::new(
+            RateLimitConfig {
+                capacity: 5,
+                refill_per_sec: 1.0,
+            },
+            Arc::clone(&clock),
+            Arc::clone(&metrics),
+        )
+        .with_keys(Arc::clone(&keys)),
+    );
+    // 普通 API：突发 50 次，每秒补 10 个
+    let api = Arc::new(
+        RateLimiter::new(
+            RateLimitConfig {
+                capacity: 50,
+                refill_per_sec: 10.0,
+            },
+            clock,
+            metrics,
+        )
+        .with_keys(keys),
+    );
+
+    let search_routes = Router::new().route("/search", get(search_handler)).layer(
+        axum::middleware::from_fn_with_state(search, rate_limit_middleware),
+    );
+    let api_routes = Router::new()
+        .route("/items", get(get_items).post(create_item))
+        .route("/items/:id", get(get_item))
+        .layer(axum::middleware::from_fn_with_state(
+            api,
+            rate_limit_middleware,
+        ));
+
+    Router::new()
+        // 健康检查不限流
+        .route("/health", get(health_check))
+        .merge(search_routes)
+        .nest("/api", api_routes)
+        .layer(axum::middleware::from_fn(request_id))
+}
+
+// ============================================
+// 13. 指标 (Prometheus)
+// ============================================
+
+// 延迟直方图的桶边界（秒），与 Prometheus 客户端库默认值一致
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    // 每个桶的非累计计数，渲染时再累加
+    counts: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| value <= *le) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+// 单个路由的统计
+#[derive(Debug, Default)]
+struct RouteStats {
+    by_status: std::collections::BTreeMap<u16, u64>,
+    latency: Histogram,
+}
+
+// 自定义计数器，克隆后共享同一个值
+#[derive(Debug, Clone, Default)]
+struct Counter(Arc<std::sync::atomic::AtomicU64>);
+
+impl Counter {
+    // 加一并返回新值
+    fn inc(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// 指标注册表
+#[derive(Debug, Default)]
+struct Metrics {
+    in_flight: std::sync::atomic::AtomicI64,
+    // (路由, 方法) -> 统计
+    routes: Mutex<std::collections::BTreeMap<(String, String), RouteStats>>,
+    // 状态码 >= 400 的响应数
+    errors: Mutex<std::collections::BTreeMap<u16, u64>>,
+    // 名称 -> (说明, 计数器)
+    counters: Mutex<std::collections::BTreeMap<String, (String, Counter)>>,
+}
+
+impl Metrics {
+    // 注册（或取回已注册的）计数器
+    fn counter(&self, name: &str, help: &str) -> Counter {
+        self.counters
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| (help.to_string(), Counter::default()))
+            .1
+            .clone()
+    }
+
+    fn observe(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        {
+            let mut routes = self.routes.lock().unwrap();
+            let stats = routes
+                .entry((route.to_string(), method.to_string()))
+                .or_default();
+            *stats.by_status.entry(status).or_insert(0) += 1;
+            stats.latency.observe(elapsed.as_secs_f64());
+        }
+        if status >= 400 {
+            *self.errors.lock().unwrap().entry(status).or_insert(0) += 1;
+        }
+    }
+
+    // Prometheus 文本格式 (0.0.4)
+    fn render(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let routes = self.routes.lock().unwrap();
+
+        out.push_str("# HELP http_requests_total 按路由、方法和状态码统计的请求数\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, method), stats) in routes.iter() {
+            for (status, count) in &stats.by_status {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                    escape_label(method),
+                    escape_label(route),
+                    status,
+                    count
+                );
+            }
+        }
+
+        out.push_str("# HELP http_request_duration_seconds 请求处理耗时\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((route, method), stats) in routes.iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\"",
+                escape_label(method),
+                escape_label(route)
+            );
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(stats.latency.counts) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, stats.latency.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, stats.latency.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, stats.latency.count
+            );
+        }
+        drop(routes);
+
+        out.push_str("# HELP http_requests_in_flight 正在处理的请求数\n");
+        out.push_str("# TYPE http_requests_in_flight gauge\n");
+        let _ = writeln!(
+            out,
+            "http_requests_in_flight {}",
+            self.in_flight.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP http_errors_total 按状态码统计的错误响应数\n");
+        out.push_str("# TYPE http_errors_total counter\n");
+        for (status, count) in self.errors.lock().unwrap().iter() {
+            let _ = writeln!(out, "http_errors_total{{status=\"{}\"}} {}", status, count);
+        }
+
+        for (name, (help, counter)) in self.counters.lock().unwrap().iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.get());
+        }
+
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// 方法标签只用标准方法，客户端可以发送任意方法名，原样记录会让标签数量无限增长
+fn method_label(method: &axum::http::Method) -> &'static str {
+    use axum::http::Method;
+
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => "other",
+    }
+}
+
+// 请求结束（包括被取消）时减少 in-flight 计数
+struct InFlightGuard(Arc<Metrics>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// 指标中间件：用 MatchedPath 作为路由标签，避免 /users/1、/users/2 各算一条
+async fn track_metrics(
+    State(metrics): State<Arc<Metrics>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = method_label(req.method());
+
+    metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _guard = InFlightGuard(Arc::clone(&metrics));
+
+    let start = std::time::Instant::now();
+    let response = next.run(req).await;
+    metrics.observe(method, &route, response.status().as_u16(), start.elapsed());
+    response
+}
+
+async fn metrics_handler(Extension(metrics): Extension<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        metrics.render(),
+    )
+}
+
+// ============================================
+// 14. OpenAPI 文档
+// ============================================
+
+// 任意 JSON 对象（文档中为 type: object）
+type JsonObject = serde_json::Map<String, serde_json::Value>;
+
+// 延迟生成 Schema 的函数，如 schema_ref::<User>；生成文档时才拿到 SchemaGenerator
+type SchemaFn = fn(&mut schemars::gen::SchemaGenerator) -> serde_json::Value;
+
+// 一个响应的文档
+enum ResponseDoc {
+    Json(u16, &'static str, SchemaFn),
+    Text(u16, &'static str),
+    Empty(u16, &'static str),
+}
+
+// 一个接口（路径 + 方法）的文档，和路由一起注册
+struct OperationDoc {
+    method: &'static str,
+    summary: &'static str,
+    description: Option<&'static str>,
+    path_params: Vec<(&'static str, SchemaFn)>,
+    // 查询参数结构体，每个字段生成一个参数
+    query: Option<SchemaFn>,
+    body: Option<SchemaFn>,
+    responses: Vec<ResponseDoc>,
+}
+
+impl OperationDoc {
+    fn new(method: &'static str, summary: &'static str) -> Self {
+        OperationDoc {
+            method,
+            summary,
+            description: None,
+            path_params: Vec::new(),
+            query: None,
+            body: None,
+            responses: Vec::new(),
+        }
+    }
+
+    fn get(summary: &'static str) -> Self {
+        OperationDoc::new("get", summary)
+    }
+
+    fn post(summary: &'static str) -> Self {
+        OperationDoc::new("post", summary)
+    }
+
+    fn put(summary: &'static str) -> Self {
+        OperationDoc::new("put", summary)
+    }
+
+    fn delete(summary: &'static str) -> Self {
+        OperationDoc::new("delete", summary)
+    }
+
+    fn description(mut self, description: &'static str) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    fn path_param<T: JsonSchema>(mut self, name: &'static str) -> Self {
+        self.path_params.push((name, inline_schema::<T>));
+        self
+    }
+
+    fn query<T: JsonSchema>(mut self) -> Self {
+        self.query = Some(inline_schema::<T>);
+        self
+    }
+
+    fn body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some(schema_ref::<T>);
+        self
+    }
+
+    fn json<T: JsonSchema>(mut self, status: u16, description: &'static str) -> Self {
+        self.responses
+            .push(ResponseDoc::Json(status, description, schema_ref::<T>));
+        self
+    }
+
+    // 错误响应统一是 ErrorBody
+    fn error(self, status: u16, description: &'static str) -> Self {
+        self.json::<ErrorBody>(status, description)
+    }
+
+    fn text(mut self, status: u16, description: &'static str) -> Self {
+        self.responses.push(ResponseDoc::Text(status, description));
+        self
+    }
+
+    fn empty(mut self, status: u16, description: &'static str) -> Self {
+        self.responses.push(ResponseDoc::Empty(status, description));
+        self
+    }
+
+    fn to_openapi(&self, schemas: &mut schemars::gen::SchemaGenerator) -> serde_json::Value {
+        let mut parameters: Vec<serde_json::Value> = self
+            .path_params
+            .iter()
+            .map(|(name, schema)| {
+                serde_json::json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": schema(schemas),
+                })
+            })
+            .collect();
+        if let Some(query) = self.query {
+            parameters.extend(query_params(query(schemas)));
+        }
+
+        let responses: serde_json::Map<String, serde_json::Value> = self
+            .responses
+            .iter()
+            .map(|response| match *response {
+                ResponseDoc::Json(status, description, schema) => (
+                    status.to_string(),
+                    serde_json::json!({
+                        "description": description,
+                        "content": { "application/json": { "schema": schema(schemas) } },
+                    }),
+                ),
+                ResponseDoc::Text(status, description) => (
+                    status.to_string(),
+                    serde_json::json!({
+                        "description": description,
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    }),
+                ),
+                ResponseDoc::Empty(status, description) => (
+                    status.to_string(),
+                    serde_json::json!({ "description": description }),
+                ),
+            })
+            .collect();
+
+        let mut operation = serde_json::json!({
+            "summary": self.summary,
+            "responses": responses,
+        });
+        if let Some(description) = self.description {
+            operation["description"] = description.into();
+        }
+        if !parameters.is_empty() {
+            operation["parameters"] = parameters.into();
+        }
+        if let Some(body) = self.body {
+            operation["requestBody"] = serde_json::json!({
+                "required": true,
+                "content": { "application/json": { "schema": body(schemas) } },
+            });
+        }
+        operation
+    }
+}
+
+// 结构体的每个字段一个查询参数，字段的 description 提到参数上
+fn query_params(schema: serde_json::Value) -> Vec<serde_json::Value> {
+    let required = schema["required"].as_array().cloned().unwrap_or_default();
+    let Some(properties) = schema["properties"].as_object() else {
+        return Vec::new();
+    };
+
+    properties
+        .iter()
+        .map(|(name, property)| {
+            let mut property = property.clone();
+            let description = property.as_object_mut().and_then(|p| {
+                // 查询参数只有缺省，没有 null
+                p.remove("nullable");
+                p.remove("description")
+            });
+            let mut param = serde_json::json!({
+                "name": name,
+                "in": "query",
+                "required": required.contains(&serde_json::Value::from(name.as_str())),
+                "schema": property,
+            });
+            if let Some(description) = description {
+                param["description"] = description;
+            }
+            param
+        })
+        .collect()
+}
+
+// 注册过的路由和它的文档
+struct RouteDoc {
+    path: &'static str,
+    operations: Vec<OperationDoc>,
+}
+
+// 注册路由的同时记录文档，openapi_spec() 的 paths 由这些记录生成
+struct RouteRecorder<S> {
+    router: Router<S>,
+    routes: Vec<RouteDoc>,
+}
+
+impl<S> RouteRecorder<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn new() -> Self {
+        RouteRecorder {
+            router: Router::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    fn route(
+        mut self,
+        path: &'static str,
+        method_router: axum::routing::MethodRouter<S>,
+        operations: impl IntoIterator<Item = OperationDoc>,
+    ) -> Self {
+        self.routes.push(RouteDoc {
+            path,
+            operations: operations.into_iter().collect(),
+        });
+        self.router = self.router.route(path, method_router);
+        self
+    }
+
+    // 包装已经注册的路由（如 route_layer），之后注册的路由不受影响
+    fn map(mut self, f: impl FnOnce(Router<S>) -> Router<S>) -> Self {
+        self.router = f(self.router);
+        self
+    }
+
+    fn finish(self) -> (Router<S>, Vec<RouteDoc>) {
+        (self.router, self.routes)
+    }
+}
+
+// complete_api 的 OpenAPI 3 文档
+fn openapi_spec() -> &'static serde_json::Value {
+    static SPEC: std::sync::OnceLock<serde_json::Value> = std::sync::OnceLock::new();
+    SPEC.get_or_init(build_openapi_spec)
+}
+
+// 类型的 Schema 由 schemars 从定义推导：第一次引用时写入 components，返回 $ref
+fn schema_ref<T: JsonSchema>(schemas: &mut schemars::gen::SchemaGenerator) -> serde_json::Value {
+    serde_json::to_value(schemas.subschema_for::<T>()).unwrap()
+}
+
+// 不写入 components，直接展开（路径参数、查询参数结构体）
+fn inline_schema<T: JsonSchema>(schemas: &mut schemars::gen::SchemaGenerator) -> serde_json::Value {
+    serde_json::to_value(T::json_schema(schemas)).unwrap()
+}
+
+fn build_openapi_spec() -> serde_json::Value {
+    let mut schemas = schemars::gen::SchemaSettings::openapi3().into_generator();
+    // 只通过 WebSocket 推送、没有出现在任何响应里的类型也写进 components
+    schema_ref::<UserEvent>(&mut schemas);
+
+    let (_, routes) = complete_api_routes(Arc::new(RateLimitMetrics::default()));
+    let mut paths = serde_json::Map::new();
+    for route in routes {
+        let item = paths
+            .entry(openapi_path(route.path))
+            .or_insert_with(|| serde_json::json!({}));
+        for operation in &route.operations {
+            item[operation.method] = operation.to_openapi(&mut schemas);
+        }
+    }
+
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": { "title": "User API", "version": "1.0.0" },
+        "paths": paths,
+        "components": { "schemas": schemas.take_definitions() },
+    })
+}
+
+// axum 路径 /users/:id -> OpenAPI 路径 /users/{id}
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|seg| match seg.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => seg.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+async fn openapi_json() -> Json<serde_json::Value> {
+    Json(openapi_spec().clone())
+}
+
+// ============================================
+// 15. WebSocket 用户变更推送
+// ============================================
+
+// 每个订阅者最多缓存的事件数，超出后最旧的事件被丢弃
+const USER_EVENTS_CAPACITY: usize = 256;
+
+// 推送给 /ws/users 的事件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum UserEvent {
+    Created { user: User },
+    Updated { user: User },
+    Deleted { id: u32 },
+    // 订阅者太慢，有 skipped 个事件被丢弃
+    Lagged { skipped: u64 },
+}
+
+// 用户变更的广播通道
+#[derive(Clone)]
+struct UserEvents {
+    sender: broadcast::Sender<UserEvent>,
+    // 串行化“修改 + 发布”；修改要写文件，用 tokio 的锁，等锁的请求不占住运行时线程
+    order: Arc<tokio::sync::Mutex<()>>,
+}
+
+struct UserEventsGuard<'a> {
+    sender: &'a broadcast::Sender<UserEvent>,
+    _order: tokio::sync::MutexGuard<'a, ()>,
+}
+
+impl UserEventsGuard<'_> {
+    // 没有订阅者时事件直接丢弃
+    fn publish(&self, event: UserEvent) {
+        let _ = self.sender.send(event);
+    }
+}
+
+impl UserEvents {
+    fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        UserEvents {
+            sender,
+            order: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    // 在修改存储之前加锁，修改完成后通过返回的 guard 发布事件；
+    // 并发修改的事件顺序因此与存储中的修改顺序一致
+    async fn lock(&self) -> UserEventsGuard<'_> {
+        UserEventsGuard {
+            sender: &self.sender,
+            _order: self.order.lock().await,
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<UserEvent> {
+        self.sender.subscribe()
+    }
+}
+
+// 下一个要推送的事件；落后时返回 Lagged，通道关闭时返回 None
+async fn next_user_event(rx: &mut broadcast::Receiver<UserEvent>) -> Option<UserEvent> {
+    match rx.recv().await {
+        Ok(event) => Some(event),
+        Err(broadcast::error::RecvError::Lagged(skipped)) => Some(UserEvent::Lagged { skipped }),
+        Err(broadcast::error::RecvError::Closed) => None,
+    }
+}
+
+async fn ws_users(
+    ws: axum::extract::ws::WebSocketUpgrade,
+    State(state): State<ApiState>,
+    readiness: Option<Extension<Readiness>>,
+) -> Response {
+    // 在握手前订阅，握手完成后发生的变更都不会漏掉
+    let rx = state.events.subscribe();
+    let readiness = readiness.map(|Extension(readiness)| readiness);
+    ws.on_upgrade(move |socket| stream_user_events(socket, rx, readiness))
+}
+
+async fn stream_user_events(
+    mut socket: axum::extract::ws::WebSocket,
+    mut rx: broadcast::Receiver<UserEvent>,
+    readiness: Option<Readiness>,
+) {
+    use axum::extract::ws::{CloseFrame, Message};
+
+    // 不由 ServerHandle 启动时没有关闭信号
+    let draining = async {
+        match readiness {
+            Some(readiness) => readiness.draining().await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(draining);
+
+    loop {
+        tokio::select! {
+            _ = &mut draining => {
+                // 1001 Going Away：服务器正在关闭，客户端应当重连到其他实例
+                let _ = socket
+                    .send(Message::Close(Some(CloseFrame {
+                        code: axum::extract::ws::close_code::AWAY,
+                        reason: "server shutting down".into(),
+                    })))
+                    .await;
+                break;
+            }
+            event = next_user_event(&mut rx) => {
+                let Some(event) = event else { break };
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                // 客户端发来的消息忽略，ping 由 axum 自动回复
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// ============================================
+// 16. 静态文件
+// ============================================
+
+// 从磁盘目录提供静态文件
+//
+// let app = Router::new().nest("/static", StaticFiles::new("public").router());
+//
+// 支持 ETag/If-None-Match、Last-Modified/If-Modified-Since、
+// 单段 Range 请求和可选的目录列表
+#[derive(Debug, Clone)]
+struct StaticFiles {
+    root: std::path::PathBuf,
+    directory_listing: bool,
+}
+
+impl StaticFiles {
+    fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        StaticFiles {
+            root: root.into(),
+            directory_listing: false,
+        }
+    }
+
+    fn with_directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+
+    fn router<S>(self) -> Router<S> {
+        let files = Arc::new(self);
+        Router::new()
+            .route("/", get(serve_static))
+            .route("/*path", get(serve_static))
+            .with_state(files)
+    }
+
+    // 把请求路径映射到 root 下的文件
+    //
+    // 只接受普通路径段；符号链接解析后仍必须位于 root 之内
+    fn resolve(&self, request_path: &str) -> Result<std::path::PathBuf, AppError> {
+        let mut path = self.root.clone();
+        for segment in request_path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return Err(AppError::Forbidden("路径不能包含 ..".to_string())),
+                _ if segment.contains(['\\', '\0']) => {
+                    return Err(AppError::Forbidden("路径不合法".to_string()))
+                }
+                _ => path.push(segment),
+            }
+        }
+
+        let not_found = || AppError::NotFound(format!("文件 /{} 不存在", request_path));
+        let root = self.root.canonicalize().map_err(|_| not_found())?;
+        let resolved = path.canonicalize().map_err(|_| not_found())?;
+        if !resolved.starts_with(&root) {
+            return Err(AppError::Forbidden("路径不合法".to_string()));
+        }
+        Ok(resolved)
+    }
+}
+
+async fn serve_static(
+    State(files): State<Arc<StaticFiles>>,
+    path: Option<Path<String>>,
+    uri: axum::extract::OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let request_path = path.map(|Path(path)| path).unwrap_or_default();
+    let resolved = files.resolve(&request_path)?;
+    let info = crate::io::filesystem::file_info(&resolved)?;
+
+    if info.is_dir {
+        if !files.directory_listing {
+            return Err(AppError::NotFound(format!("文件 /{} 不存在", request_path)));
+        }
+        return Ok(directory_listing(uri.path(), &resolved)?.into_response());
+    }
+
+    let validators = FileValidators::new(&info);
+    if validators.not_modified(&headers) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        validators.apply(response.headers_mut());
+        return Ok(response);
+    }
+
+    let len = info.len;
+    let range = headers
+        .get(axum::http::header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| validators.if_range_matches(&headers))
+        .map(|value| parse_range(value, len));
+
+    let (status, start, body_len, content_range) = match range {
+        Some(Some(ByteRange::Satisfiable(start, end))) => {
+            let content_range = format!("bytes {}-{}/{}", start, end, len);
+            (
+                StatusCode::PARTIAL_CONTENT,
+                start,
+                end - start + 1,
+                Some(content_range),
+            )
+        }
+        Some(Some(ByteRange::Unsatisfiable)) => {
+            let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+            response.headers_mut().insert(
+                axum::http::header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len)).unwrap(),
+            );
+            return Ok(response);
+        }
+        // 没有 Range、格式不支持（如多段）或 If-Range 不匹配时返回整个文件
+        Some(None) | None => (StatusCode::OK, 0, len, None),
+    };
+
+    let body = file_body(&resolved, start, body_len).await?;
+    let mut response = (status, body).into_response();
+    let response_headers = response.headers_mut();
+    // 流式响应体没有长度提示，显式给出，避免退化成 chunked
+    response_headers.insert(
+        axum::http::header::CONTENT_LENGTH,
+        HeaderValue::from(body_len),
+    );
+    response_headers.insert(
+        axum::http::header::CONTENT_TYPE,
+        HeaderValue::from_static(content_type(&resolved)),
+    );
+    response_headers.insert(
+        axum::http::header::ACCEPT_RANGES,
+        HeaderValue::from_static("bytes"),
+    );
+    if let Some(content_range) = content_range {
+        response_headers.insert(
+            axum::http::header::CONTENT_RANGE,
+            HeaderValue::from_str(&content_range).unwrap(),
+        );
+    }
+    validators.apply(response_headers);
+    Ok(response)
+}
+
+// 从 start 开始的 len 个字节，边读边发，不把整个文件读进内存
+async fn file_body(path: &std::path::Path, start: u64, len: u64) -> std::io::Result<Body> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    Ok(Body::from_stream(tokio_util::io::ReaderStream::new(
+        file.take(len),
+    )))
+}
+
+// 条件请求用到的 ETag 和 Last-Modified
+struct FileValidators {
+    etag: String,
+    last_modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl FileValidators {
+    // 弱验证器：由大小和修改时间生成，不读文件内容
+    fn new(info: &crate::io::filesystem::FileInfo) -> Self {
+        let modified = info.modified.map(chrono::DateTime::<chrono::Utc>::from);
+        let nanos = modified
+            .and_then(|time| time.timestamp_nanos_opt())
+            .unwrap_or(0);
+        FileValidators {
+            etag: format!("W/\"{:x}-{:x}\"", info.len, nanos),
+            last_modified: modified,
+        }
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(
+            axum::http::header::ETAG,
+            HeaderValue::from_str(&self.etag).unwrap(),
+        );
+        if let Some(time) = self.last_modified {
+            headers.insert(
+                axum::http::header::LAST_MODIFIED,
+                HeaderValue::from_str(&http_date(time)).unwrap(),
+            );
+        }
+    }
+
+    // If-None-Match 优先；没有时才看 If-Modified-Since
+    fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(value) = headers.get(axum::http::header::IF_NONE_MATCH) {
+            let value = value.to_str().unwrap_or("");
+            return value.trim() == "*"
+                || value.split(',').any(|tag| weak_eq(tag.trim(), &self.etag));
+        }
+
+        match (
+            self.last_modified,
+            headers
+                .get(axum::http::header::IF_MODIFIED_SINCE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_http_date),
+        ) {
+            (Some(modified), Some(since)) => modified.timestamp() <= since.timestamp(),
+            _ => false,
+        }
+    }
+
+    // If-Range 只接受强比较，弱 ETag 永远不匹配；日期须与 Last-Modified 完全一致
+    fn if_range_matches(&self, headers: &HeaderMap) -> bool {
+        let Some(value) = headers
+            .get(axum::http::header::IF_RANGE)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return true;
+        };
+        match (parse_http_date(value), self.last_modified) {
+            (Some(date), Some(modified)) => date.timestamp() == modified.timestamp(),
+            _ => false,
+        }
+    }
+}
+
+// 弱比较：忽略 W/ 前缀
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+// IMF-fixdate，例如 Sun, 06 Nov 1994 08:49:37 GMT
+fn http_date(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn parse_http_date(value: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|time| time.with_timezone(&chrono::Utc))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    // 闭区间 [start, end]
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+}
+
+// 解析单段 Range 头；格式不支持时返回 None，由调用方返回整个文件
+fn parse_range(value: &str, len: u64) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // bytes=-N：最后 N 个字节
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => len.saturating_sub(1),
+            end => end.parse::<u64>().ok()?.min(len.saturating_sub(1)),
+        };
+        if start >= len {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        if end < start {
+            return None;
+        }
+        (start, end)
+    };
+    Some(ByteRange::Satisfiable(range.0, range.1))
+}
+
+// 按扩展名推断 Content-Type
+fn content_type(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+// 目录列表页面
+fn directory_listing(request_path: &str, dir: &std::path::Path) -> std::io::Result<Html<String>> {
+    let base = request_path.trim_end_matches('/');
+    let mut items = String::new();
+    if !base.is_empty() {
+        items.push_str(&format!("<li><a href=\"{}/..\">../</a></li>\n", base));
+    }
+    crate::io::filesystem::walk_dir(dir, 1, &mut |entry, depth| {
+        if depth == 0 {
+            return;
+        }
+        let suffix = if entry.is_dir { "/" } else { "" };
+        items.push_str(&format!(
+            "<li><a href=\"{}/{}{}\">{}{}</a></li>\n",
+            base,
+            percent_encode(&entry.name),
+            suffix,
+            html_escape(&entry.name),
+            suffix,
+        ));
+    })?;
+
+    let title = html_escape(if base.is_empty() { "/" } else { base });
+    Ok(Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<ul>\n{1}</ul>\n</body>\n</html>\n",
+        title, items
+    )))
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/*
+=== 总结 ===
+
+1. Axum 核心概念:
+
+   路由:
+   - Router::new()
+   - route() - 定义路由
+   - nest() - 嵌套路由
+   - method_router() - 多方法路由
+
+   提取器:
+   - Path - 路径参数
+   - Query - 查询参数
+   - Json - JSON 请求体
+   - ValidatedJson - JSON + 字段校验 (422)
+   - State - 共享状态
+   - Headers - 请求头
+
+2. 响应类型:
+
+   简单:
+   - &str - 纯文本
+   - String - 字符串
+   - Html<T> - HTML
+   - Json<T> - JSON
+
+   组合:
+   - (StatusCode, Json<T>)
+   - (HeaderMap, Body)
+   - Result<T, E>
+   - Response - 自定义响应
+
+3. 状态管理:
+
+   模式:
+   - Arc<Mutex<T>> - 可变状态
+   - Arc<T> - 只读状态
+   - Arc<dyn Trait> - 可替换的存储后端
+   - with_state() - 附加状态
+   - State<T> - 提取状态
+
+4. 最佳实践:
+
+   DO:
+   ✓ 使用类型安全的提取器
+   ✓ 实现 IntoResponse for 自定义错误
+   ✓ 错误统一为 {code, message, details, request_id}
+   ✓ 使用 Router 组织路由
+   ✓ Arc + Mutex 管理共享状态
+
+   DON'T:
+   ✗ 在处理器中阻塞
+   ✗ 长时间持有锁
+   ✗ 忽略错误处理
+
+5. 优雅关闭:
+
+   - ServerHandle::start(listener, app, config)
+   - shutdown() 或 Ctrl+C 触发
+   - /health 在排空期间返回 503
+   - drain_timeout 限制等待时间
+
+6. 常用模式:
+
+   REST API:
+   - CRUD 操作
+   - 状态管理
+   - 错误处理
+   - 中间件
+
+   静态文件:
+   - StaticFiles::new(root).router()
+   - ETag/Last-Modified 条件请求返回 304
+   - Range 请求返回 206，越界返回 416
+   - 只接受普通路径段，canonicalize 后检查仍在 root 内
+
+   WebSocket:
+   - GET /ws/users 推送 created/updated/deleted 事件
+   - tokio broadcast 通道，慢消费者收到 lagged 事件
+   - 握手前 subscribe，避免漏掉事件
+
+   OpenAPI:
+   - GET /openapi.json
+   - 组件 Schema 由 schemars 从类型推导
+   - RouteRecorder 注册路由时一并记录文档，paths 由此生成
+
+   指标:
+   - GET /metrics (Prometheus 文本格式)
+   - 请求数、延迟直方图、in-flight、错误数
+   - Metrics::counter() 注册自定义计数器
+
+   限流:
+   - 令牌桶，按 API Key 或 IP 区分客户端
+   - 每个路由组一个 RateLimiter
+   - 429 + Retry-After + X-RateLimit-*
+
+   认证:
+   - Authorization: Bearer <token> 或 X-API-Key
+   - Auth::guard(Access, 方法路由) 按路由配置
+   - Extension<Principal> 取出调用方
+
+   路由组织:
+   - nest() 分组
+   - 版本化 API
+   - 模块化处理器
+
+运行示例:
+  cargo run --bin axum_detailed
+
+测试 API:
+  curl http://localhost:3000/
+  curl http://localhost:3000/hello
+  curl http://localhost:3000/users/123
+  curl http://localhost:3000/search?q=rust
+  curl "http://localhost:3000/users?page=2&limit=10&sort=-name"
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow_app(delay: Duration) -> Router {
+        Router::new().route("/health", get(health_check)).route(
+            "/slow",
+            get(move || async move {
+                tokio::time::sleep(delay).await;
+                "done"
+            }),
+        )
+    }
+
+    async fn start(app: Router, config: ShutdownConfig) -> ServerHandle {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        ServerHandle::start(
+            listener,
+            app,
+            ShutdownConfig {
+                ctrl_c: false,
+                ..config
+            },
+        )
+        .unwrap()
+    }
+
+    // 每次请求都新建连接，避免复用已被关闭的 keep-alive 连接
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_request_finishes_during_drain() {
+        let handle = start(
+            slow_app(Duration::from_millis(300)),
+            ShutdownConfig::default(),
+        )
+        .await;
+        let url = format!("http://{}/slow", handle.local_addr());
+
+        let in_flight = tokio::spawn(async move { client().get(url).send().await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let addr = handle.local_addr();
+        handle.shutdown();
+        assert_eq!(handle.wait().await.unwrap(), ShutdownOutcome::Drained);
+
+        let response = in_flight.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "done");
+
+        // 新连接被拒绝
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_draining() {
+        let config = ShutdownConfig {
+            readiness_delay: Duration::from_millis(300),
+            ..ShutdownConfig::default()
+        };
+        let handle = start(slow_app(Duration::ZERO), config).await;
+        let url = format!("http://{}/health", handle.local_addr());
+
+        let response = client().get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        handle.shutdown();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(handle.readiness().is_draining());
+
+        let response = client().get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.text().await.unwrap(), "DRAINING");
+
+        assert_eq!(handle.wait().await.unwrap(), ShutdownOutcome::Drained);
+    }
+
+    #[tokio::test]
+    async fn test_drain_deadline_exceeded() {
+        // 处理器的 future 被丢弃时记录下来
+        struct DropFlag(Arc<std::sync::atomic::AtomicBool>);
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = Arc::clone(&dropped);
+        let app = Router::new().route(
+            "/slow",
+            get(move || {
+                let guard = DropFlag(Arc::clone(&flag));
+                async move {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    drop(guard);
+                    "done"
+                }
+            }),
+        );
+
+        let config = ShutdownConfig {
+            drain_timeout: Duration::from_millis(100),
+            ..ShutdownConfig::default()
+        };
+        let handle = start(app, config).await;
+        let url = format!("http://{}/slow", handle.local_addr());
+
+        let in_flight = tokio::spawn(async move { client().get(url).send().await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let start = std::time::Instant::now();
+        handle.shutdown();
+        assert_eq!(
+            handle.wait().await.unwrap(),
+            ShutdownOutcome::DeadlineExceeded
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // 超时后连接被中止，处理器没有继续在后台运行
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(in_flight.await.unwrap().is_err());
+    }
+
+    fn temp_log(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("http_server_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("users.jsonl")
+    }
+
+    fn create_req(name: &str) -> CreateUserRequest {
+        CreateUserRequest {
+            name: name.to_string(),
+            email: format!("{}@example.com", name),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_repository_survives_restart() {
+        let path = temp_log("restart");
+
+        {
+            let repo = FileUserRepository::open(&path).unwrap();
+            let alice = repo.create(create_req("alice")).await.unwrap();
+            let bob = repo.create(create_req("bob")).await.unwrap();
+            repo.update(
+                alice.id,
+                UpdateUserRequest {
+                    name: Some("Alice".to_string()),
+                    email: None,
+                },
+            )
+            .await
+            .unwrap();
+            assert!(repo.delete(bob.id).await.unwrap());
+        }
+
+        let repo = FileUserRepository::open(&path).unwrap();
+        let users = repo.list().await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "Alice");
+        assert_eq!(users[0].email, "alice@example.com");
+
+        // 被删除的 id 不会被复用
+        assert_eq!(repo.create(create_req("carol")).await.unwrap().id, 3);
+    }
+
+    #[tokio::test]
+    async fn test_file_repository_compacts_on_open() {
+        let path = temp_log("compact");
+
+        {
+            let repo = FileUserRepository::open(&path).unwrap();
+            let user = repo.create(create_req("alice")).await.unwrap();
+            for i in 0..5 {
+                repo.update(
+                    user.id,
+                    UpdateUserRequest {
+                        name: Some(format!("alice-{}", i)),
+                        email: None,
+                    },
+                )
+                .await
+                .unwrap();
+            }
+            repo.create(create_req("bob")).await.unwrap();
+        }
+        // 模拟崩溃时写了一半的最后一行
+        {
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(b"{\"op\":\"put\",\"us").unwrap();
+        }
+
+        let repo = FileUserRepository::open(&path).unwrap();
+        assert_eq!(repo.get(1).await.unwrap().unwrap().name, "alice-4");
+        assert_eq!(repo.list().await.unwrap().len(), 2);
+
+        // next_id 一行 + 两个用户
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_file_repository_rejects_corrupt_middle_line() {
+        let path = temp_log("corrupt");
+
+        {
+            let repo = FileUserRepository::open(&path).unwrap();
+            repo.create(create_req("alice")).await.unwrap();
+        }
+        // 坏行后面还有正常记录，不是崩溃留下的半行
+        {
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(b"not json\n{\"op\":\"delete\",\"id\":1}\n")
+                .unwrap();
+        }
+
+        let err = FileUserRepository::open(&path).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // 出错时不压缩，原文件保持不变
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_file_repository_failed_append_is_rolled_back() {
+        let path = temp_log("append_fail");
+        let repo = FileUserRepository::open(&path).unwrap();
+        repo.create(create_req("alice")).await.unwrap();
+
+        // 写失败：换成只读句柄
+        let writable = {
+            let mut log = repo.log.lock().await;
+            let log = log.as_mut().unwrap();
+            std::mem::replace(&mut log.file, std::fs::File::open(&path).unwrap())
+        };
+        assert!(repo.create(create_req("bob")).await.is_err());
+        assert!(repo.delete(1).await.is_err());
+        assert_eq!(repo.list().await.unwrap().len(), 1);
+
+        // 恢复后继续追加，失败的修改没有占用 id
+        repo.log.lock().await.as_mut().unwrap().file = writable;
+        assert_eq!(repo.create(create_req("carol")).await.unwrap().id, 2);
+        drop(repo);
+
+        // 日志中间没有坏行，重启正常
+        let repo = FileUserRepository::open(&path).unwrap();
+        let mut names: Vec<String> = repo
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["alice", "carol"]);
+    }
+
+    #[tokio::test]
+    async fn test_complete_api_with_file_repository() {
+        let path = temp_log("api");
+        let repo = Arc::new(FileUserRepository::open(&path).unwrap());
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let created: serde_json::Value = client()
+            .post(format!("{}/users", base))
+            .json(&serde_json::json!({"name": "alice", "email": "alice@example.com"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(created["id"], 1);
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+
+        let repo = FileUserRepository::open(&path).unwrap();
+        assert_eq!(repo.get(1).await.unwrap().unwrap().name, "alice");
+    }
+
+    #[test]
+    fn test_validation_rules() {
+        let req = CreateUserRequest {
+            name: "a b".to_string(),
+            email: "nope".to_string(),
+        };
+        let fields: Vec<String> = req
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+        assert_eq!(fields, vec!["name", "email"]);
+
+        assert!(create_req("alice").validate().is_ok());
+
+        let empty = UpdateUserRequest {
+            name: None,
+            email: None,
+        };
+        assert_eq!(empty.validate().unwrap_err()[0].field, "body");
+    }
+
+    #[tokio::test]
+    async fn test_create_user_validation_error() {
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(complete_api(repo.clone()), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let response = client()
+            .post(format!("{}/users", base))
+            .json(&serde_json::json!({"name": "x", "email": "not-an-email"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!(body.code, "validation_failed");
+        let fields: Vec<FieldError> =
+            serde_json::from_value(body.details["fields"].clone()).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].field, "name");
+        assert_eq!(fields[1].field, "email");
+        assert!(repo.list().await.unwrap().is_empty());
+
+        // 格式错误的 JSON 仍然是 400
+        let response = client()
+            .post(format!("{}/users", base))
+            .header("content-type", "application/json")
+            .body("{")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_error_envelope_carries_request_id() {
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let response = client()
+            .get(format!("{}/users/42", base))
+            .header(X_REQUEST_ID, "req-123")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[X_REQUEST_ID], "req-123");
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!(body.code, "not_found");
+        assert_eq!(body.message, "用户 42 不存在");
+        assert!(body.details.is_null());
+        assert_eq!(body.request_id.as_deref(), Some("req-123"));
+
+        // 未传入时自动生成
+        let response = client()
+            .delete(format!("{}/users/7", base))
+            .send()
+            .await
+            .unwrap();
+        let generated = response.headers()[X_REQUEST_ID]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!(body.request_id, Some(generated));
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[test]
+    fn test_domain_errors_convert_to_app_error() {
+        use crate::types::thiserror_examples::{ConfigError, DatabaseError};
+
+        let err: AppError = DatabaseError::DuplicateKey {
+            key: "email".to_string(),
+        }
+        .into();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+
+        let err: AppError = DatabaseError::NotFound {
+            table: "users".to_string(),
+            id: 1,
+        }
+        .into();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+
+        let err: AppError = DatabaseError::PoolExhausted.into();
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let err: AppError = ConfigError::MissingField("port".to_string()).into();
+        assert_eq!(err.code(), "internal_error");
+    }
+
+    #[test]
+    fn test_rate_limited_sets_retry_after() {
+        let response = AppError::RateLimited {
+            retry_after: Duration::from_millis(1500),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[axum::http::header::RETRY_AFTER], "2");
+    }
+
+    fn test_keys() -> KeyStore {
+        KeyStore::default()
+            .with_bearer_token("reader-token", Principal::new("reader", &["users:read"]))
+            .with_api_key("writer-key", Principal::new("writer", &["users:write"]))
+    }
+
+    #[tokio::test]
+    async fn test_auth_per_route_policies() {
+        let handle = start(
+            nested_routes_example(test_keys()),
+            ShutdownConfig::default(),
+        )
+        .await;
+        let api = format!("http://{}/api/v1", handle.local_addr());
+
+        // 公开路由
+        let response = client().get(format!("{}/users", api)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        // 同一路径的 POST 需要 users:write
+        let response = client()
+            .post(format!("{}/users", api))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key("www-authenticate"));
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!(body.code, "unauthorized");
+
+        let response = client()
+            .post(format!("{}/users", api))
+            .bearer_auth("reader-token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!(body.code, "forbidden");
+
+        let response = client()
+            .post(format!("{}/users", api))
+            .header(X_API_KEY, "writer-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "POST /api/v1/users by writer"
+        );
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_middleware_example_auth_layer() {
+        let handle = start(middleware_example(test_keys()), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let response = client().get(&base).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let response = client().get(format!("{}/me", base)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key("x-request-id"));
+
+        let response = client()
+            .get(format!("{}/me", base))
+            .header(X_API_KEY, "writer-key")
+            .send()
+            .await
+            .unwrap();
+        let principal: Principal = response.json().await.unwrap();
+        assert_eq!(principal.name, "writer");
+
+        // 未注册的路径不经过认证
+        let response = client()
+            .get(format!("{}/missing", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_auth_principal_in_extensions() {
+        let handle = start(
+            nested_routes_example(test_keys()),
+            ShutdownConfig::default(),
+        )
+        .await;
+        let me = format!("http://{}/api/v1/me", handle.local_addr());
+
+        let principal: Principal = client()
+            .get(&me)
+            .bearer_auth("reader-token")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(principal, Principal::new("reader", &["users:read"]));
+
+        for request in [
+            client().get(&me).bearer_auth("wrong-token"),
+            client().get(&me).header(X_API_KEY, "wrong-key"),
+            client().get(&me).basic_auth("user", Some("pass")),
+        ] {
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        }
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    // 手动推进的时钟
+    struct ManualClock {
+        start: std::time::Instant,
+        offset: Mutex<Duration>,
+    }
+
+    impl ManualClock {
+        fn new() -> Arc<Self> {
+            Arc::new(ManualClock {
+                start: std::time::Instant::now(),
+                offset: Mutex::new(Duration::ZERO),
+            })
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.offset.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> std::time::Instant {
+            self.start + *self.offset.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let clock = ManualClock::new();
+        let metrics = Arc::new(RateLimitMetrics::default());
+        let limiter = RateLimiter::new(
+            RateLimitConfig {
+                capacity: 3,
+                refill_per_sec: 2.0,
+            },
+            clock.clone(),
+            metrics.clone(),
+        );
+
+        for remaining in [2, 1, 0] {
+            assert!(matches!(
+                limiter.check("a"),
+                RateDecision::Allowed { remaining: r, .. } if r == remaining
+            ));
+        }
+        assert_eq!(
+            limiter.check("a"),
+            RateDecision::Limited {
+                retry_after: Duration::from_millis(500)
+            }
+        );
+
+        // 其他客户端不受影响
+        assert!(matches!(limiter.check("b"), RateDecision::Allowed { .. }));
+
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(
+            limiter.check("a"),
+            RateDecision::Limited {
+                retry_after: Duration::from_millis(250)
+            }
+        );
+
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(
+            limiter.check("a"),
+            RateDecision::Allowed {
+                remaining: 0,
+                reset: Duration::from_millis(1500)
+            }
+        );
+
+        // 长时间空闲后最多恢复到容量上限
+        clock.advance(Duration::from_secs(60));
+        assert!(matches!(
+            limiter.check("a"),
+            RateDecision::Allowed { remaining: 2, .. }
+        ));
+
+        assert_eq!(metrics.allowed(), 6);
+        assert_eq!(metrics.rejected(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "refill_per_sec")]
+    fn test_rate_limiter_rejects_zero_refill() {
+        RateLimiter::new(
+            RateLimitConfig {
+                capacity: 1,
+                refill_per_sec: 0.0,
+            },
+            Arc::new(SystemClock),
+            Arc::new(RateLimitMetrics::default()),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_complete_api_is_rate_limited() {
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let response = client()
+            .get(format!("{}/users", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-ratelimit-limit"], "100");
+
+        // 健康检查和指标不限流
+        for path in ["/health", "/metrics"] {
+            let response = client()
+                .get(format!("{}{}", base, path))
+                .send()
+                .await
+                .unwrap();
+            assert!(!response.headers().contains_key("x-ratelimit-limit"));
+        }
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_requires_connect_info() {
+        use tower::ServiceExt;
+
+        // 用普通的 axum::serve 启动时没有 ConnectInfo，不能把所有匿名客户端算成一个
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let request = axum::http::Request::get("/users")
+            .body(Body::empty())
+            .unwrap();
+        let response = complete_api(repo).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!response.headers().contains_key("x-ratelimit-limit"));
+
+        // 不限流的路由不受影响
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let request = axum::http::Request::get("/health")
+            .body(Body::empty())
+            .unwrap();
+        let response = complete_api(repo).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_headers_per_group() {
+        let clock = ManualClock::new();
+        let metrics = Arc::new(RateLimitMetrics::default());
+        let handle = start(
+            rate_limited_routes(clock.clone(), metrics.clone(), test_keys()),
+            ShutdownConfig::default(),
+        )
+        .await;
+        let base = format!("http://{}", handle.local_addr());
+        let search = format!("{}/search?q=rust", base);
+
+        for _ in 0..5 {
+            let response = client().get(&search).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            assert_eq!(response.headers()["x-ratelimit-limit"], "5");
+        }
+
+        let response = client().get(&search).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "1");
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!(body.code, "rate_limited");
+
+        // 未经验证的 API Key 仍然算在对端 IP 上，换 Key 不能绕过限流
+        let response = client()
+            .get(&search)
+            .header(X_API_KEY, "random-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+        // 有效凭证按调用方单独计数
+        let response = client()
+            .get(&search)
+            .header(X_API_KEY, "writer-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        // 其他路由组有自己的桶，健康检查不限流
+        let response = client()
+            .get(format!("{}/api/items", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.headers()["x-ratelimit-limit"], "50");
+        let response = client()
+            .get(format!("{}/health", base))
+            .send()
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key("x-ratelimit-limit"));
+
+        clock.advance(Duration::from_secs(1));
+        let response = client().get(&search).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        assert_eq!(metrics.rejected(), 2);
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    async fn scrape(base: &str) -> String {
+        client()
+            .get(format!("{}/metrics", base))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        client()
+            .post(format!("{}/users", base))
+            .json(&serde_json::json!({"name": "alice", "email": "alice@example.com"}))
+            .send()
+            .await
+            .unwrap();
+        for id in [1, 2, 3] {
+            client()
+                .get(format!("{}/users/{}", base, id))
+                .send()
+                .await
+                .unwrap();
+        }
+        // 非标准方法归到 other
+        client()
+            .request(
+                reqwest::Method::from_bytes(b"BREW").unwrap(),
+                format!("{}/users", base),
+            )
+            .send()
+            .await
+            .unwrap();
+
+        let text = scrape(&base).await;
+        let has = |line: &str| text.lines().any(|l| l == line);
+
+        assert!(has(
+            r#"http_requests_total{method="POST",route="/users",status="201"} 1"#
+        ));
+        assert!(has(
+            r#"http_requests_total{method="GET",route="/users/:id",status="200"} 1"#
+        ));
+        assert!(has(
+            r#"http_requests_total{method="GET",route="/users/:id",status="404"} 2"#
+        ));
+        assert!(has(
+            r#"http_request_duration_seconds_count{method="GET",route="/users/:id"} 3"#
+        ));
+        assert!(has(
+            r#"http_request_duration_seconds_bucket{method="GET",route="/users/:id",le="+Inf"} 3"#
+        ));
+        assert!(has(r#"http_errors_total{status="404"} 2"#));
+        assert!(has(
+            r#"http_requests_total{method="other",route="/users",status="405"} 1"#
+        ));
+        assert!(!text.contains("BREW"));
+        // 限流计数也在同一个端点导出
+        assert!(has("rate_limit_allowed_total 5"));
+        assert!(has("rate_limit_rejected_total 0"));
+        // 抓取请求自身正在处理中
+        assert!(has("http_requests_in_flight 1"));
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_state_counter_exported_as_metric() {
+        let handle = start(state_example(), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        for expected in ["Counter: 1", "Counter: 2"] {
+            let body = client()
+                .post(format!("{}/counter", base))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            assert_eq!(body, expected);
+        }
+
+        let text = scrape(&base).await;
+        assert!(text.lines().any(|l| l == "demo_counter_total 2"));
+        assert!(text.contains("# TYPE demo_counter_total counter"));
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        for ms in [3, 30, 30, 20_000] {
+            metrics.observe("GET", "/x", 200, Duration::from_millis(ms));
+        }
+        let text = metrics.render();
+        let bucket = |le: &str| {
+            let prefix = format!(
+                "http_request_duration_seconds_bucket{{method=\"GET\",route=\"/x\",le=\"{}\"}} ",
+                le
+            );
+            text.lines()
+                .find_map(|l| l.strip_prefix(prefix.as_str()))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(bucket("0.005"), "1");
+        assert_eq!(bucket("0.025"), "1");
+        assert_eq!(bucket("0.05"), "3");
+        assert_eq!(bucket("10"), "3");
+        assert_eq!(bucket("+Inf"), "4");
+    }
+
+    // 用一个未注册的方法探测路由，405 响应的 Allow 头列出了实际注册的方法
+    async fn registered_methods(url: &str) -> std::collections::BTreeSet<String> {
+        let probe = reqwest::Method::from_bytes(b"PROBE").unwrap();
+        let response = client().request(probe, url).send().await.unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::METHOD_NOT_ALLOWED,
+            "{}",
+            url
+        );
+        response.headers()["allow"]
+            .to_str()
+            .unwrap()
+            .split(',')
+            .map(|m| m.trim().to_lowercase())
+            .filter(|m| m != "head")
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_ws_users_streams_changes_in_order() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/ws/users", handle.local_addr()))
+                .await
+                .unwrap();
+
+        let http = client();
+        let created: User = http
+            .post(format!("{}/users", base))
+            .json(&serde_json::json!({"name": "alice", "email": "alice@example.com"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let updated: User = http
+            .put(format!("{}/users/{}", base, created.id))
+            .json(&serde_json::json!({"name": "alice2"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let status = http
+            .delete(format!("{}/users/{}", base, created.id))
+            .send()
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, reqwest::StatusCode::NO_CONTENT);
+        // 失败的操作不产生事件
+        let status = http
+            .delete(format!("{}/users/{}", base, created.id))
+            .send()
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, reqwest::StatusCode::NOT_FOUND);
+
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("等待事件超时")
+                .unwrap()
+                .unwrap();
+            if let Message::Text(text) = message {
+                received.push(serde_json::from_str::<UserEvent>(&text).unwrap());
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                UserEvent::Created {
+                    user: created.clone()
+                },
+                UserEvent::Updated { user: updated },
+                UserEvent::Deleted { id: created.id },
+            ]
+        );
+
+        socket.close(None).await.unwrap();
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ws_users_concurrent_updates_end_with_final_state() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let http = client();
+        let created: User = http
+            .post(format!("{}/users", base))
+            .json(&serde_json::json!({"name": "alice", "email": "alice@example.com"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/ws/users", handle.local_addr()))
+                .await
+                .unwrap();
+
+        let url = format!("{}/users/{}", base, created.id);
+        let updates: Vec<_> = (0..20)
+            .map(|i| {
+                let request = http
+                    .put(&url)
+                    .json(&serde_json::json!({ "name": format!("alice_{}", i) }));
+                tokio::spawn(async move { request.send().await.unwrap().status() })
+            })
+            .collect();
+        for update in updates {
+            assert_eq!(update.await.unwrap(), reqwest::StatusCode::OK);
+        }
+
+        let mut last = None;
+        for _ in 0..20 {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("等待事件超时")
+                .unwrap()
+                .unwrap();
+            if let Message::Text(text) = message {
+                last = Some(serde_json::from_str::<UserEvent>(&text).unwrap());
+            }
+        }
+        // 事件与修改同序：最后一个事件就是存储中的最终状态
+        let current: User = http.get(&url).send().await.unwrap().json().await.unwrap();
+        assert_eq!(last, Some(UserEvent::Updated { user: current }));
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ws_users_closed_on_shutdown() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(
+            complete_api(repo),
+            ShutdownConfig {
+                drain_timeout: Duration::from_secs(5),
+                ..ShutdownConfig::default()
+            },
+        )
+        .await;
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/ws/users", handle.local_addr()))
+                .await
+                .unwrap();
+
+        handle.shutdown();
+        let message = tokio::time::timeout(Duration::from_secs(2), socket.next())
+            .await
+            .expect("关闭时没有收到 Close 帧")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(message, Message::Close(Some(frame)) if u16::from(frame.code) == 1001));
+
+        // 连接主动结束，排空不必等到 drain_timeout
+        let started = std::time::Instant::now();
+        assert_eq!(handle.wait().await.unwrap(), ShutdownOutcome::Drained);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_user_events_signal_lag() {
+        let events = UserEvents::new(2);
+        let mut rx = events.subscribe();
+        for id in 1..=5 {
+            events.lock().await.publish(UserEvent::Deleted { id });
+        }
+
+        assert_eq!(
+            next_user_event(&mut rx).await,
+            Some(UserEvent::Lagged { skipped: 3 })
+        );
+        assert_eq!(
+            next_user_event(&mut rx).await,
+            Some(UserEvent::Deleted { id: 4 })
+        );
+        assert_eq!(
+            next_user_event(&mut rx).await,
+            Some(UserEvent::Deleted { id: 5 })
+        );
+
+        drop(events);
+        assert_eq!(next_user_event(&mut rx).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_user_events_lock_does_not_block_runtime() {
+        let events = UserEvents::new(4);
+        let mut rx = events.subscribe();
+        let first = events.lock().await;
+
+        // 单线程运行时：等锁的任务如果阻塞了线程，下面的 sleep 永远不会返回
+        let waiting = tokio::spawn({
+            let events = events.clone();
+            async move {
+                events.lock().await.publish(UserEvent::Deleted { id: 2 });
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        first.publish(UserEvent::Deleted { id: 1 });
+        drop(first);
+        waiting.await.unwrap();
+
+        assert_eq!(
+            next_user_event(&mut rx).await,
+            Some(UserEvent::Deleted { id: 1 })
+        );
+        assert_eq!(
+            next_user_event(&mut rx).await,
+            Some(UserEvent::Deleted { id: 2 })
+        );
+    }
+
+    fn static_root(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("static_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("public/docs")).unwrap();
+        std::fs::write(dir.join("public/hello.txt"), "hello world").unwrap();
+        std::fs::write(dir.join("public/docs/a <b>.html"), "<p>a</p>").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    async fn start_static(files: StaticFiles) -> (ServerHandle, String) {
+        let app = Router::new().nest("/static", files.router());
+        let handle = start(app, ShutdownConfig::default()).await;
+        let base = format!("http://{}/static", handle.local_addr());
+        (handle, base)
+    }
+
+    #[tokio::test]
+    async fn test_static_files_conditional_and_range() {
+        let dir = static_root("range");
+        let (handle, base) = start_static(StaticFiles::new(dir.join("public"))).await;
+        let url = format!("{}/hello.txt", base);
+        let http = client();
+
+        let response = http.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(response.headers()["accept-ranges"], "bytes");
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let last_modified = response.headers()["last-modified"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(last_modified.ends_with(" GMT"));
+        assert_eq!(response.headers()["content-length"], "11");
+        assert_eq!(response.text().await.unwrap(), "hello world");
+
+        let response = http
+            .get(&url)
+            .header("If-None-Match", &etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["etag"], etag.as_str());
+        let response = http
+            .get(&url)
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_MODIFIED);
+        let response = http
+            .get(&url)
+            .header("If-None-Match", "\"other\"")
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let cases = [
+            ("bytes=0-4", "hello", "bytes 0-4/11"),
+            ("bytes=6-", "world", "bytes 6-10/11"),
+            ("bytes=-5", "world", "bytes 6-10/11"),
+            ("bytes=6-100", "world", "bytes 6-10/11"),
+        ];
+        for (range, body, content_range) in cases {
+            let response = http.get(&url).header("Range", range).send().await.unwrap();
+            assert_eq!(
+                response.status(),
+                reqwest::StatusCode::PARTIAL_CONTENT,
+                "{}",
+                range
+            );
+            assert_eq!(response.headers()["content-range"], content_range);
+            assert_eq!(
+                response.headers()["content-length"],
+                body.len().to_string().as_str()
+            );
+            assert_eq!(response.text().await.unwrap(), body);
+        }
+
+        let response = http
+            .get(&url)
+            .header("Range", "bytes=11-")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+        );
+        assert_eq!(response.headers()["content-range"], "bytes */11");
+
+        // If-Range 不匹配时忽略 Range
+        let response = http
+            .get(&url)
+            .header("Range", "bytes=0-4")
+            .header("If-Range", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let response = http
+            .get(&url)
+            .header("Range", "bytes=0-4")
+            .header("If-Range", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_static_files_traversal_and_listing() {
+        let dir = static_root("listing");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("public/link.txt")).unwrap();
+
+        let (handle, base) = start_static(StaticFiles::new(dir.join("public"))).await;
+        let http = client();
+
+        // reqwest 会规范化 ../，编码后的 / 由 axum 解码成 ..
+        for path in [
+            "..%2fsecret.txt",
+            "docs/..%2f..%2fsecret.txt",
+            "..%5csecret.txt",
+        ] {
+            let response = http.get(format!("{}/{}", base, path)).send().await.unwrap();
+            assert_eq!(
+                response.status(),
+                reqwest::StatusCode::FORBIDDEN,
+                "{}",
+                path
+            );
+        }
+        #[cfg(unix)]
+        {
+            let response = http.get(format!("{}/link.txt", base)).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+        }
+
+        let response = http
+            .get(format!("{}/missing.txt", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        // 默认不列目录
+        let response = http.get(format!("{}/docs/", base)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        handle.shutdown();
+        handle.wait().await.unwrap();
+
+        let files = StaticFiles::new(dir.join("public")).with_directory_listing(true);
+        let (handle, base) = start_static(files).await;
+        let response = http.get(format!("{}/docs/", base)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let html = response.text().await.unwrap();
+        assert!(html.contains("<a href=\"/static/docs/a%20%3Cb%3E.html\">a &lt;b&gt;.html</a>"));
+
+        let response = http
+            .get(format!("{}/docs/a%20%3Cb%3E.html", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(response.text().await.unwrap(), "<p>a</p>");
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            parse_range("bytes=0-0", 10),
+            Some(ByteRange::Satisfiable(0, 0))
+        );
+        assert_eq!(
+            parse_range("bytes=-20", 10),
+            Some(ByteRange::Satisfiable(0, 9))
+        );
+        assert_eq!(parse_range("bytes=10-", 10), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=-0", 10), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), None);
+        assert_eq!(parse_range("bytes=5-2", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+        assert_eq!(parse_range("bytes=a-b", 10), None);
+    }
+
+    #[tokio::test]
+    async fn test_openapi_documents_every_route() {
+        let repo = Arc::new(InMemoryUserRepository::default());
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let spec: serde_json::Value = client()
+            .get(format!("{}/openapi.json", base))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(spec["openapi"], "3.0.3");
+        let documented = spec["paths"].as_object().unwrap();
+
+        let (_, routes) = complete_api_routes(Arc::new(RateLimitMetrics::default()));
+        assert_eq!(routes.len(), documented.len(), "文档中有多余的路径");
+
+        for route in routes {
+            let path = route.path;
+            let doc_path = openapi_path(path);
+            let operations = documented
+                .get(&doc_path)
+                .unwrap_or_else(|| panic!("路由 {} 没有写进 OpenAPI 文档", path));
+            let documented_methods: std::collections::BTreeSet<String> = operations
+                .as_object()
+                .unwrap()
+                .keys()
+                .filter(|k| *k != "parameters")
+                .cloned()
+                .collect();
+
+            let url = format!("{}{}", base, path.replace(":id", "1"));
+            assert_eq!(
+                registered_methods(&url).await,
+                documented_methods,
+                "路由 {} 的方法与文档不一致",
+                path
+            );
+
+            // 路径中的每个参数在每个方法里都有说明
+            for segment in path.split('/').filter_map(|s| s.strip_prefix(':')) {
+                for method in &documented_methods {
+                    let params = operations[method]["parameters"].as_array();
+                    assert!(
+                        params.is_some_and(|params| params
+                            .iter()
+                            .any(|p| p["in"] == "path" && p["name"] == segment)),
+                        "{} {} 缺少路径参数 {}",
+                        method,
+                        path,
+                        segment
+                    );
+                }
+            }
+        }
+
+        // 参数类型来自处理器的类型：id 是 u32，查询参数按实际接受的类型说明
+        let id = &documented["/users/{id}"]["get"]["parameters"][0]["schema"];
+        assert_eq!(
+            (&id["type"], &id["format"]),
+            (&"integer".into(), &"uint32".into())
+        );
+        let query: Vec<&serde_json::Value> = documented["/users"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .collect();
+        let limit = query.iter().find(|p| p["name"] == "limit").unwrap();
+        assert_eq!(limit["schema"]["maximum"].as_f64(), Some(MAX_LIMIT as f64));
+        assert_eq!(limit["schema"]["default"], default_limit());
+        assert_eq!(limit["required"], false);
+        assert_eq!(query.len(), 4);
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+
+    fn property_names(schema: &serde_json::Value) -> std::collections::BTreeSet<String> {
+        schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    fn json_keys(value: serde_json::Value) -> std::collections::BTreeSet<String> {
+        value.as_object().unwrap().keys().cloned().collect()
+    }
+
+    fn component(name: &str) -> serde_json::Value {
+        let schema = &openapi_spec()["components"]["schemas"][name];
+        assert!(schema.is_object(), "components 中没有 {}", name);
+        schema.clone()
+    }
+
+    #[test]
+    fn test_openapi_schemas_match_serde() {
+        let page = UserPage {
+            data: vec![],
+            total: 0,
+            page: 1,
+            limit: 20,
+            links: PageLinks {
+                current: "/users".to_string(),
+                next: None,
+                prev: None,
+            },
+        };
+        let error = ErrorBody {
+            code: "x".to_string(),
+            message: "x".to_string(),
+            details: serde_json::Value::Null,
+            request_id: None,
+        };
+
+        let cases = [
+            (
+                component("User"),
+                serde_json::to_value(user(1, "a", "a@b.co")).unwrap(),
+            ),
+            (component("UserPage"), serde_json::to_value(&page).unwrap()),
+            (
+                component("PageLinks"),
+                serde_json::to_value(&page.links).unwrap(),
+            ),
+            (
+                component("ErrorBody"),
+                serde_json::to_value(&error).unwrap(),
+            ),
+        ];
+        for (schema, value) in cases {
+            assert_eq!(property_names(&schema), json_keys(value));
+        }
+
+        // 请求类型只能反序列化：按文档构造的请求必须能解析，缺少必填字段必须失败
+        let create = serde_json::json!({"name": "alice", "email": "alice@example.com"});
+        assert_eq!(
+            property_names(&component("CreateUserRequest")),
+            json_keys(create.clone())
+        );
+        assert!(serde_json::from_value::<CreateUserRequest>(create).is_ok());
+        for required in component("CreateUserRequest")["required"]
+            .as_array()
+            .unwrap()
+        {
+            let mut body = serde_json::json!({"name": "alice", "email": "alice@example.com"});
+            body.as_object_mut()
+                .unwrap()
+                .remove(required.as_str().unwrap());
+            assert!(serde_json::from_value::<CreateUserRequest>(body).is_err());
+        }
+
+        let update = serde_json::json!({"name": "alice", "email": "alice@example.com"});
+        assert_eq!(
+            property_names(&component("UpdateUserRequest")),
+            json_keys(update.clone())
+        );
+        assert!(serde_json::from_value::<UpdateUserRequest>(update).is_ok());
+
+        // 文档中的用户名规则就是 Validator 使用的那一个
+        assert_eq!(
+            component("CreateUserRequest")["properties"]["name"]["pattern"],
+            USERNAME_PATTERN
+        );
+        assert_eq!(
+            component("UserPage")["properties"]["limit"]["maximum"],
+            100.0
+        );
+
+        // 所有 $ref 都能在 components 中找到
+        let spec = openapi_spec().to_string();
+        for reference in spec.split("#/components/schemas/").skip(1) {
+            let name = reference.split('"').next().unwrap();
+            component(name);
+        }
+        let event = component("UserEvent");
+        assert_eq!(event["oneOf"].as_array().unwrap().len(), 4);
+
+        assert_eq!(openapi_path("/users/:id"), "/users/{id}");
+    }
+
+    fn params(query: &[(&str, &str)]) -> ListUsersParams {
+        let get = |k: &str| {
+            query
+                .iter()
+                .find(|(n, _)| *n == k)
+                .map(|(_, v)| v.to_string())
+        };
+        ListUsersParams {
+            page: get("page"),
+            limit: get("limit"),
+            sort: get("sort"),
+            email_contains: get("email_contains"),
+        }
+    }
+
+    fn user(id: u32, name: &str, email: &str) -> User {
+        User {
+            id,
+            name: name.to_string(),
+            email: email.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_list_query_sort_and_filter() {
+        let users = vec![
+            user(3, "bob", "bob@example.com"),
+            user(1, "carol", "carol@test.org"),
+            user(2, "bob", "bob2@example.com"),
+            user(4, "alice", "alice@example.com"),
+        ];
+
+        let query = ListUsersQuery::parse(params(&[("sort", "name,-id")])).unwrap();
+        let ids: Vec<u32> = query.apply(users.clone()).iter().map(|u| u.id).collect();
+        assert_eq!(ids, vec![4, 3, 2, 1]);
+
+        // 同名时按 id 升序兜底
+        let query = ListUsersQuery::parse(params(&[("sort", "name")])).unwrap();
+        let ids: Vec<u32> = query.apply(users.clone()).iter().map(|u| u.id).collect();
+        assert_eq!(ids, vec![4, 2, 3, 1]);
+
+        let query = ListUsersQuery::parse(params(&[("email_contains", "EXAMPLE")])).unwrap();
+        let ids: Vec<u32> = query.apply(users).iter().map(|u| u.id).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_list_query_rejects_invalid_params() {
+        let field = |q: &[(&str, &str)]| match ListUsersQuery::parse(params(q)) {
+            Err(AppError::InvalidQuery { field, .. }) => field,
+            other => panic!("expected InvalidQuery, got {:?}", other),
+        };
+
+        assert_eq!(field(&[("page", "0")]), "page");
+        assert_eq!(field(&[("page", "abc")]), "page");
+        assert_eq!(field(&[("limit", "1000")]), "limit");
+        assert_eq!(field(&[("sort", "age")]), "sort");
+        assert_eq!(field(&[("sort", "name,-name")]), "sort");
+    }
+
+    #[tokio::test]
+    async fn test_list_users_pagination_envelope() {
+        let repo = Arc::new(InMemoryUserRepository::default());
+        for name in ["dave", "alice", "carol", "bob", "erin"] {
+            repo.create(CreateUserRequest {
+                name: name.to_string(),
+                email: format!("{}@example.com", name),
+            })
+            .await
+            .unwrap();
+        }
+        let handle = start(complete_api(repo), ShutdownConfig::default()).await;
+        let base = format!("http://{}", handle.local_addr());
+
+        let page: UserPage = client()
+            .get(format!(
+                "{}/users?page=2&limit=2&sort=name&email_contains=%40example",
+                base
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let names: Vec<&str> = page.data.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, vec!["carol", "dave"]);
+        assert_eq!(page.total, 5);
+        assert_eq!(
+            page.links.next.as_deref(),
+            Some("/users?page=3&limit=2&sort=name&email_contains=%40example")
+        );
+        assert_eq!(
+            page.links.prev.as_deref(),
+            Some("/users?page=1&limit=2&sort=name&email_contains=%40example")
+        );
+
+        let response = client()
+            .get(format!("{}/users?limit=0", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: ErrorBody = response.json().await.unwrap();
+        assert_eq!(body.code, "invalid_query");
+        assert_eq!(body.details["field"], "limit");
+
+        handle.shutdown();
+        handle.wait().await.unwrap();
+    }
+}
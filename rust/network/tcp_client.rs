@@ -1,9 +1,110 @@
 // TCP 客户端
 
-use std::net::TcpStream;
-use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::io::{self, BufRead, BufReader, Write};
+use std::thread;
 use std::time::Duration;
 
+/// 聊天客户端，对应 `network::tcp_server::ChatServer` 的行协议
+pub struct ChatClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl ChatClient {
+    /// 连接服务器并完成昵称握手
+    ///
+    /// `timeout` 同时作为读超时，昵称被占用或无效时返回错误。
+    pub fn connect<A: ToSocketAddrs>(
+        addr: A,
+        nick: &str,
+        timeout: Option<Duration>,
+    ) -> io::Result<ChatClient> {
+        let writer = TcpStream::connect(addr)?;
+        writer.set_read_timeout(timeout)?;
+        
+        let mut client = ChatClient {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        };
+        
+        client.recv_line()?; // 昵称提示
+        client.send_line(nick)?;
+        
+        let reply = client.recv_line()?;
+        if let Some(reason) = reply.strip_prefix("! ") {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, reason.to_string()));
+        }
+        
+        Ok(client)
+    }
+    
+    /// 发送原始一行
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()
+    }
+    
+    /// 广播到当前房间
+    pub fn say(&mut self, text: &str) -> io::Result<()> {
+        self.send_line(text)
+    }
+    
+    pub fn join_room(&mut self, room: &str) -> io::Result<()> {
+        self.send_line(&format!("/join {}", room))
+    }
+    
+    pub fn leave_room(&mut self) -> io::Result<()> {
+        self.send_line("/leave")
+    }
+    
+    pub fn private_message(&mut self, to: &str, text: &str) -> io::Result<()> {
+        self.send_line(&format!("/msg {} {}", to, text))
+    }
+    
+    pub fn quit(mut self) -> io::Result<()> {
+        self.send_line("/quit")
+    }
+    
+    /// 读取服务器发来的一行（不含换行符），连接关闭时返回 `UnexpectedEof`
+    pub fn recv_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "服务器已关闭连接"));
+        }
+        Ok(line.trim_end().to_string())
+    }
+}
+
+/// 交互式聊天客户端：标准输入逐行发送，服务器消息打印到标准输出
+pub fn run_chat_client<A: ToSocketAddrs>(addr: A) -> io::Result<()> {
+    let stream = TcpStream::connect(addr)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    
+    let printer = thread::spawn(move || {
+        for line in reader.lines() {
+            match line {
+                Ok(line) => println!("{}", line),
+                Err(_) => break,
+            }
+        }
+    });
+    
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        writeln!(writer, "{}", line)?;
+        if line.trim() == "/quit" {
+            break;
+        }
+    }
+    
+    // 标准输入结束时没有发 /quit：关闭写方向，服务器读到 EOF 后断开，打印线程才会结束
+    writer.shutdown(Shutdown::Write)?;
+    let _ = printer.join();
+    Ok(())
+}
+
 /// # 基本 TCP 客户端
 pub fn basic_tcp_client_demo() {
     println!("\n=== 基本 TCP 客户端 ===");
@@ -117,6 +218,21 @@ pub fn http_client_demo() {
     println!("  println!(\"响应: {{}}\", response);");
 }
 
+/// # 实战示例：聊天客户端
+pub fn chat_client_demo() {
+    println!("\n=== 实战示例：聊天客户端 ===");
+    
+    println!("交互模式:");
+    println!("  run_chat_client(\"127.0.0.1:7878\")?;");
+    
+    println!("\n编程方式:");
+    println!("  let mut alice = ChatClient::connect(addr, \"alice\", None)?;");
+    println!("  alice.join_room(\"rust\")?;");
+    println!("  alice.say(\"hello\")?;");
+    println!("  alice.private_message(\"bob\", \"hi\")?;");
+    println!("  let line = alice.recv_line()?;");
+}
+
 /// # TCP 客户端最佳实践
 pub fn tcp_client_best_practices_demo() {
     println!("\n=== TCP 客户端最佳实践 ===");
@@ -158,5 +274,6 @@ pub fn run_all() {
     async_tcp_client_demo();
     echo_client_demo();
     http_client_demo();
+    chat_client_demo();
     tcp_client_best_practices_demo();
 }
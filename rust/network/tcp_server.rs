@@ -1,8 +1,1491 @@
 // TCP 服务器
 
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::concurrency::channels::{channel, select, Receiver, Sender};
+use crate::concurrency::threads::ThreadPool;
+
+/// 连接处理器
+///
+/// 每个被接受的连接都会在线程池中调用一次 `handle`，
+/// 返回的错误只会被记录，不会影响其他连接。
+pub trait ConnectionHandler: Send + Sync + 'static {
+    fn handle(&self, stream: TcpStream) -> io::Result<()>;
+}
+
+impl<F> ConnectionHandler for F
+where
+    F: Fn(TcpStream) -> io::Result<()> + Send + Sync + 'static,
+{
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        self(stream)
+    }
+}
+
+/// Echo 处理器：原样返回客户端发送的内容，直到对端关闭
+pub struct EchoHandler;
+
+impl ConnectionHandler for EchoHandler {
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut buffer = [0; 1024];
+        
+        loop {
+            let n = stream.read(&mut buffer)?;
+            if n == 0 {
+                return Ok(()); // 连接关闭
+            }
+            
+            stream.write_all(&buffer[..n])?;
+            stream.flush()?;
+        }
+    }
+}
+
+/// 基于线程池的阻塞式 TCP 服务器
+///
+/// ```ignore
+/// let server = TcpServer::bind("127.0.0.1:7878", 4)?;
+/// let handle = server.shutdown_handle();
+/// thread::spawn(move || server.serve(EchoHandler));
+/// // ...
+/// handle.shutdown();
+/// ```
+pub struct TcpServer {
+    listener: TcpListener,
+    pool: ThreadPool,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl TcpServer {
+    /// 绑定地址并创建 `workers` 个工作线程
+    pub fn bind<A: ToSocketAddrs>(addr: A, workers: usize) -> io::Result<TcpServer> {
+        let listener = TcpListener::bind(addr)?;
+        
+        Ok(TcpServer {
+            listener,
+            pool: ThreadPool::new(workers),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        })
+    }
+    
+    /// 和 `bind` 相同，但指定最多排队等待的连接数
+    pub fn bind_with_queue<A: ToSocketAddrs>(
+        addr: A,
+        workers: usize,
+        queue: usize,
+    ) -> io::Result<TcpServer> {
+        let listener = TcpListener::bind(addr)?;
+        
+        Ok(TcpServer {
+            listener,
+            pool: ThreadPool::with_queue_capacity(workers, queue),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        })
+    }
+    
+    /// 实际监听的地址（绑定端口 0 时用于获取系统分配的端口）
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    
+    /// 获取可跨线程使用的关闭句柄
+    pub fn shutdown_handle(&self) -> io::Result<ShutdownHandle> {
+        Ok(ShutdownHandle {
+            flag: Arc::clone(&self.shutdown),
+            addr: self.local_addr()?,
+        })
+    }
+    
+    /// 接受连接并交给线程池处理，直到调用 `ShutdownHandle::shutdown`
+    ///
+    /// 排队的连接已满时直接关闭新连接，accept 循环不会阻塞，能及时看到关闭请求。
+    /// 关闭时先对每个还没处理完的连接调用 `shutdown(Shutdown::Both)`，
+    /// 阻塞在读上的长连接处理器（聊天、keep-alive）随即返回；之后线程池被 Drop，
+    /// 等待处理器结束。还在排队的连接不再处理，直接关闭。
+    pub fn serve<H: ConnectionHandler>(self, handler: H) -> io::Result<()> {
+        let handler = Arc::new(handler);
+        let connections = OpenConnections::default();
+        
+        for stream in self.listener.incoming() {
+            if self.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            
+            match stream {
+                Ok(stream) => {
+                    let peer = stream.peer_addr().ok();
+                    // 跟踪不了的连接关闭时也断不开，直接丢弃
+                    let tracked = match connections.track(&stream) {
+                        Ok(tracked) => tracked,
+                        Err(e) => {
+                            eprintln!("连接 {:?} 被丢弃: {}", peer, e);
+                            continue;
+                        }
+                    };
+                    let handler = Arc::clone(&handler);
+                    let shutdown = Arc::clone(&self.shutdown);
+                    let queued = self.pool.try_execute(move || {
+                        let _tracked = tracked;
+                        if shutdown.load(Ordering::SeqCst) {
+                            return;
+                        }
+                        if let Err(e) = handler.handle(stream) {
+                            eprintln!("连接 {:?} 处理失败: {}", peer, e);
+                        }
+                    });
+                    // 被拒绝的任务连同其中的连接一起丢弃，客户端看到连接关闭
+                    if let Err(e) = queued {
+                        eprintln!("连接 {:?} 被丢弃: {}", peer, e);
+                    }
+                }
+                Err(e) => eprintln!("接受连接失败: {}", e),
+            }
+        }
+        
+        connections.shutdown_all();
+        Ok(())
+    }
+}
+
+/// 已接受、还没处理完的连接
+///
+/// 保存每个连接 `try_clone` 出的句柄，处理结束（或任务被丢弃）时由
+/// `TrackedConnection` 移除。
+#[derive(Default, Clone)]
+struct OpenConnections {
+    streams: Arc<Mutex<HashMap<usize, TcpStream>>>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl OpenConnections {
+    fn track(&self, stream: &TcpStream) -> io::Result<TrackedConnection> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.streams.lock().unwrap().insert(id, stream.try_clone()?);
+        Ok(TrackedConnection {
+            connections: self.clone(),
+            id,
+        })
+    }
+    
+    /// 断开所有连接，处理器的读写随即返回 EOF 或错误
+    fn shutdown_all(&self) {
+        for (_, stream) in self.streams.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+struct TrackedConnection {
+    connections: OpenConnections,
+    id: usize,
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        self.connections.streams.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// 服务器关闭句柄
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    flag: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    /// 请求关闭服务器
+    ///
+    /// `accept` 是阻塞调用，因此在设置标志后主动连接一次监听地址把它唤醒。
+    pub fn shutdown(&self) {
+        if self.flag.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            match addr {
+                SocketAddr::V4(_) => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                SocketAddr::V6(_) => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+        
+        if let Ok(stream) = TcpStream::connect(addr) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+    
+    /// 是否已请求关闭
+    pub fn is_shutdown(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
+// ============================================
+// 多房间聊天服务器
+// ============================================
+
+/// 新连接默认所在的房间
+pub const DEFAULT_ROOM: &str = "lobby";
+
+/// 昵称握手时等待 hub 答复的最长时间
+const HUB_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 连接线程发给中心线程 (hub) 的事件
+///
+/// 用 `concurrency::channels` 的通道实现扇入/扇出：所有连接通过克隆的
+/// `Sender` 扇入到 hub，hub 再通过每个客户端自己的 `Sender<String>` 扇出消息。
+enum ChatEvent {
+    Register {
+        id: usize,
+        nick: String,
+        out: Sender<String>,
+        reply: Sender<bool>,
+    },
+    Line {
+        id: usize,
+        line: String,
+    },
+    Leave {
+        id: usize,
+    },
+}
+
+struct ChatMember {
+    nick: String,
+    room: Option<String>,
+    out: Sender<String>,
+}
+
+/// 行协议聊天服务器
+///
+/// 客户端连接后先发送昵称，之后每行是一条消息或命令:
+///
+/// - `/join <房间>`: 切换房间（新连接默认在 `lobby`）
+/// - `/leave`: 离开当前房间
+/// - `/msg <昵称> <内容>`: 私信
+/// - `/quit`: 断开连接
+/// - 其他内容: 广播到当前房间
+///
+/// 作为 `ConnectionHandler` 交给 `TcpServer` 使用，
+/// 每个在线客户端会占用线程池中的一个 Worker。
+pub struct ChatServer {
+    hub: Sender<ChatEvent>,
+    next_id: AtomicUsize,
+}
+
+impl ChatServer {
+    /// 创建服务器并启动 hub 线程
+    pub fn new() -> ChatServer {
+        let (hub, events) = channel();
+        thread::spawn(move || run_chat_hub(events));
+        
+        ChatServer {
+            hub,
+            next_id: AtomicUsize::new(0),
+        }
+    }
+    
+    fn send(&self, event: ChatEvent) -> io::Result<()> {
+        self.hub
+            .send(event)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "聊天服务已停止"))
+    }
+    
+    /// 昵称握手，成功后返回客户端的输出通道
+    fn register(
+        &self,
+        id: usize,
+        reader: &mut BufReader<TcpStream>,
+        writer: &mut TcpStream,
+    ) -> io::Result<Option<Receiver<String>>> {
+        let (out, out_rx) = channel();
+        let mut line = String::new();
+        
+        loop {
+            writeln!(writer, "请输入昵称:")?;
+            
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            
+            let nick = line.trim();
+            if nick.is_empty() || nick.starts_with('/') || nick.contains(char::is_whitespace) {
+                writeln!(writer, "! 无效的昵称")?;
+                continue;
+            }
+            
+            let (reply, accepted) = channel();
+            self.send(ChatEvent::Register {
+                id,
+                nick: nick.to_string(),
+                out: out.clone(),
+                reply,
+            })?;
+            
+            let accepted = select! {
+                recv(accepted) -> ok => ok.unwrap_or(false),
+                timeout(HUB_REPLY_TIMEOUT) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "聊天服务无响应"))
+                }
+            };
+            if accepted {
+                return Ok(Some(out_rx));
+            }
+            writeln!(writer, "! 昵称已被占用: {}", nick)?;
+        }
+    }
+}
+
+impl Default for ChatServer {
+    fn default() -> Self {
+        ChatServer::new()
+    }
+}
+
+impl ConnectionHandler for ChatServer {
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        
+        let out_rx = match self.register(id, &mut reader, &mut writer)? {
+            Some(out_rx) => out_rx,
+            None => return Ok(()),
+        };
+        
+        // 写线程：把 hub 发来的消息写回客户端，hub 移除该客户端后自动结束
+        let writer_thread = thread::spawn(move || {
+            while let Ok(line) = out_rx.recv() {
+                if writeln!(writer, "{}", line).is_err() {
+                    break;
+                }
+            }
+        });
+        
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            
+            let line = line.trim_end();
+            if line == "/quit" {
+                break;
+            }
+            if !line.is_empty() {
+                self.send(ChatEvent::Line {
+                    id,
+                    line: line.to_string(),
+                })?;
+            }
+        }
+        
+        self.send(ChatEvent::Leave { id })?;
+        let _ = writer_thread.join();
+        Ok(())
+    }
+}
+
+/// hub 线程：持有全部在线状态，串行处理所有事件
+fn run_chat_hub(events: Receiver<ChatEvent>) {
+    let mut members: HashMap<usize, ChatMember> = HashMap::new();
+    
+    while let Ok(event) = events.recv() {
+        match event {
+            ChatEvent::Register { id, nick, out, reply } => {
+                if members.values().any(|m| m.nick == nick) {
+                    let _ = reply.send(false);
+                    continue;
+                }
+                
+                let _ = out.send(format!("* 欢迎 {}，已加入房间 {}", nick, DEFAULT_ROOM));
+                broadcast(&members, DEFAULT_ROOM, None, &format!("* {} 加入了房间 {}", nick, DEFAULT_ROOM));
+                members.insert(
+                    id,
+                    ChatMember {
+                        nick,
+                        room: Some(DEFAULT_ROOM.to_string()),
+                        out,
+                    },
+                );
+                let _ = reply.send(true);
+            }
+            ChatEvent::Line { id, line } => handle_chat_line(&mut members, id, &line),
+            ChatEvent::Leave { id } => {
+                if let Some(member) = members.remove(&id) {
+                    if let Some(room) = &member.room {
+                        broadcast(&members, room, None, &format!("* {} 离开了房间 {}", member.nick, room));
+                    }
+                    let _ = member.out.send("* 再见".to_string());
+                }
+            }
+        }
+    }
+}
+
+fn handle_chat_line(members: &mut HashMap<usize, ChatMember>, id: usize, line: &str) {
+    let (nick, room) = match members.get(&id) {
+        Some(m) => (m.nick.clone(), m.room.clone()),
+        None => return,
+    };
+    let reply = |members: &HashMap<usize, ChatMember>, msg: String| {
+        if let Some(m) = members.get(&id) {
+            let _ = m.out.send(msg);
+        }
+    };
+    
+    let mut parts = line.splitn(3, ' ');
+    match parts.next() {
+        Some("/join") => {
+            let new_room = match parts.next().map(str::trim) {
+                Some(r) if !r.is_empty() => r.to_string(),
+                _ => return reply(members, "! 用法: /join <房间>".to_string()),
+            };
+            if room.as_deref() == Some(new_room.as_str()) {
+                return reply(members, format!("! 已在房间 {}", new_room));
+            }
+            
+            if let Some(old) = &room {
+                broadcast(members, old, Some(id), &format!("* {} 离开了房间 {}", nick, old));
+            }
+            broadcast(members, &new_room, Some(id), &format!("* {} 加入了房间 {}", nick, new_room));
+            reply(members, format!("* 已加入房间 {}", new_room));
+            if let Some(m) = members.get_mut(&id) {
+                m.room = Some(new_room);
+            }
+        }
+        Some("/leave") => match room {
+            Some(old) => {
+                broadcast(members, &old, Some(id), &format!("* {} 离开了房间 {}", nick, old));
+                reply(members, format!("* 已离开房间 {}", old));
+                if let Some(m) = members.get_mut(&id) {
+                    m.room = None;
+                }
+            }
+            None => reply(members, "! 当前不在任何房间".to_string()),
+        },
+        Some("/msg") => {
+            let (to, text) = match (parts.next(), parts.next()) {
+                (Some(to), Some(text)) if !text.trim().is_empty() => (to, text),
+                _ => return reply(members, "! 用法: /msg <昵称> <内容>".to_string()),
+            };
+            match members.values().find(|m| m.nick == to) {
+                Some(target) => {
+                    let _ = target.out.send(format!("[私信] {}: {}", nick, text));
+                }
+                None => reply(members, format!("! 用户不存在: {}", to)),
+            }
+        }
+        Some(cmd) if cmd.starts_with('/') => reply(members, format!("! 未知命令: {}", cmd)),
+        _ => match room {
+            Some(room) => broadcast(members, &room, Some(id), &format!("[{}] {}: {}", room, nick, line)),
+            None => reply(members, "! 请先使用 /join 加入房间".to_string()),
+        },
+    }
+}
+
+/// 扇出：把消息发给房间内除 `except` 之外的所有成员
+fn broadcast(members: &HashMap<usize, ChatMember>, room: &str, except: Option<usize>, msg: &str) {
+    for (id, member) in members {
+        if Some(*id) != except && member.room.as_deref() == Some(room) {
+            let _ = member.out.send(msg.to_string());
+        }
+    }
+}
+
+// ============================================
+// HTTP/1.1 服务器（手写解析器，不依赖 HTTP 库）
+// ============================================
+
+/// 起始行加全部头部的最大字节数
+pub const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+/// 最多允许的头部个数
+pub const MAX_HEADERS: usize = 100;
+
+/// 请求体的最大字节数
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// keep-alive 连接等待下一个请求的时间
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 一个连接最多处理的请求数，达到后回复 `Connection: close`
+///
+/// 每个连接独占一个工作线程，不设上限时少数一直发请求的客户端就能占满线程池
+pub const MAX_REQUESTS_PER_CONNECTION: usize = 100;
+
+/// 一个连接最长保持的时间，超过后处理完当前请求就关闭
+pub const MAX_CONNECTION_LIFETIME: Duration = Duration::from_secs(60);
+
+/// HTTP 解析错误
+#[derive(Debug)]
+pub enum HttpError {
+    /// 语法错误
+    Malformed(&'static str),
+    /// 起始行或头部过大
+    HeadTooLarge,
+    /// 请求体超过 `MAX_BODY_BYTES`
+    BodyTooLarge,
+    /// 不是 HTTP/1.0 或 HTTP/1.1
+    UnsupportedVersion,
+    /// 除 chunked 之外的 Transfer-Encoding
+    UnsupportedEncoding,
+    /// 消息还没读完连接就关闭了
+    UnexpectedEof,
+    Io(io::Error),
+}
+
+impl HttpError {
+    /// 应当回复给客户端的状态码；连接层面的错误返回 None
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            HttpError::Malformed(_) => Some(400),
+            HttpError::HeadTooLarge => Some(431),
+            HttpError::BodyTooLarge => Some(413),
+            HttpError::UnsupportedVersion => Some(505),
+            HttpError::UnsupportedEncoding => Some(501),
+            HttpError::UnexpectedEof | HttpError::Io(_) => None,
+        }
+    }
+    
+    /// 错误响应体中的 code，与 `status` 对应
+    pub fn code(&self) -> &'static str {
+        match self {
+            HttpError::Malformed(_) => "bad_request",
+            HttpError::HeadTooLarge => "header_too_large",
+            HttpError::BodyTooLarge => "payload_too_large",
+            HttpError::UnsupportedVersion => "http_version_not_supported",
+            HttpError::UnsupportedEncoding => "not_implemented",
+            HttpError::UnexpectedEof | HttpError::Io(_) => "connection_error",
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Malformed(reason) => write!(f, "请求格式错误: {}", reason),
+            HttpError::HeadTooLarge => write!(f, "请求头过大"),
+            HttpError::BodyTooLarge => write!(f, "请求体过大"),
+            HttpError::UnsupportedVersion => write!(f, "不支持的 HTTP 版本"),
+            HttpError::UnsupportedEncoding => write!(f, "不支持的 Transfer-Encoding"),
+            HttpError::UnexpectedEof => write!(f, "连接意外关闭"),
+            HttpError::Io(e) => write!(f, "I/O 错误: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+/// 头部列表，保留原始顺序和大小写
+pub type Headers = Vec<(String, String)>;
+
+/// HTTP 版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+/// 解析后的请求
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// 请求目标，包含查询字符串，如 `/users?page=2`
+    pub target: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// 去掉查询字符串的路径
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+    
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+    
+    /// 按名称查找头部（不区分大小写）
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+    
+    /// HTTP/1.1 默认保持连接，HTTP/1.0 需要显式的 `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").unwrap_or("");
+        let has = |token: &str| {
+            connection
+                .split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        };
+        
+        match self.version {
+            Version::Http11 => !has("close"),
+            Version::Http10 => has("keep-alive"),
+        }
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// RFC 9110 中 token 允许的字符
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// 读取一行并去掉行尾的 CRLF（也接受单独的 LF）
+///
+/// `budget` 是剩余可读的字节数，超出时返回 `HeadTooLarge`；
+/// 一个字节都没读到就遇到 EOF 时返回 None。
+fn read_line<R: BufRead>(reader: &mut R, budget: &mut usize) -> Result<Option<Vec<u8>>, HttpError> {
+    let mut line = Vec::new();
+    
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return if line.is_empty() {
+                Ok(None)
+            } else {
+                Err(HttpError::UnexpectedEof)
+            };
+        }
+        
+        let (len, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (available.len(), false),
+        };
+        if len > *budget {
+            return Err(HttpError::HeadTooLarge);
+        }
+        *budget -= len;
+        line.extend_from_slice(&available[..len]);
+        reader.consume(len);
+        
+        if done {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            return Ok(Some(line));
+        }
+    }
+}
+
+/// 读取起始行和头部
+///
+/// 连接在起始行之前被干净地关闭时返回 None，
+/// 这是 keep-alive 连接正常结束的方式。
+pub fn read_head<R: BufRead>(reader: &mut R) -> Result<Option<(String, Headers)>, HttpError> {
+    let mut budget = MAX_HEAD_BYTES;
+    
+    // 起始行之前的空行直接忽略（RFC 9112 2.2）
+    let start = loop {
+        match read_line(reader, &mut budget)? {
+            None => return Ok(None),
+            Some(line) if line.is_empty() => continue,
+            Some(line) => break line,
+        }
+    };
+    if start.iter().any(|&b| b.is_ascii_control()) {
+        return Err(HttpError::Malformed("起始行包含控制字符"));
+    }
+    let start = String::from_utf8(start).map_err(|_| HttpError::Malformed("起始行不是 UTF-8"))?;
+    
+    let headers = read_fields(reader, &mut budget)?;
+    Ok(Some((start, headers)))
+}
+
+/// 读取头部（或 chunked 的 trailer），直到空行
+fn read_fields<R: BufRead>(reader: &mut R, budget: &mut usize) -> Result<Headers, HttpError> {
+    let mut fields = Vec::new();
+    
+    loop {
+        let line = read_line(reader, budget)?.ok_or(HttpError::UnexpectedEof)?;
+        if line.is_empty() {
+            return Ok(fields);
+        }
+        if fields.len() == MAX_HEADERS {
+            return Err(HttpError::HeadTooLarge);
+        }
+        fields.push(parse_field(&line)?);
+    }
+}
+
+fn parse_field(line: &[u8]) -> Result<(String, String), HttpError> {
+    if line[0] == b' ' || line[0] == b'\t' {
+        return Err(HttpError::Malformed("不支持折叠的头部"));
+    }
+    
+    let colon = line
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or(HttpError::Malformed("头部缺少冒号"))?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    // 名称和冒号之间不能有空白，否则可能被不同的实现解析成不同的头
+    if name.is_empty() || !name.iter().all(|&b| is_tchar(b)) {
+        return Err(HttpError::Malformed("头部名称不合法"));
+    }
+    if value.iter().any(|&b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+        return Err(HttpError::Malformed("头部值包含控制字符"));
+    }
+    
+    let value = std::str::from_utf8(value).map_err(|_| HttpError::Malformed("头部值不是 UTF-8"))?;
+    Ok((
+        String::from_utf8_lossy(name).into_owned(),
+        value.trim_matches([' ', '\t']).to_string(),
+    ))
+}
+
+fn parse_request_line(line: &str) -> Result<(String, String, Version), HttpError> {
+    let parts: Vec<&str> = line.split(' ').collect();
+    let [method, target, version] = parts[..] else {
+        return Err(HttpError::Malformed("请求行应为 方法 目标 版本"));
+    };
+    
+    if method.is_empty() || !method.bytes().all(is_tchar) {
+        return Err(HttpError::Malformed("方法不合法"));
+    }
+    if !target.starts_with('/') {
+        return Err(HttpError::Malformed("请求目标必须以 / 开头"));
+    }
+    
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.len() == 8
+            && v.starts_with("HTTP/")
+            && v.as_bytes()[5].is_ascii_digit()
+            && v.as_bytes()[6] == b'.'
+            && v.as_bytes()[7].is_ascii_digit() =>
+        {
+            return Err(HttpError::UnsupportedVersion)
+        }
+        _ => return Err(HttpError::Malformed("版本不合法")),
+    };
+    
+    Ok((method.to_string(), target.to_string(), version))
+}
+
+/// 按 Content-Length 或 chunked 编码读取消息体；两者都没有时消息体为空
+fn read_body<R: BufRead>(
+    reader: &mut R,
+    headers: &[(String, String)],
+) -> Result<Vec<u8>, HttpError> {
+    let encodings: Vec<String> = headers
+        .iter()
+        .filter(|(n, _)| n.eq_ignore_ascii_case("transfer-encoding"))
+        .flat_map(|(_, v)| v.split(','))
+        .map(|e| e.trim().to_ascii_lowercase())
+        .collect();
+    let lengths: Vec<&str> = headers
+        .iter()
+        .filter(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+        .flat_map(|(_, v)| v.split(','))
+        .map(str::trim)
+        .collect();
+    
+    if !encodings.is_empty() {
+        // 两者同时出现是请求走私的典型手法，直接拒绝
+        if !lengths.is_empty() {
+            return Err(HttpError::Malformed(
+                "同时存在 Content-Length 和 Transfer-Encoding",
+            ));
+        }
+        if encodings != ["chunked"] {
+            return Err(HttpError::UnsupportedEncoding);
+        }
+        return read_chunked(reader);
+    }
+    
+    let Some(&first) = lengths.first() else {
+        return Ok(Vec::new());
+    };
+    if lengths.iter().any(|&l| l != first) {
+        return Err(HttpError::Malformed("Content-Length 不一致"));
+    }
+    if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(HttpError::Malformed("Content-Length 不合法"));
+    }
+    let len: usize = match first.parse() {
+        Ok(len) if len <= MAX_BODY_BYTES => len,
+        _ => return Err(HttpError::BodyTooLarge),
+    };
+    
+    let mut body = Vec::with_capacity(len);
+    read_exact_into(reader, len, &mut body)?;
+    Ok(body)
+}
+
+fn read_exact_into<R: Read>(
+    reader: &mut R,
+    len: usize,
+    buf: &mut Vec<u8>,
+) -> Result<(), HttpError> {
+    let start = buf.len();
+    reader.take(len as u64).read_to_end(buf)?;
+    if buf.len() - start < len {
+        return Err(HttpError::UnexpectedEof);
+    }
+    Ok(())
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, HttpError> {
+    let mut body = Vec::new();
+    let too_long = |e| match e {
+        HttpError::HeadTooLarge => HttpError::Malformed("块大小行过长"),
+        e => e,
+    };
+    
+    loop {
+        let mut budget = 1024;
+        let line = read_line(reader, &mut budget)
+            .map_err(too_long)?
+            .ok_or(HttpError::UnexpectedEof)?;
+        // 块扩展（;name=value）忽略
+        let size = line.split(|&b| b == b';').next().unwrap_or(&[]);
+        let size = std::str::from_utf8(size)
+            .unwrap_or("")
+            .trim_end_matches([' ', '\t']);
+        if size.is_empty() || size.len() > 16 || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(HttpError::Malformed("块大小不合法"));
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| HttpError::BodyTooLarge)?;
+        if size == 0 {
+            break;
+        }
+        if size > MAX_BODY_BYTES - body.len() {
+            return Err(HttpError::BodyTooLarge);
+        }
+        
+        read_exact_into(reader, size, &mut body)?;
+        let mut budget = 2;
+        match read_line(reader, &mut budget) {
+            Ok(Some(line)) if line.is_empty() => {}
+            Ok(None) => return Err(HttpError::UnexpectedEof),
+            Err(HttpError::Io(e)) => return Err(HttpError::Io(e)),
+            Err(HttpError::UnexpectedEof) => return Err(HttpError::UnexpectedEof),
+            _ => return Err(HttpError::Malformed("块数据后缺少 CRLF")),
+        }
+    }
+    
+    // trailer 字段读完后丢弃
+    let mut budget = MAX_HEAD_BYTES;
+    read_fields(reader, &mut budget)?;
+    Ok(body)
+}
+
+/// 从连接读取下一个请求；对端在两个请求之间关闭连接时返回 None
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Option<Request>, HttpError> {
+    let Some((start, headers)) = read_head(reader)? else {
+        return Ok(None);
+    };
+    let (method, target, version) = parse_request_line(&start)?;
+    
+    let hosts = headers
+        .iter()
+        .filter(|(n, _)| n.eq_ignore_ascii_case("host"))
+        .count();
+    if hosts > 1 || (version == Version::Http11 && hosts == 0) {
+        return Err(HttpError::Malformed("HTTP/1.1 请求必须有且只有一个 Host"));
+    }
+    
+    let body = read_body(reader, &headers)?;
+    Ok(Some(Request {
+        method,
+        target,
+        version,
+        headers,
+        body,
+    }))
+}
+
+/// HTTP 响应
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+    
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into().into_bytes())
+    }
+    
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        let body = serde_json::to_vec(value).expect("序列化响应体失败");
+        Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(body)
+    }
+    
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+    
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+    
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+    
+    /// 写出响应，Content-Length 和 Connection 由这里统一设置
+    pub fn write_to<W: Write>(&self, writer: &mut W, keep_alive: bool) -> io::Result<()> {
+        self.write(writer, keep_alive, true)
+    }
+    
+    /// HEAD 请求的响应头和 GET 一致，但不发送消息体
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        keep_alive: bool,
+        include_body: bool,
+    ) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+        for (name, value) in &self.headers {
+            if name.eq_ignore_ascii_case("content-length")
+                || name.eq_ignore_ascii_case("connection")
+            {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        // 1xx、204 和 304 不能带消息体
+        let bodiless = self.status < 200 || self.status == 204 || self.status == 304;
+        if !bodiless {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str(if keep_alive {
+            "Connection: keep-alive\r\n\r\n"
+        } else {
+            "Connection: close\r\n\r\n"
+        });
+        
+        writer.write_all(head.as_bytes())?;
+        if include_body && !bodiless {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()
+    }
+}
+
+/// 读取一个响应，供客户端使用
+///
+/// `method` 是对应请求的方法，HEAD 的响应没有消息体；
+/// 没有 Content-Length 也不是 chunked 的响应体读到连接关闭为止。
+pub fn read_response<R: BufRead>(
+    reader: &mut R,
+    method: &str,
+) -> Result<Option<Response>, HttpError> {
+    let Some((start, headers)) = read_head(reader)? else {
+        return Ok(None);
+    };
+    let mut parts = start.splitn(3, ' ');
+    let status = match (parts.next(), parts.next()) {
+        (Some("HTTP/1.1" | "HTTP/1.0"), Some(code)) if code.len() == 3 => code
+            .parse()
+            .map_err(|_| HttpError::Malformed("状态码不合法"))?,
+        _ => return Err(HttpError::Malformed("状态行不合法")),
+    };
+    
+    let bodiless = method == "HEAD" || status < 200 || status == 204 || status == 304;
+    let framed = find_header(&headers, "content-length").is_some()
+        || find_header(&headers, "transfer-encoding").is_some();
+    let body = if bodiless {
+        Vec::new()
+    } else if framed {
+        read_body(reader, &headers)?
+    } else {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        body
+    };
+    
+    Ok(Some(Response {
+        status,
+        headers,
+        body,
+    }))
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        422 => "Unprocessable Content",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+/// 路径参数，如 `/users/:id` 中的 `id`
+pub type Params = HashMap<String, String>;
+
+type RouteHandler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync>;
+
+struct Route {
+    method: String,
+    segments: Vec<String>,
+    handler: RouteHandler,
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<Params> {
+        let segments: Vec<&str> = path.split('/').skip(1).collect();
+        if segments.len() != self.segments.len() {
+            return None;
+        }
+        
+        let mut params = Params::new();
+        for (pattern, segment) in self.segments.iter().zip(segments) {
+            match pattern.strip_prefix(':') {
+                Some(name) if !segment.is_empty() => {
+                    params.insert(name.to_string(), segment.to_string());
+                }
+                None if pattern == segment => {}
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+/// 按方法和路径分发请求的路由表
+///
+/// ```ignore
+/// let router = HttpRouter::new()
+///     .route("GET", "/users/:id", |_req, params| Response::text(200, &params["id"]));
+/// ```
+#[derive(Default)]
+pub struct HttpRouter {
+    routes: Vec<Route>,
+}
+
+impl HttpRouter {
+    pub fn new() -> Self {
+        HttpRouter::default()
+    }
+    
+    pub fn route<F>(mut self, method: &str, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            segments: pattern.split('/').skip(1).map(String::from).collect(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+    
+    /// 路径不存在返回 404，路径存在但方法不对返回 405 和 Allow 头；HEAD 按 GET 处理
+    pub fn handle(&self, request: &Request) -> Response {
+        let method = match request.method.as_str() {
+            "HEAD" => "GET",
+            method => method,
+        };
+        let mut allowed: Vec<&str> = Vec::new();
+        
+        for route in &self.routes {
+            if let Some(params) = route.matches(request.path()) {
+                if route.method == method {
+                    return (route.handler)(request, &params);
+                }
+                allowed.push(&route.method);
+            }
+        }
+        
+        if allowed.is_empty() {
+            return error_response(404, "not_found", format!("路径 {} 不存在", request.path()));
+        }
+        if allowed.contains(&"GET") {
+            allowed.push("HEAD");
+        }
+        error_response(
+            405,
+            "method_not_allowed",
+            format!("不支持 {} 方法", request.method),
+        )
+        .with_header("Allow", allowed.join(", "))
+    }
+}
+
+/// HTTP/1.1 连接处理器，交给 `TcpServer::serve` 使用
+///
+/// 同一连接上的请求依次处理（支持 keep-alive 和管道化），
+/// 解析出错时回复对应状态码后关闭连接。连接处理满 `MAX_REQUESTS_PER_CONNECTION`
+/// 个请求或存在超过 `MAX_CONNECTION_LIFETIME` 后关闭，把工作线程让给其他连接。
+pub struct HttpHandler {
+    router: HttpRouter,
+}
+
+impl HttpHandler {
+    pub fn new(router: HttpRouter) -> Self {
+        HttpHandler { router }
+    }
+}
+
+impl ConnectionHandler for HttpHandler {
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let deadline = Instant::now() + MAX_CONNECTION_LIFETIME;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        
+        for served in 1.. {
+            // 等待下一个请求的时间也不超过连接的剩余寿命
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            writer.set_read_timeout(Some(remaining.min(KEEP_ALIVE_TIMEOUT)))?;
+            
+            let request = match read_request(&mut reader) {
+                Ok(Some(request)) => request,
+                Ok(None) => return Ok(()),
+                // 空闲超时，静默关闭
+                Err(HttpError::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Ok(())
+                }
+                Err(HttpError::Io(e)) => return Err(e),
+                Err(e) => {
+                    // 出错后无法确定下一个请求从哪里开始，只能关闭连接
+                    if let Some(status) = e.status() {
+                        error_response(status, e.code(), e.to_string())
+                            .write_to(&mut writer, false)?;
+                    }
+                    return Ok(());
+                }
+            };
+            
+            let keep_alive = request.keep_alive()
+                && served < MAX_REQUESTS_PER_CONNECTION
+                && Instant::now() < deadline;
+            let response = self.router.handle(&request);
+            response.write(&mut writer, keep_alive, request.method != "HEAD")?;
+            
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 与 axum 版本一致的错误响应体 `{code, message, details, request_id}`
+fn error_response(status: u16, code: &str, message: impl Into<String>) -> Response {
+    error_response_with_details(status, code, message, serde_json::Value::Null)
+}
+
+fn error_response_with_details(
+    status: u16,
+    code: &str,
+    message: impl Into<String>,
+    details: serde_json::Value,
+) -> Response {
+    Response::json(
+        status,
+        &serde_json::json!({
+            "code": code,
+            "message": message.into(),
+            "details": details,
+            "request_id": null,
+        }),
+    )
+}
+
+// ============================================
+// 原始 socket 版本的 /users 接口
+// ============================================
+
+/// 用户，字段与 axum 版本相同
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub id: u32,
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+struct CreateUser {
+    name: String,
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct UpdateUser {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+/// 内存中的用户表
+#[derive(Default)]
+pub struct UserStore {
+    users: std::collections::BTreeMap<u32, User>,
+    next_id: u32,
+}
+
+type SharedUsers = Arc<Mutex<UserStore>>;
+
+/// 构建与 axum `complete_api` 相同的 /users 路由
+///
+/// - GET    /users?page=&limit=&sort=&email_contains=
+/// - POST   /users
+/// - GET    /users/:id
+/// - PUT    /users/:id
+/// - DELETE /users/:id
+pub fn users_router(store: SharedUsers) -> HttpRouter {
+    let list = Arc::clone(&store);
+    let create = Arc::clone(&store);
+    let get = Arc::clone(&store);
+    let update = Arc::clone(&store);
+    let delete = store;
+    
+    HttpRouter::new()
+        .route("GET", "/health", |_, _| Response::text(200, "OK"))
+        .route("GET", "/users", move |req, _| list_users(&list, req))
+        .route("POST", "/users", move |req, _| create_user(&create, req))
+        .route("GET", "/users/:id", move |_, params| {
+            with_user_id(params, |id| match get.lock().unwrap().users.get(&id) {
+                Some(user) => Response::json(200, user),
+                None => user_not_found(id),
+            })
+        })
+        .route("PUT", "/users/:id", move |req, params| {
+            with_user_id(params, |id| update_user(&update, id, req))
+        })
+        .route("DELETE", "/users/:id", move |_, params| {
+            with_user_id(params, |id| {
+                match delete.lock().unwrap().users.remove(&id) {
+                    Some(_) => Response::new(204),
+                    None => user_not_found(id),
+                }
+            })
+        })
+}
+
+fn with_user_id(params: &Params, f: impl FnOnce(u32) -> Response) -> Response {
+    match params["id"].parse() {
+        Ok(id) => f(id),
+        Err(_) => error_response(
+            400,
+            "bad_request",
+            format!("无效的用户 ID: {}", params["id"]),
+        ),
+    }
+}
+
+fn user_not_found(id: u32) -> Response {
+    error_response(404, "not_found", format!("用户 {} 不存在", id))
+}
+
+/// 查询参数的解析、排序和分页与 axum 版本共用 `list_users_page`，
+/// 响应体和错误体完全相同
+fn list_users(store: &SharedUsers, req: &Request) -> Response {
+    use crate::network::http_server::{self, ListUsersParams};
+    
+    // 与 axum 的 Query 一样：未知参数忽略，已知参数交给共用的校验
+    let pairs: serde_json::Map<String, serde_json::Value> = req
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode(key),
+                serde_json::Value::String(percent_decode(value)),
+            )
+        })
+        .collect();
+    let params: ListUsersParams = match serde_json::from_value(pairs.into()) {
+        Ok(params) => params,
+        Err(e) => return error_response(400, "bad_request", format!("无效的查询参数: {}", e)),
+    };
+    
+    let users = store
+        .lock()
+        .unwrap()
+        .users
+        .values()
+        .map(|u| http_server::User::new(u.id, u.name.clone(), u.email.clone()))
+        .collect();
+    match http_server::list_users_page(params, users) {
+        Ok(page) => Response::json(200, &page),
+        Err(e) => {
+            let (status, body) = e.into_parts();
+            Response::json(status.as_u16(), &body)
+        }
+    }
+}
+
+/// 解码查询串中的 `+` 和 `%XX`；不完整的转义原样保留
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => out.push(b' '),
+            (b, _) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// 解析 JSON 请求体；格式错误返回 400
+fn parse_json<T: serde::de::DeserializeOwned>(req: &Request) -> Result<T, Response> {
+    serde_json::from_slice(&req.body)
+        .map_err(|e| error_response(400, "bad_request", format!("无效的 JSON: {}", e)))
+}
+
+/// 与 axum 版本相同的字段校验，失败返回 422
+fn validate_user(name: Option<&str>, email: Option<&str>) -> Result<(), Response> {
+    static VALIDATOR: std::sync::OnceLock<crate::types::regex_examples::Validator> =
+        std::sync::OnceLock::new();
+    let validator = VALIDATOR.get_or_init(Default::default);
+    
+    let mut errors = Vec::new();
+    if let Some(name) = name {
+        if !validator.validate_username(name) {
+            errors.push(
+                serde_json::json!({"field": "name", "reason": "必须是 3-20 位字母、数字或下划线"}),
+            );
+        }
+    }
+    if let Some(email) = email {
+        if !validator.validate_email(email) {
+            errors.push(serde_json::json!({"field": "email", "reason": "不是有效的邮箱地址"}));
+        }
+    }
+    
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(error_response_with_details(
+            422,
+            "validation_failed",
+            "请求参数校验失败",
+            errors.into(),
+        ))
+    }
+}
+
+fn create_user(store: &SharedUsers, req: &Request) -> Response {
+    let payload: CreateUser = match parse_json(req) {
+        Ok(payload) => payload,
+        Err(response) => return response,
+    };
+    if let Err(response) = validate_user(Some(&payload.name), Some(&payload.email)) {
+        return response;
+    }
+    
+    let mut store = store.lock().unwrap();
+    store.next_id += 1;
+    let user = User {
+        id: store.next_id,
+        name: payload.name,
+        email: payload.email,
+    };
+    store.users.insert(user.id, user.clone());
+    Response::json(201, &user)
+}
+
+fn update_user(store: &SharedUsers, id: u32, req: &Request) -> Response {
+    let payload: UpdateUser = match parse_json(req) {
+        Ok(payload) => payload,
+        Err(response) => return response,
+    };
+    if payload.name.is_none() && payload.email.is_none() {
+        let details = serde_json::json!([{"field": "body", "reason": "至少需要提供一个字段"}]);
+        return error_response_with_details(422, "validation_failed", "请求参数校验失败", details);
+    }
+    if let Err(response) = validate_user(payload.name.as_deref(), payload.email.as_deref()) {
+        return response;
+    }
+    
+    let mut store = store.lock().unwrap();
+    let Some(user) = store.users.get_mut(&id) else {
+        return user_not_found(id);
+    };
+    if let Some(name) = payload.name {
+        user.name = name;
+    }
+    if let Some(email) = payload.email {
+        user.email = email;
+    }
+    Response::json(200, user)
+}
 
 /// # 基本 TCP 服务器
 pub fn basic_tcp_server_demo() {
@@ -65,17 +1548,43 @@ pub fn multithreaded_server_demo() {
 pub fn thread_pool_server_demo() {
     println!("\n=== 线程池 TCP 服务器 ===");
     
-    println!("使用线程池:");
-    println!("  let listener = TcpListener::bind(\"127.0.0.1:7878\")?;");
-    println!("  let pool = ThreadPool::new(4);");
-    println!("  ");
-    println!("  for stream in listener.incoming() {{");
-    println!("      let stream = stream?;");
-    println!("      ");
-    println!("      pool.execute(move || {{");
-    println!("          handle_client(stream);");
-    println!("      }});");
-    println!("  }}");
+    println!("使用 TcpServer（内部复用 concurrency::threads::ThreadPool）:");
+    println!("  let server = TcpServer::bind(\"127.0.0.1:7878\", 4)?;");
+    println!("  let handle = server.shutdown_handle()?;");
+    println!("  server.serve(|stream: TcpStream| handle_client(stream))?;");
+    
+    let result = (|| -> io::Result<()> {
+        let server = TcpServer::bind("127.0.0.1:0", 4)?;
+        let addr = server.local_addr()?;
+        let handle = server.shutdown_handle()?;
+        
+        let server_thread = thread::spawn(move || {
+            server.serve(|mut stream: TcpStream| {
+                stream.write_all(b"hello from pool\n")
+            })
+        });
+        
+        let mut clients = vec![];
+        for i in 0..4 {
+            clients.push(thread::spawn(move || -> io::Result<String> {
+                let mut stream = TcpStream::connect(addr)?;
+                let mut reply = String::new();
+                stream.read_to_string(&mut reply)?;
+                Ok(format!("客户端 {}: {}", i, reply.trim()))
+            }));
+        }
+        
+        for client in clients {
+            println!("  {}", client.join().unwrap()?);
+        }
+        
+        handle.shutdown();
+        server_thread.join().unwrap()
+    })();
+    
+    if let Err(e) = result {
+        println!("  运行失败: {}", e);
+    }
     
     println!("\n优点:");
     println!("  - 固定数量的线程");
@@ -112,19 +1621,31 @@ pub fn echo_server_demo() {
     println!("\n=== Echo 服务器示例 ===");
     
     println!("Echo 服务器（返回客户端发送的内容）:");
-    println!("  fn handle_client(mut stream: TcpStream) -> io::Result<()> {{");
-    println!("      let mut buffer = [0; 1024];");
-    println!("      ");
-    println!("      loop {{");
-    println!("          let n = stream.read(&mut buffer)?;");
-    println!("          if n == 0 {{");
-    println!("              return Ok(());  // 连接关闭");
-    println!("          }}");
-    println!("          ");
-    println!("          stream.write_all(&buffer[..n])?;");
-    println!("          stream.flush()?;");
-    println!("      }}");
-    println!("  }}");
+    println!("  let server = TcpServer::bind(\"127.0.0.1:7878\", 4)?;");
+    println!("  server.serve(EchoHandler)?;");
+    
+    let result = (|| -> io::Result<()> {
+        let server = TcpServer::bind("127.0.0.1:0", 2)?;
+        let addr = server.local_addr()?;
+        let handle = server.shutdown_handle()?;
+        let server_thread = thread::spawn(move || server.serve(EchoHandler));
+        
+        let mut stream = TcpStream::connect(addr)?;
+        let mut buffer = [0; 1024];
+        for msg in ["hello", "echo"] {
+            stream.write_all(msg.as_bytes())?;
+            let n = stream.read(&mut buffer)?;
+            println!("  发送: {} -> 收到: {}", msg, String::from_utf8_lossy(&buffer[..n]));
+        }
+        drop(stream);
+        
+        handle.shutdown();
+        server_thread.join().unwrap()
+    })();
+    
+    if let Err(e) = result {
+        println!("  运行失败: {}", e);
+    }
 }
 
 /// # 聊天服务器示例
@@ -132,21 +1653,16 @@ pub fn chat_server_demo() {
     println!("\n=== 聊天服务器示例 ===");
     
     println!("聊天服务器结构:");
-    println!("  1. 维护客户端列表");
-    println!("  2. 广播消息给所有客户端");
-    println!("  3. 处理客户端加入/离开");
+    println!("  1. 每个连接一个读循环，事件通过 channels::channel 扇入到 hub 线程");
+    println!("  2. hub 维护昵称和房间，按房间扇出到各客户端的写线程");
+    println!("  3. 处理客户端加入/离开、私信");
     
-    println!("\n关键代码:");
-    println!("  let clients = Arc::new(Mutex::new(Vec::new()));");
-    println!("  ");
-    println!("  // 新客户端");
-    println!("  let clients_clone = Arc::clone(&clients);");
-    println!("  clients_clone.lock().unwrap().push(stream.try_clone()?);");
-    println!("  ");
-    println!("  // 广播消息");
-    println!("  for client in clients.lock().unwrap().iter_mut() {{");
-    println!("      client.write_all(message.as_bytes())?;");
-    println!("  }}");
+    println!("\n启动:");
+    println!("  TcpServer::bind(\"127.0.0.1:7878\", 32)?.serve(ChatServer::new())?;");
+    
+    println!("\n客户端命令:");
+    println!("  /join <房间>  /leave  /msg <昵称> <内容>  /quit");
+    println!("  客户端: network::tcp_client::run_chat_client(\"127.0.0.1:7878\")");
 }
 
 /// # HTTP 服务器基础
@@ -164,6 +1680,24 @@ pub fn http_server_basics_demo() {
     println!("      body");
     println!("  );");
     println!("  stream.write_all(response.as_bytes())?;");
+    
+    println!("\n解析请求:");
+    let raw = "POST /users HTTP/1.1\r\nHost: localhost\r\nContent-Length: 13\r\n\r\n{\"name\":\"a\"}";
+    match read_request(&mut io::Cursor::new(raw)) {
+        Ok(Some(request)) => {
+            println!("  方法: {}", request.method);
+            println!("  路径: {}", request.path());
+            println!("  请求体: {}", String::from_utf8_lossy(&request.body));
+            println!("  keep-alive: {}", request.keep_alive());
+        }
+        Ok(None) => println!("  连接已关闭"),
+        Err(e) => println!("  解析失败: {}", e),
+    }
+    
+    println!("\n运行 /users 服务:");
+    println!("  let store = Arc::new(Mutex::new(UserStore::default()));");
+    println!("  let server = TcpServer::bind(\"127.0.0.1:8080\", 4)?;");
+    println!("  server.serve(HttpHandler::new(users_router(store)))?;");
 }
 
 /// # 实战示例：简单 Web 服务器
@@ -242,3 +1776,679 @@ pub fn run_all() {
     simple_web_server_demo();
     tcp_server_best_practices_demo();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn spawn_server<H: ConnectionHandler>(
+        workers: usize,
+        handler: H,
+    ) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<io::Result<()>>) {
+        let server = TcpServer::bind("127.0.0.1:0", workers).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let join = thread::spawn(move || server.serve(handler));
+        (addr, handle, join)
+    }
+    
+    fn echo_once(stream: &mut TcpStream, msg: &[u8]) -> Vec<u8> {
+        stream.write_all(msg).unwrap();
+        let mut buf = vec![0; msg.len()];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+    
+    #[test]
+    fn test_echo_server() {
+        let (addr, handle, join) = spawn_server(2, EchoHandler);
+        
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(echo_once(&mut stream, b"hello"), b"hello");
+        assert_eq!(echo_once(&mut stream, b"world"), b"world");
+        drop(stream);
+        
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+    }
+    
+    #[test]
+    fn test_concurrent_clients() {
+        let (addr, handle, join) = spawn_server(4, EchoHandler);
+        
+        let clients: Vec<_> = (0..4)
+            .map(|i| {
+                thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                    let msg = format!("client-{}", i);
+                    echo_once(&mut stream, msg.as_bytes()) == msg.as_bytes()
+                })
+            })
+            .collect();
+        
+        for client in clients {
+            assert!(client.join().unwrap());
+        }
+        
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+    }
+    
+    #[test]
+    fn test_closure_handler_and_shutdown() {
+        let (addr, handle, join) = spawn_server(1, |mut stream: TcpStream| {
+            stream.write_all(b"bye")
+        });
+        
+        let mut reply = String::new();
+        TcpStream::connect(addr).unwrap().read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "bye");
+        
+        handle.shutdown();
+        assert!(handle.is_shutdown());
+        join.join().unwrap().unwrap();
+    }
+    
+    #[test]
+    fn test_full_queue_sheds_connections_and_shutdown_is_prompt() {
+        let server = TcpServer::bind_with_queue("127.0.0.1:0", 1, 1).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let join = thread::spawn(move || server.serve(EchoHandler));
+        
+        // 第一个连接占住唯一的 Worker，第二个排队
+        let mut busy = TcpStream::connect(addr).unwrap();
+        assert_eq!(echo_once(&mut busy, b"busy"), b"busy");
+        let mut queued = TcpStream::connect(addr).unwrap();
+        
+        // 第三个连接被直接关闭，accept 循环没有阻塞
+        let mut shed = TcpStream::connect(addr).unwrap();
+        shed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0; 4];
+        assert!(matches!(shed.read(&mut buf), Ok(0) | Err(_)));
+        
+        // 关闭后排队的连接不再处理，Worker 空出来 serve 就返回
+        handle.shutdown();
+        drop(busy);
+        join.join().unwrap().unwrap();
+        queued
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let _ = queued.write_all(b"late");
+        assert!(matches!(queued.read(&mut buf), Ok(0) | Err(_)));
+    }
+    
+    #[test]
+    fn test_chat_rooms_and_private_messages() {
+        use crate::network::tcp_client::ChatClient;
+        
+        let (addr, handle, join) = spawn_server(4, ChatServer::new());
+        let timeout = Some(Duration::from_secs(5));
+        
+        let mut alice = ChatClient::connect(addr, "alice", timeout).unwrap();
+        let mut bob = ChatClient::connect(addr, "bob", timeout).unwrap();
+        assert_eq!(alice.recv_line().unwrap(), "* bob 加入了房间 lobby");
+        
+        assert!(ChatClient::connect(addr, "bob", timeout).is_err());
+        
+        let mut carol = ChatClient::connect(addr, "carol", timeout).unwrap();
+        assert_eq!(alice.recv_line().unwrap(), "* carol 加入了房间 lobby");
+        assert_eq!(bob.recv_line().unwrap(), "* carol 加入了房间 lobby");
+        
+        // 房间广播不回显给发送者
+        alice.say("hi").unwrap();
+        assert_eq!(bob.recv_line().unwrap(), "[lobby] alice: hi");
+        assert_eq!(carol.recv_line().unwrap(), "[lobby] alice: hi");
+        
+        carol.join_room("rust").unwrap();
+        assert_eq!(carol.recv_line().unwrap(), "* 已加入房间 rust");
+        assert_eq!(alice.recv_line().unwrap(), "* carol 离开了房间 lobby");
+        assert_eq!(bob.recv_line().unwrap(), "* carol 离开了房间 lobby");
+        
+        // carol 已不在 lobby，收不到 bob 的广播；下一条只会是私信
+        bob.say("lobby only").unwrap();
+        assert_eq!(alice.recv_line().unwrap(), "[lobby] bob: lobby only");
+        alice.private_message("carol", "secret").unwrap();
+        assert_eq!(carol.recv_line().unwrap(), "[私信] alice: secret");
+        
+        alice.private_message("dave", "hello?").unwrap();
+        assert_eq!(alice.recv_line().unwrap(), "! 用户不存在: dave");
+        
+        bob.quit().unwrap();
+        assert_eq!(alice.recv_line().unwrap(), "* bob 离开了房间 lobby");
+        
+        // 客户端还连着：关闭服务器会断开它们，serve 不会一直等下去
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+        assert!(alice.recv_line().is_err());
+        assert!(carol.recv_line().is_err());
+    }
+    
+    fn parse(raw: &[u8]) -> Result<Option<Request>, HttpError> {
+        read_request(&mut io::Cursor::new(raw))
+    }
+    
+    #[test]
+    fn test_parse_content_length_and_chunked() {
+        let request =
+            parse(b"POST /users?page=2 HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap()
+                .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path(), "/users");
+        assert_eq!(request.query(), Some("page=2"));
+        assert_eq!(request.header("content-length"), Some("5"));
+        assert_eq!(request.body, b"hello");
+        assert!(request.keep_alive());
+        
+        let chunked = b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: t\r\n\r\n";
+        assert_eq!(parse(chunked).unwrap().unwrap().body, b"hello world");
+        
+        let request = parse(b"GET / HTTP/1.0\r\n\r\n").unwrap().unwrap();
+        assert!(!request.keep_alive());
+        let request = parse(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert!(request.keep_alive());
+        let request = parse(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert!(!request.keep_alive());
+        
+        // 管道化：同一个缓冲区里的多个请求依次解析
+        let mut reader = io::Cursor::new(
+            &b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nPOST /b HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\n\r\nz"[..],
+        );
+        assert_eq!(read_request(&mut reader).unwrap().unwrap().path(), "/a");
+        assert_eq!(read_request(&mut reader).unwrap().unwrap().body, b"z");
+        assert!(read_request(&mut reader).unwrap().is_none());
+    }
+    
+    #[test]
+    fn test_parse_rejects_malformed() {
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nHost: x\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD_BYTES)
+        );
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\nHost: x\r\n{}\r\n",
+            "A: b\r\n".repeat(MAX_HEADERS)
+        );
+        let cases: Vec<(&[u8], Option<u16>)> = vec![
+            (b"GET /\r\n\r\n", Some(400)),
+            (b"GET  / HTTP/1.1\r\nHost: x\r\n\r\n", Some(400)),
+            (b"GET http://x/ HTTP/1.1\r\nHost: x\r\n\r\n", Some(400)),
+            (b"G@T / HTTP/1.1\r\nHost: x\r\n\r\n", Some(400)),
+            (b"GET / HTTP/2.0\r\nHost: x\r\n\r\n", Some(505)),
+            (b"GET / HTTX/1.1\r\nHost: x\r\n\r\n", Some(400)),
+            (b"GET / HTTP/1.1\r\n\r\n", Some(400)),
+            (b"GET / HTTP/1.1\r\nHost: x\r\nHost: y\r\n\r\n", Some(400)),
+            (b"GET / HTTP/1.1\r\nHost: x\r\n folded\r\n\r\n", Some(400)),
+            (b"GET / HTTP/1.1\r\nHost : x\r\n\r\n", Some(400)),
+            (b"GET / HTTP/1.1\r\nHost: x\r\nNoColon\r\n\r\n", Some(400)),
+            (b"GET / HTTP/1.1\r\nHost: x\rY: z\r\n\r\n", Some(400)),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: -1\r\n\r\n", Some(400)),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab", Some(400)),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 99999999999999999999\r\n\r\n", Some(413)),
+            (
+                b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+                Some(400),
+            ),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip\r\n\r\n", Some(501)),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", Some(400)),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab\r\n0\r\n\r\n", Some(400)),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\nfffffffffffffffff\r\n", Some(400)),
+            (b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\nfffffff\r\n", Some(413)),
+            (long_header.as_bytes(), Some(431)),
+            (many_headers.as_bytes(), Some(431)),
+            // 截断的请求不是客户端错误，只是连接断了
+            (b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhel", None),
+            (b"GET / HTTP/1.1\r\nHost: x\r\n", None),
+        ];
+        
+        for (raw, status) in cases {
+            match parse(raw) {
+                Err(e) => assert_eq!(
+                    e.status(),
+                    status,
+                    "{:?}: {}",
+                    String::from_utf8_lossy(raw),
+                    e
+                ),
+                Ok(r) => panic!(
+                    "{:?} 应当解析失败，得到 {:?}",
+                    String::from_utf8_lossy(raw),
+                    r
+                ),
+            }
+        }
+    }
+    
+    /// xorshift64，测试用的确定性随机数
+    struct Rng(u64);
+    
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+        
+        fn token(&mut self, alphabet: &[u8], max_len: usize) -> String {
+            let len = 1 + self.below(max_len);
+            (0..len)
+                .map(|_| alphabet[self.below(alphabet.len())] as char)
+                .collect()
+        }
+    }
+    
+    /// 随机生成合法请求，返回请求和它的线上编码
+    fn random_request(rng: &mut Rng) -> (Request, Vec<u8>) {
+        const TOKEN: &[u8] =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_.~!$";
+        let methods = ["GET", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"];
+        let method = methods[rng.below(methods.len())].to_string();
+        let target = format!(
+            "/{}?{}={}",
+            rng.token(TOKEN, 20),
+            rng.token(TOKEN, 5),
+            rng.token(TOKEN, 5)
+        );
+        let body: Vec<u8> = (0..rng.below(300)).map(|_| rng.next() as u8).collect();
+        
+        let mut headers = vec![("Host".to_string(), "localhost".to_string())];
+        for _ in 0..rng.below(8) {
+            headers.push((format!("X-{}", rng.token(TOKEN, 10)), rng.token(TOKEN, 30)));
+        }
+        let chunked = rng.below(2) == 0;
+        if chunked {
+            headers.push(("Transfer-Encoding".to_string(), "chunked".to_string()));
+        } else {
+            headers.push(("Content-Length".to_string(), body.len().to_string()));
+        }
+        
+        let mut raw = format!("{} {} HTTP/1.1\r\n", method, target).into_bytes();
+        for (name, value) in &headers {
+            raw.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        raw.extend_from_slice(b"\r\n");
+        if chunked {
+            let mut rest = &body[..];
+            while !rest.is_empty() {
+                let n = 1 + rng.below(rest.len());
+                raw.extend_from_slice(format!("{:x}\r\n", n).as_bytes());
+                raw.extend_from_slice(&rest[..n]);
+                raw.extend_from_slice(b"\r\n");
+                rest = &rest[n..];
+            }
+            raw.extend_from_slice(b"0\r\n\r\n");
+        } else {
+            raw.extend_from_slice(&body);
+        }
+        
+        let request = Request {
+            method,
+            target,
+            version: Version::Http11,
+            headers,
+            body,
+        };
+        (request, raw)
+    }
+    
+    #[test]
+    fn test_parser_roundtrip_property() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        
+        for _ in 0..500 {
+            let (expected, raw) = random_request(&mut rng);
+            assert_eq!(parse(&raw).unwrap().unwrap(), expected);
+            
+            // 任何真前缀都不能被解析成完整请求
+            let cut = rng.below(raw.len());
+            match parse(&raw[..cut]) {
+                Ok(None) => assert!(raw[..cut].iter().all(|&b| b == b'\r' || b == b'\n')),
+                Ok(Some(r)) => panic!("截断到 {} 字节仍解析成功: {:?}", cut, r),
+                Err(e) => assert!(matches!(e, HttpError::UnexpectedEof), "{}", e),
+            }
+        }
+    }
+    
+    #[test]
+    fn test_parser_survives_random_mutations() {
+        let mut rng = Rng(0xdeadbeefcafef00d);
+        
+        for _ in 0..3000 {
+            let (_, mut raw) = random_request(&mut rng);
+            for _ in 0..1 + rng.below(4) {
+                let i = rng.below(raw.len());
+                match rng.below(4) {
+                    0 => raw[i] = rng.next() as u8,
+                    1 => raw.insert(i, b"\r\n: \0\x7f;-"[rng.below(8)]),
+                    2 => {
+                        raw.remove(i);
+                    }
+                    _ => raw.truncate(i),
+                }
+                if raw.is_empty() {
+                    break;
+                }
+            }
+            
+            // 不能 panic；成功时结果必须满足上限
+            if let Ok(Some(request)) = parse(&raw) {
+                assert!(request.body.len() <= MAX_BODY_BYTES);
+                assert!(request.headers.len() <= MAX_HEADERS);
+                assert!(request.target.starts_with('/'));
+            }
+        }
+    }
+    
+    #[test]
+    fn test_router_dispatch() {
+        let router = HttpRouter::new()
+            .route("GET", "/users/:id", |_, params| {
+                Response::text(200, params["id"].clone())
+            })
+            .route("DELETE", "/users/:id", |_, _| Response::new(204));
+        let request = |method: &str, target: &str| Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: Version::Http11,
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        
+        assert_eq!(router.handle(&request("GET", "/users/42?x=1")).body, b"42");
+        assert_eq!(router.handle(&request("HEAD", "/users/42")).status, 200);
+        assert_eq!(router.handle(&request("GET", "/users/")).status, 404);
+        assert_eq!(router.handle(&request("GET", "/users/1/posts")).status, 404);
+        let response = router.handle(&request("PUT", "/users/1"));
+        assert_eq!(response.status, 405);
+        assert_eq!(response.header("allow"), Some("GET, DELETE, HEAD"));
+    }
+    
+    fn send(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, raw: &str) -> Response {
+        stream.write_all(raw.as_bytes()).unwrap();
+        let method = raw.split(' ').next().unwrap();
+        read_response(reader, method)
+            .unwrap()
+            .expect("服务器提前关闭了连接")
+    }
+    
+    fn json_request(method: &str, path: &str, body: &str) -> String {
+        format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+    }
+    
+    fn json_body(response: &Response) -> serde_json::Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+    
+    #[test]
+    fn test_http_users_over_keep_alive() {
+        let store = Arc::new(Mutex::new(UserStore::default()));
+        let (addr, handle, join) = spawn_server(2, HttpHandler::new(users_router(store)));
+        
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        
+        // 同一连接上完成全部 CRUD
+        let created = send(
+            &mut stream,
+            &mut reader,
+            &json_request(
+                "POST",
+                "/users",
+                r#"{"name":"alice","email":"alice@example.com"}"#,
+            ),
+        );
+        assert_eq!(created.status, 201);
+        assert_eq!(created.header("connection"), Some("keep-alive"));
+        let user: User = serde_json::from_slice(&created.body).unwrap();
+        assert_eq!(user.name, "alice");
+        
+        let invalid = send(
+            &mut stream,
+            &mut reader,
+            &json_request("POST", "/users", r#"{"name":"a","email":"nope"}"#),
+        );
+        assert_eq!(invalid.status, 422);
+        assert_eq!(json_body(&invalid)["details"].as_array().unwrap().len(), 2);
+        
+        let bad_json = send(
+            &mut stream,
+            &mut reader,
+            &json_request("POST", "/users", "{"),
+        );
+        assert_eq!(bad_json.status, 400);
+        assert_eq!(json_body(&bad_json)["code"], "bad_request");
+        
+        // chunked 请求体
+        let chunked =
+            "PUT /users/1 HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+            8\r\n{\"name\":\r\n9\r\n\"alice2\"}\r\n0\r\n\r\n";
+        let updated = send(&mut stream, &mut reader, chunked);
+        assert_eq!(updated.status, 200);
+        assert_eq!(json_body(&updated)["name"], "alice2");
+        
+        let list = send(
+            &mut stream,
+            &mut reader,
+            "GET /users?limit=10 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(json_body(&list)["total"], 1);
+        assert_eq!(json_body(&list)["data"][0]["name"], "alice2");
+        
+        let head = send(
+            &mut stream,
+            &mut reader,
+            "HEAD /users/1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(head.status, 200);
+        assert!(head.body.is_empty());
+        
+        let deleted = send(
+            &mut stream,
+            &mut reader,
+            "DELETE /users/1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(deleted.status, 204);
+        let missing = send(
+            &mut stream,
+            &mut reader,
+            "GET /users/1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(missing.status, 404);
+        assert_eq!(json_body(&missing)["message"], "用户 1 不存在");
+        assert_eq!(missing.header("connection"), Some("close"));
+        assert!(read_response(&mut reader, "GET").unwrap().is_none());
+        
+        // 格式错误：回复 400 后关闭连接
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let response = send(
+            &mut stream,
+            &mut reader,
+            "GET / HTTP/1.1\r\nHost: x\r\n bad\r\n\r\n",
+        );
+        assert_eq!(response.status, 400);
+        assert!(read_response(&mut reader, "GET").unwrap().is_none());
+        
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+    }
+    
+    #[test]
+    fn test_list_users_matches_axum() {
+        use crate::network::http_server::{complete_api, InMemoryUserRepository};
+        use axum::extract::ConnectInfo;
+        use tower::ServiceExt;
+        
+        let store = Arc::new(Mutex::new(UserStore::default()));
+        let (addr, handle, join) = spawn_server(1, HttpHandler::new(users_router(store)));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut raw = |method: &str, path: &str, body: &str| {
+            let response = send(&mut stream, &mut reader, &json_request(method, path, body));
+            (response.status, json_body(&response))
+        };
+        
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let app = complete_api(Arc::new(InMemoryUserRepository::default()));
+        let axum = |method: &str, path: &str, body: &str| {
+            runtime.block_on(async {
+                let mut request = axum::http::Request::builder()
+                    .method(method)
+                    .uri(path)
+                    .header("Content-Type", "application/json")
+                    .body(axum::body::Body::from(body.to_string()))
+                    .unwrap();
+                request.extensions_mut().insert(ConnectInfo(addr));
+                let response = app.clone().oneshot(request).await.unwrap();
+                let status = response.status().as_u16();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let mut body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                // 请求 ID 每次都不同，原始 socket 版本没有
+                if let Some(id) = body.get_mut("request_id") {
+                    *id = serde_json::Value::Null;
+                }
+                (status, body)
+            })
+        };
+        
+        for (name, domain) in [
+            ("dave", "example.com"),
+            ("alice", "corp.test"),
+            ("carol", "example.com"),
+            ("bob", "Corp.test"),
+            ("erin", "example.com"),
+        ] {
+            let body = format!(r#"{{"name":"{}","email":"{}@{}"}}"#, name, name, domain);
+            assert_eq!(raw("POST", "/users", &body), axum("POST", "/users", &body));
+        }
+        
+        for path in [
+            "/users",
+            "/users?page=2&limit=2&sort=name&email_contains=%40example",
+            "/users?sort=-email,id&limit=3",
+            "/users?email_contains=%43ORP",
+            "/users?page=99",
+            "/users?page=1&unknown=1&sort=-id",
+            "/users?page=0",
+            "/users?limit=abc",
+            "/users?limit=101",
+            "/users?sort=age",
+            "/users?sort=name,-name",
+        ] {
+            assert_eq!(raw("GET", path, ""), axum("GET", path, ""), "{}", path);
+        }
+        
+        let (status, filtered) = raw("GET", "/users?email_contains=%43ORP", "");
+        assert_eq!(status, 200);
+        assert_eq!(filtered["total"], 2);
+        let (status, invalid) = raw("GET", "/users?sort=age", "");
+        assert_eq!(status, 400);
+        assert_eq!(invalid["code"], "invalid_query");
+        assert_eq!(invalid["details"]["field"], "sort");
+        
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+    }
+    
+    #[test]
+    fn test_http_error_codes_match_status() {
+        let cases = [
+            (HttpError::Malformed("x"), 400, "bad_request"),
+            (HttpError::HeadTooLarge, 431, "header_too_large"),
+            (HttpError::BodyTooLarge, 413, "payload_too_large"),
+            (HttpError::UnsupportedVersion, 505, "http_version_not_supported"),
+            (HttpError::UnsupportedEncoding, 501, "not_implemented"),
+        ];
+        for (error, status, code) in cases {
+            assert_eq!(error.status(), Some(status));
+            assert_eq!(error.code(), code);
+        }
+        
+        let store = Arc::new(Mutex::new(UserStore::default()));
+        let (addr, handle, join) = spawn_server(1, HttpHandler::new(users_router(store)));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let response = send(&mut stream, &mut reader, "GET / HTTP/2.0\r\n\r\n");
+        assert_eq!(response.status, 505);
+        assert_eq!(json_body(&response)["code"], "http_version_not_supported");
+        
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+    }
+    
+    #[test]
+    fn test_shutdown_closes_idle_keep_alive() {
+        let store = Arc::new(Mutex::new(UserStore::default()));
+        let (addr, handle, join) = spawn_server(1, HttpHandler::new(users_router(store)));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let response = send(
+            &mut stream,
+            &mut reader,
+            "GET /users HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(response.header("connection"), Some("keep-alive"));
+        
+        // 处理器正等着下一个请求；关闭不必等到 keep-alive 超时
+        let started = Instant::now();
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+        assert!(started.elapsed() < KEEP_ALIVE_TIMEOUT / 2);
+        assert!(read_response(&mut reader, "GET").unwrap().is_none());
+    }
+    
+    #[test]
+    fn test_http_connection_request_cap() {
+        let store = Arc::new(Mutex::new(UserStore::default()));
+        let (addr, handle, join) = spawn_server(1, HttpHandler::new(users_router(store)));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        
+        let get = "GET /users HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        for _ in 1..MAX_REQUESTS_PER_CONNECTION {
+            let response = send(&mut stream, &mut reader, get);
+            assert_eq!(response.header("connection"), Some("keep-alive"));
+        }
+        // 最后一个请求之后服务器关闭连接，工作线程可以去处理别的连接
+        let last = send(&mut stream, &mut reader, get);
+        assert_eq!(last.status, 200);
+        assert_eq!(last.header("connection"), Some("close"));
+        assert!(read_response(&mut reader, "GET").unwrap().is_none());
+        
+        handle.shutdown();
+        join.join().unwrap().unwrap();
+    }
+}
@@ -7,15 +7,24 @@ use axum::{
     body::Body,
     extract::{Json, Path, Query, State},
     http::{HeaderMap, HeaderValue, StatusCode},
-    response::{Html, IntoResponse, Response},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
     routing::{get, post},
     Router,
 };
+use futures_util::stream::{self, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
 use tower_http::cors::CorsLayer;
 
+use crate::network::http_server::{request_id, AppError};
+
 #[tokio::main]
 async fn main() {
     println!("=== Axum Web 框架详解 ===\n");
@@ -30,6 +39,7 @@ async fn main() {
     println!("  2. 提取器示例   - 见下方代码");
     println!("  3. 状态管理     - 见下方代码");
     println!("  4. 完整 API     - 见下方代码");
+    println!("  5. SSE 进度推送 - 见 sse_example");
 
     // 启动基础示例服务器
     run_basic_server().await;
@@ -312,6 +322,173 @@ async fn result_response() -> Result<Json<ApiResponse>, StatusCode> {
     }))
 }
 
+// Server-Sent Events 响应
+//
+// 每个事件带递增 id，断线重连时浏览器会带上 Last-Event-ID，
+// 服务器从重放缓冲区补发之后的事件
+
+// 重放缓冲区保留的事件数
+const SSE_REPLAY_CAPACITY: usize = 64;
+
+// 保活注释的间隔，防止代理断开空闲连接
+const SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, PartialEq)]
+struct SseMessage {
+    id: u64,
+    event: &'static str,
+    data: String,
+}
+
+impl SseMessage {
+    fn to_event(&self) -> Event {
+        Event::default()
+            .id(self.id.to_string())
+            .event(self.event)
+            .data(&self.data)
+    }
+}
+
+struct ReplayBuffer {
+    capacity: usize,
+    next_id: u64,
+    events: VecDeque<SseMessage>,
+}
+
+// 事件源：有界重放缓冲 + 实时广播
+#[derive(Clone)]
+struct EventFeed {
+    buffer: Arc<Mutex<ReplayBuffer>>,
+    live: broadcast::Sender<SseMessage>,
+}
+
+impl EventFeed {
+    fn new(capacity: usize) -> Self {
+        let (live, _) = broadcast::channel(capacity);
+        EventFeed {
+            buffer: Arc::new(Mutex::new(ReplayBuffer {
+                capacity,
+                next_id: 1,
+                events: VecDeque::with_capacity(capacity),
+            })),
+            live,
+        }
+    }
+
+    // 发布事件，返回分配的 id
+    fn publish(&self, event: &'static str, data: impl Into<String>) -> u64 {
+        let mut buffer = self.buffer.lock().unwrap();
+        let message = SseMessage {
+            id: buffer.next_id,
+            event,
+            data: data.into(),
+        };
+        buffer.next_id += 1;
+        if buffer.events.len() == buffer.capacity {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back(message.clone());
+        // 持锁广播，subscribe 拿到的重放和实时事件既不重叠也不遗漏
+        let _ = self.live.send(message);
+        buffer.next_id - 1
+    }
+
+    // 需要重放的事件和后续实时事件的接收端
+    //
+    // 没有 Last-Event-ID 时重放整个缓冲区；请求的 id 之后有事件已被淘汰时，
+    // 先发一个 reset 事件（data 为丢失的事件数），再从缓冲区中最旧的事件开始
+    fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<SseMessage>, broadcast::Receiver<SseMessage>) {
+        let buffer = self.buffer.lock().unwrap();
+        let after = last_event_id.unwrap_or(0);
+        let mut replay = Vec::new();
+
+        if let (Some(after), Some(oldest)) = (last_event_id, buffer.events.front()) {
+            if after + 1 < oldest.id {
+                // id 取最旧事件的前一个，客户端的 Last-Event-ID 随之前移
+                replay.push(SseMessage {
+                    id: oldest.id - 1,
+                    event: "reset",
+                    data: serde_json::json!({ "missed": oldest.id - 1 - after }).to_string(),
+                });
+            }
+        }
+
+        replay.extend(
+            buffer
+                .events
+                .iter()
+                .filter(|message| message.id > after)
+                .cloned(),
+        );
+        (replay, self.live.subscribe())
+    }
+
+    fn stream(&self, last_event_id: Option<u64>) -> impl Stream<Item = Result<Event, Infallible>> {
+        let (replay, rx) = self.subscribe(last_event_id);
+        let live = stream::unfold(rx, |mut rx| async move {
+            match rx.recv().await {
+                Ok(message) => Some((message, rx)),
+                // 落后太多时结束流，客户端带 Last-Event-ID 重连后从缓冲区补齐
+                Err(_) => None,
+            }
+        });
+
+        stream::iter(replay)
+            .chain(live)
+            .map(|message| Ok(message.to_event()))
+    }
+}
+
+fn last_event_id(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn sse_routes(feed: EventFeed, keep_alive: Duration) -> Router {
+    Router::new().route(
+        "/events",
+        get(move |headers: HeaderMap| async move {
+            Sse::new(feed.stream(last_event_id(&headers)))
+                .keep_alive(KeepAlive::new().interval(keep_alive).text("keep-alive"))
+        }),
+    )
+}
+
+// SSE 示例：推送异步任务队列的进度
+//
+// GET /tasks/events
+//   id: 1
+//   event: progress
+//   data: {"id":0,"name":"Task 0","completed":1,"total":5}
+//   ...
+//   event: done
+fn sse_example() -> Router {
+    let feed = EventFeed::new(SSE_REPLAY_CAPACITY);
+
+    let publisher = feed.clone();
+    tokio::spawn(async move {
+        let progress = publisher.clone();
+        crate::concurrency::async_await::run_task_queue(5, move |p| {
+            let data = serde_json::json!({
+                "id": p.id,
+                "name": p.name,
+                "completed": p.completed,
+                "total": p.total,
+            });
+            progress.publish("progress", data.to_string());
+        })
+        .await;
+        publisher.publish("done", "all tasks completed");
+    });
+
+    Router::new().nest("/tasks", sse_routes(feed, SSE_KEEP_ALIVE))
+}
+
 // ============================================
 // 5. 状态管理
 // ============================================
@@ -365,30 +542,14 @@ async fn set_data(State(state): State<AppState>, Path(key): Path<u32>, body: Str
 // 6. 错误处理
 // ============================================
 
-// 自定义错误类型
-enum AppError {
-    NotFound,
-    BadRequest(String),
-    InternalError,
-}
-
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, message) = match self {
-            AppError::NotFound => (StatusCode::NOT_FOUND, "Not Found"),
-            AppError::BadRequest(msg) => {
-                return (StatusCode::BAD_REQUEST, msg).into_response();
-            }
-            AppError::InternalError => (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
-        };
-
-        (status, message).into_response()
-    }
-}
+// 错误类型和请求 ID 中间件与 network/http_server.rs 共用一份定义，
+// 所有错误统一返回 JSON:
+//   {"code": "not_found", "message": "...", "details": null, "request_id": "..."}
+// DatabaseError / ConfigError 通过 From 转换，handler 里可以直接用 ?
 
 async fn error_handler() -> Result<String, AppError> {
     // 可以返回自定义错误
-    Err(AppError::NotFound)
+    Err(AppError::NotFound("resource not found".to_string()))
 }
 
 // ============================================
@@ -400,6 +561,8 @@ fn middleware_example() -> Router {
 
     Router::new()
         .route("/", get(root_handler))
+        // 请求 ID（错误响应体中的 request_id）
+        .layer(axum::middleware::from_fn(request_id))
         // 添加 CORS
         .layer(CorsLayer::permissive())
         // 添加日志追踪
@@ -508,6 +671,8 @@ fn complete_api() -> Router {
         .route("/health", get(health_check))
         // 添加状态
         .with_state(state)
+        // 请求 ID（错误响应体中的 request_id）
+        .layer(axum::middleware::from_fn(request_id))
         // 添加 CORS
         .layer(CorsLayer::permissive())
 }
@@ -628,6 +793,12 @@ async fn delete_user_api(State(state): State<ApiState>, Path(id): Path<u32>) ->
    - Result<T, E>
    - Response - 自定义响应
 
+   流式:
+   - Sse<S> - Server-Sent Events
+   - KeepAlive - 定期发送保活注释
+   - 事件 id + Last-Event-ID 断线续传
+   - 续传点已被淘汰时先发 reset 事件
+
 3. 状态管理:
 
    模式:
@@ -641,6 +812,7 @@ async fn delete_user_api(State(state): State<ApiState>, Path(id): Path<u32>) ->
    DO:
    ✓ 使用类型安全的提取器
    ✓ 实现 IntoResponse for 自定义错误
+   ✓ 错误统一为 {code, message, details, request_id}
    ✓ 使用 Router 组织路由
    ✓ Arc + Mutex 管理共享状态
 
@@ -671,3 +843,104 @@ async fn delete_user_api(State(state): State<ApiState>, Path(id): Path<u32>) ->
   curl http://localhost:3000/users/123
   curl http://localhost:3000/search?q=rust
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn serve(app: Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    // 读取响应体直到出现 needle，返回已读到的全部文本
+    async fn read_until(response: &mut reqwest::Response, needle: &str) -> String {
+        let mut text = String::new();
+        while !text.contains(needle) {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), response.chunk())
+                .await
+                .expect("等待事件超时")
+                .unwrap()
+                .expect("流提前结束");
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        text
+    }
+
+    #[test]
+    fn test_replay_buffer_resume() {
+        let feed = EventFeed::new(3);
+        for i in 1..=5 {
+            assert_eq!(feed.publish("tick", i.to_string()), i);
+        }
+
+        let ids = |last: Option<u64>| -> Vec<u64> {
+            feed.subscribe(last).0.iter().map(|m| m.id).collect()
+        };
+        assert_eq!(ids(None), vec![3, 4, 5]);
+        assert_eq!(ids(Some(3)), vec![4, 5]);
+        assert_eq!(ids(Some(5)), Vec::<u64>::new());
+        assert_eq!(ids(Some(2)), vec![3, 4, 5]);
+
+        // 已被淘汰的 id：先告诉客户端丢了多少，再从最旧的事件开始
+        let (replay, _) = feed.subscribe(Some(0));
+        assert_eq!(replay[0].event, "reset");
+        assert_eq!(replay[0].id, 2);
+        assert_eq!(replay[0].data, r#"{"missed":2}"#);
+        assert_eq!(ids(Some(1)), vec![2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_sse_resume_and_keep_alive() {
+        let feed = EventFeed::new(SSE_REPLAY_CAPACITY);
+        for i in 1..=3 {
+            feed.publish("tick", format!("n={}", i));
+        }
+        let addr = serve(sse_routes(feed.clone(), Duration::from_millis(50))).await;
+
+        let mut response = reqwest::Client::new()
+            .get(format!("http://{}/events", addr))
+            .header("Last-Event-ID", "1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        let replayed = read_until(&mut response, "data: n=3").await;
+        assert!(!replayed.contains("id: 1\n"));
+        assert!(replayed.contains("id: 2\nevent: tick\ndata: n=2"));
+
+        feed.publish("tick", "n=4");
+        let live = read_until(&mut response, "data: n=4").await;
+        assert!(live.contains("id: 4\n"));
+
+        let idle = read_until(&mut response, ": keep-alive").await;
+        assert!(idle.contains(": keep-alive"));
+    }
+
+    #[tokio::test]
+    async fn test_sse_example_streams_task_progress() {
+        let addr = serve(sse_example()).await;
+
+        let mut response = reqwest::Client::new()
+            .get(format!("http://{}/tasks/events", addr))
+            .send()
+            .await
+            .unwrap();
+        let text = read_until(&mut response, "event: done").await;
+
+        let progress: Vec<serde_json::Value> = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str(data).ok())
+            .collect();
+        assert_eq!(progress.len(), 5);
+        for (i, p) in progress.iter().enumerate() {
+            assert_eq!(p["completed"], i + 1);
+            assert_eq!(p["total"], 5);
+        }
+        assert!(text.contains("id: 6\nevent: done"));
+    }
+}
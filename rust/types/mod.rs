@@ -5,5 +5,8 @@ pub mod collections;
 pub mod structs;
 pub mod enums;
 pub mod datetime;
+#[path = "regex.rs"]
 pub mod regex_examples;
 pub mod errors;
+#[path = "thiserror.rs"]
+pub mod thiserror_examples;
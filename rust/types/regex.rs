@@ -278,38 +278,62 @@ pub fn data_extraction_demo() {
     }
 }
 
-/// # 实战示例：输入验证
-pub fn validation_demo() {
-    println!("\n=== 实战示例：输入验证 ===");
+/// 用户名规则：3-20 位字母、数字或下划线
+///
+/// `network::http_server` 的 OpenAPI 文档也引用这个常量，两处规则不会不一致
+pub const USERNAME_PATTERN: &str = r"^[a-zA-Z0-9_]{3,20}$";
+
+/// 输入验证器
+///
+/// 正则只需编译一次，可在多处复用（如 `network::http_server` 的请求校验）。
+/// 注意：regex crate 不支持前瞻断言 `(?=...)`，密码规则拆成多个简单正则组合判断。
+pub struct Validator {
+    username_re: Regex,
+    password_len_re: Regex,
+    password_classes: [Regex; 3],
+    email_re: Regex,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator {
+            username_re: Regex::new(USERNAME_PATTERN).unwrap(),
+            password_len_re: Regex::new(r"^.{8,}$").unwrap(),
+            password_classes: [
+                Regex::new(r"[a-z]").unwrap(),
+                Regex::new(r"[A-Z]").unwrap(),
+                Regex::new(r"\d").unwrap(),
+            ],
+            email_re: Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").unwrap(),
+        }
+    }
     
-    struct Validator {
-        username_re: Regex,
-        password_re: Regex,
-        email_re: Regex,
+    /// 3-20 位字母、数字或下划线
+    pub fn validate_username(&self, username: &str) -> bool {
+        self.username_re.is_match(username)
     }
     
-    impl Validator {
-        fn new() -> Self {
-            Validator {
-                username_re: Regex::new(r"^[a-zA-Z0-9_]{3,20}$").unwrap(),
-                password_re: Regex::new(r"^(?=.*[a-z])(?=.*[A-Z])(?=.*\d).{8,}$").unwrap(),
-                email_re: Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").unwrap(),
-            }
-        }
-        
-        fn validate_username(&self, username: &str) -> bool {
-            self.username_re.is_match(username)
-        }
-        
-        fn validate_password(&self, password: &str) -> bool {
-            self.password_re.is_match(password)
-        }
-        
-        fn validate_email(&self, email: &str) -> bool {
-            self.email_re.is_match(email)
-        }
+    /// 至少 8 位，且同时包含小写字母、大写字母和数字
+    pub fn validate_password(&self, password: &str) -> bool {
+        self.password_len_re.is_match(password)
+            && self.password_classes.iter().all(|re| re.is_match(password))
     }
     
+    pub fn validate_email(&self, email: &str) -> bool {
+        self.email_re.is_match(email)
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator::new()
+    }
+}
+
+/// # 实战示例：输入验证
+pub fn validation_demo() {
+    println!("\n=== 实战示例：输入验证 ===");
+    
     let validator = Validator::new();
     
     let test_data = vec![
@@ -370,4 +394,16 @@ mod tests {
         let result = re.replace("I have 2 apples", "X");
         assert_eq!(result, "I have X apples");
     }
+    
+    #[test]
+    fn test_validator() {
+        let v = Validator::new();
+        assert!(v.validate_username("user_123"));
+        assert!(!v.validate_username("ab"));
+        assert!(v.validate_email("user@example.com"));
+        assert!(!v.validate_email("invalid"));
+        assert!(v.validate_password("Password1"));
+        assert!(!v.validate_password("password1"));
+        assert!(!v.validate_password("Pass1"));
+    }
 }
@@ -7,7 +7,10 @@ use std::io;
 use std::num::ParseIntError;
 use thiserror::Error;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// 运行所有 thiserror 示例
+///
+/// 本文件作为 `types::thiserror_examples` 模块引入，和其他模块一样从 `run_all` 进入
+pub fn run_all() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== Thiserror 错误处理详解 ===\n");
     
     // 1. Thiserror 基础
@@ -518,29 +521,31 @@ fn demo_real_world_examples() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 // 案例 1: 配置库错误
+//
+// 配置库和数据库库的错误类型定义在模块级，供其他模块（如 network::http_server）转换使用
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("config file not found at: {path}")]
+    NotFound { path: String },
+    
+    #[error("failed to read config file: {path}")]
+    ReadError {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    
+    #[error("invalid config format")]
+    ParseError(#[from] serde_json::Error),
+    
+    #[error("missing required field: {0}")]
+    MissingField(String),
+    
+    #[error("invalid value for {field}: {value}")]
+    InvalidValue { field: String, value: String },
+}
+
 fn config_error_example() -> Result<(), Box<dyn std::error::Error>> {
-    #[derive(Error, Debug)]
-    pub enum ConfigError {
-        #[error("config file not found at: {path}")]
-        NotFound { path: String },
-        
-        #[error("failed to read config file: {path}")]
-        ReadError {
-            path: String,
-            #[source]
-            source: io::Error,
-        },
-        
-        #[error("invalid config format")]
-        ParseError(#[from] serde_json::Error),
-        
-        #[error("missing required field: {0}")]
-        MissingField(String),
-        
-        #[error("invalid value for {field}: {value}")]
-        InvalidValue { field: String, value: String },
-    }
-    
     fn load_config(path: &str) -> Result<(), ConfigError> {
         if path.is_empty() {
             return Err(ConfigError::NotFound {
@@ -633,54 +638,54 @@ fn http_error_example() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 // 案例 3: 数据库库错误
-fn database_error_example() -> Result<(), Box<dyn std::error::Error>> {
-    #[derive(Error, Debug)]
-    pub enum DatabaseError {
-        #[error("connection pool exhausted")]
-        PoolExhausted,
-        
-        #[error("connection failed: {0}")]
-        ConnectionFailed(String),
-        
-        #[error("query failed: {query}")]
-        QueryFailed {
-            query: String,
-            #[source]
-            source: Box<dyn std::error::Error + Send + Sync>,
-        },
-        
-        #[error("transaction failed")]
-        TransactionFailed,
-        
-        #[error("record not found: {table} id={id}")]
-        NotFound { table: String, id: i64 },
-        
-        #[error("duplicate key: {key}")]
-        DuplicateKey { key: String },
-    }
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("connection pool exhausted")]
+    PoolExhausted,
     
-    impl DatabaseError {
-        // 错误恢复建议
-        pub fn recovery_suggestion(&self) -> &str {
-            match self {
-                DatabaseError::PoolExhausted => "Increase pool size or wait",
-                DatabaseError::ConnectionFailed(_) => "Check network and credentials",
-                DatabaseError::QueryFailed { .. } => "Review query syntax",
-                DatabaseError::TransactionFailed => "Retry transaction",
-                DatabaseError::NotFound { .. } => "Check if record exists",
-                DatabaseError::DuplicateKey { .. } => "Use different key",
-            }
-        }
-        
-        // 是否可重试
-        pub fn is_retryable(&self) -> bool {
-            matches!(
-                self,
-                DatabaseError::PoolExhausted | DatabaseError::TransactionFailed
-            )
+    #[error("connection failed: {0}")]
+    ConnectionFailed(String),
+    
+    #[error("query failed: {query}")]
+    QueryFailed {
+        query: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    
+    #[error("transaction failed")]
+    TransactionFailed,
+    
+    #[error("record not found: {table} id={id}")]
+    NotFound { table: String, id: i64 },
+    
+    #[error("duplicate key: {key}")]
+    DuplicateKey { key: String },
+}
+
+impl DatabaseError {
+    // 错误恢复建议
+    pub fn recovery_suggestion(&self) -> &str {
+        match self {
+            DatabaseError::PoolExhausted => "Increase pool size or wait",
+            DatabaseError::ConnectionFailed(_) => "Check network and credentials",
+            DatabaseError::QueryFailed { .. } => "Review query syntax",
+            DatabaseError::TransactionFailed => "Retry transaction",
+            DatabaseError::NotFound { .. } => "Check if record exists",
+            DatabaseError::DuplicateKey { .. } => "Use different key",
         }
     }
     
+    // 是否可重试
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            DatabaseError::PoolExhausted | DatabaseError::TransactionFailed
+        )
+    }
+}
+
+fn database_error_example() -> Result<(), Box<dyn std::error::Error>> {
     // 使用示例
     let err = DatabaseError::NotFound {
         table: "users".to_string(),